@@ -0,0 +1,570 @@
+use crate::disassembly::PROGRAM_START;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+// Includes nested deeper than this are assumed to include themselves.
+const MAX_INCLUDE_DEPTH: usize = 16;
+// Same for constants defined in terms of other constants.
+const MAX_CONSTANT_DEPTH: usize = 32;
+// Names that mean an operand, so they cannot be labels or constants.
+const RESERVED: [&str; 10] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "PITCH", "LONG"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    // The file the error is in, None for source passed as text.
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}:{}: {}", path.display(), self.line, self.message),
+            None => write!(f, "Line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+impl From<AssemblyError> for io::Error {
+    fn from(err: AssemblyError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Location {
+    path: Option<PathBuf>,
+    line: usize,
+}
+
+impl Location {
+    fn error(&self, message: String) -> AssemblyError {
+        AssemblyError {
+            path: self.path.clone(),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SourceLine {
+    location: Location,
+    text: String,
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Instruction(String, Vec<String>),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    location: Location,
+    item: Item,
+}
+
+#[derive(Debug, Clone)]
+enum Symbol {
+    Label(usize),
+    Constant(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    V(u16),
+    // Vx-Vy for the XO-CHIP register range loads
+    Range(u16, u16),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Pitch,
+    Long(i64),
+    Value(i64),
+}
+
+// Assembles the syntax `decrypt_chip8_instruction` prints, e.g.
+// `LD V3, 0x10` or `DRW V1, V2, 0x5`, into a ROM loaded at 0x200:
+//
+//     start:                  ; a label
+//     SPEED EQU 2             ; a constant, `SPEED = 2` works too
+//         LD I, sprite
+//         DRW V0, V1, 0x2
+//         JP start
+//     sprite:
+//         DB 0x3C, 0x42       ; bytes, DW for big-endian words
+//     INCLUDE "font.asm"      ; relative to the including file
+//
+// Mnemonics are case-insensitive and symbols are not. Operands accept decimal,
+// 0x and 0b numbers and symbols joined by + and -. `;` starts a comment.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblyError> {
+    assemble_with(source, |path| fs::read_to_string(path))
+}
+
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AssemblyError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|err| AssemblyError {
+        path: Some(path.to_path_buf()),
+        line: 0,
+        message: err.to_string(),
+    })?;
+
+    let mut lines = Vec::new();
+    read_lines(
+        &source,
+        Some(path),
+        0,
+        &mut |path: &Path| fs::read_to_string(path),
+        &mut lines,
+    )?;
+    Assembler::default().assemble(&lines)
+}
+
+// Like `assemble`, but includes are read with `read_include`.
+pub fn assemble_with<F>(source: &str, mut read_include: F) -> Result<Vec<u8>, AssemblyError>
+where
+    F: FnMut(&Path) -> io::Result<String>,
+{
+    let mut lines = Vec::new();
+    read_lines(source, None, 0, &mut read_include, &mut lines)?;
+    Assembler::default().assemble(&lines)
+}
+
+// Splits the source into lines, replacing includes with the included lines.
+fn read_lines<F>(
+    source: &str,
+    path: Option<&Path>,
+    depth: usize,
+    read_include: &mut F,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), AssemblyError>
+where
+    F: FnMut(&Path) -> io::Result<String>,
+{
+    for (number, text) in source.lines().enumerate() {
+        let location = Location {
+            path: path.map(Path::to_path_buf),
+            line: number + 1,
+        };
+        let code = strip_comment(text).trim();
+        let (word, rest) = split_word(code);
+        if !word.eq_ignore_ascii_case("INCLUDE") {
+            lines.push(SourceLine {
+                location,
+                text: code.to_string(),
+            });
+            continue;
+        }
+
+        let name = rest
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .ok_or_else(|| location.error(format!("Invalid include: {rest}")))?;
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(location.error(format!("Includes nested too deeply: {name}")));
+        }
+        let include = match path.and_then(Path::parent) {
+            Some(directory) => directory.join(name),
+            None => PathBuf::from(name),
+        };
+        let text = read_include(&include)
+            .map_err(|err| location.error(format!("Cannot include {name}: {err}")))?;
+        read_lines(&text, Some(&include), depth + 1, read_include, lines)?;
+    }
+
+    Ok(())
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..index],
+            _ => {}
+        }
+    }
+    text
+}
+
+// The first word and the trimmed rest of the line.
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+// Splits on commas outside string literals.
+fn split_operands(text: &str) -> Vec<String> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+
+    let mut operands = vec![String::new()];
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                operands.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        operands.last_mut().unwrap().push(c);
+    }
+
+    operands
+        .iter()
+        .map(|operand| operand.trim().to_string())
+        .collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn is_reserved(name: &str) -> bool {
+    register(name).is_some() || RESERVED.iter().any(|word| word.eq_ignore_ascii_case(name))
+}
+
+fn register(text: &str) -> Option<u16> {
+    match text.as_bytes() {
+        [b'V' | b'v', digit] => (*digit as char).to_digit(16).map(|x| x as u16),
+        _ => None,
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(digits) = lower.strip_prefix("0x") {
+        i64::from_str_radix(digits, 16).ok()
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        i64::from_str_radix(digits, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+// The bytes of a `"text"` data operand.
+fn string_bytes(text: &str) -> Option<&[u8]> {
+    text.strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .map(str::as_bytes)
+}
+
+fn data_size(item: &Item) -> usize {
+    match item {
+        Item::Instruction(mnemonic, operands) => {
+            let long = mnemonic.eq_ignore_ascii_case("LD")
+                && operands
+                    .get(1)
+                    .is_some_and(|operand| split_word(operand).0.eq_ignore_ascii_case("LONG"));
+            if long { 4 } else { 2 }
+        }
+        Item::Bytes(operands) => operands
+            .iter()
+            .map(|operand| string_bytes(operand).map_or(1, <[u8]>::len))
+            .sum(),
+        Item::Words(operands) => operands.len() * 2,
+    }
+}
+
+#[derive(Debug, Default)]
+struct Assembler {
+    symbols: HashMap<String, Symbol>,
+    statements: Vec<Statement>,
+}
+
+impl Assembler {
+    fn assemble(mut self, lines: &[SourceLine]) -> Result<Vec<u8>, AssemblyError> {
+        // First pass: addresses only depend on sizes, never on values
+        let mut address = PROGRAM_START;
+        for line in lines {
+            address += self.parse_line(line, address)?;
+        }
+
+        let mut rom = Vec::new();
+        for statement in &self.statements {
+            let bytes = self
+                .encode(&statement.item)
+                .map_err(|message| statement.location.error(message))?;
+            rom.extend_from_slice(&bytes);
+        }
+        Ok(rom)
+    }
+
+    // Records the labels, constants and statement on a line. Returns its size.
+    fn parse_line(&mut self, line: &SourceLine, address: usize) -> Result<usize, AssemblyError> {
+        let location = &line.location;
+        let mut text = line.text.as_str();
+
+        if let Some((name, rest)) = text.split_once(':') {
+            let name = name.trim();
+            if is_identifier(name) {
+                self.define(name, Symbol::Label(address), location)?;
+                text = rest.trim();
+            }
+        }
+        if text.is_empty() {
+            return Ok(0);
+        }
+
+        let (word, rest) = split_word(text);
+        let (word, rest) = match word.split_once('=') {
+            Some((name, value)) => (name, format!("= {value} {rest}")),
+            None => (word, rest.to_string()),
+        };
+        let (equals, value) = split_word(&rest);
+        if equals == "=" || equals.eq_ignore_ascii_case("EQU") {
+            self.define(word, Symbol::Constant(value.to_string()), location)?;
+            return Ok(0);
+        }
+
+        let operands = split_operands(&rest);
+        let item = match word.to_ascii_uppercase().as_str() {
+            "DB" => Item::Bytes(operands),
+            "DW" => Item::Words(operands),
+            _ => Item::Instruction(word.to_string(), operands),
+        };
+        let size = data_size(&item);
+        self.statements.push(Statement {
+            location: location.clone(),
+            item,
+        });
+        Ok(size)
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        symbol: Symbol,
+        location: &Location,
+    ) -> Result<(), AssemblyError> {
+        if !is_identifier(name) || is_reserved(name) {
+            return Err(location.error(format!("Invalid symbol name: {name}")));
+        }
+        if self.symbols.insert(name.to_string(), symbol).is_some() {
+            return Err(location.error(format!("Symbol defined twice: {name}")));
+        }
+        Ok(())
+    }
+
+    // Numbers and symbols joined by + and -.
+    fn evaluate(&self, expression: &str, depth: usize) -> Result<i64, String> {
+        if depth > MAX_CONSTANT_DEPTH {
+            return Err("Constant defined in terms of itself".to_string());
+        }
+
+        let mut total = 0;
+        let mut rest = expression.trim();
+        let mut sign = match rest.strip_prefix('-') {
+            Some(negated) => {
+                rest = negated;
+                -1
+            }
+            None => 1,
+        };
+
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let name = rest[..end].trim();
+            if name.is_empty() {
+                return Err(format!("Missing value: {expression}"));
+            }
+            total += sign * self.value(name, depth)?;
+            if end == rest.len() {
+                return Ok(total);
+            }
+            sign = if rest[end..].starts_with('-') { -1 } else { 1 };
+            rest = &rest[end + 1..];
+        }
+    }
+
+    fn value(&self, name: &str, depth: usize) -> Result<i64, String> {
+        if let Some(number) = parse_number(name) {
+            return Ok(number);
+        }
+        match self.symbols.get(name) {
+            Some(Symbol::Label(address)) => Ok(*address as i64),
+            Some(Symbol::Constant(expression)) => self.evaluate(expression, depth + 1),
+            None => Err(format!("Unknown symbol: {name}")),
+        }
+    }
+
+    fn operand(&self, text: &str) -> Result<Operand, String> {
+        let operand = match text.to_ascii_uppercase().as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::DelayTimer,
+            "ST" => Operand::SoundTimer,
+            "K" => Operand::Key,
+            "F" => Operand::Font,
+            "HF" => Operand::BigFont,
+            "B" => Operand::Bcd,
+            "R" => Operand::Flags,
+            "PITCH" => Operand::Pitch,
+            _ => {
+                let (word, rest) = split_word(text);
+                if word.eq_ignore_ascii_case("LONG") {
+                    Operand::Long(self.evaluate(rest, 0)?)
+                } else if let Some(x) = register(text) {
+                    Operand::V(x)
+                } else if let Some((x, y)) = text
+                    .split_once('-')
+                    .and_then(|(x, y)| Some((register(x.trim())?, register(y.trim())?)))
+                {
+                    Operand::Range(x, y)
+                } else {
+                    Operand::Value(self.evaluate(text, 0)?)
+                }
+            }
+        };
+        Ok(operand)
+    }
+
+    fn encode(&self, item: &Item) -> Result<Vec<u8>, String> {
+        match item {
+            Item::Bytes(operands) => {
+                let mut bytes = Vec::new();
+                for operand in operands {
+                    match string_bytes(operand) {
+                        Some(text) => bytes.extend_from_slice(text),
+                        None => bytes.push(byte(self.evaluate(operand, 0)?)? as u8),
+                    }
+                }
+                Ok(bytes)
+            }
+            Item::Words(operands) => {
+                let mut bytes = Vec::new();
+                for operand in operands {
+                    bytes.extend_from_slice(&word(self.evaluate(operand, 0)?)?.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            Item::Instruction(mnemonic, operands) => {
+                let operands = operands
+                    .iter()
+                    .map(|operand| self.operand(operand))
+                    .collect::<Result<Vec<_>, _>>()?;
+                encode_instruction(mnemonic, &operands)
+            }
+        }
+    }
+}
+
+fn in_range(value: i64, min: i64, max: i64) -> Result<u16, String> {
+    if (min..=max).contains(&value) {
+        Ok((value & 0xFFFF) as u16)
+    } else {
+        Err(format!("Value out of range: {value}"))
+    }
+}
+
+fn nibble(value: i64) -> Result<u16, String> {
+    in_range(value, 0, 0xF)
+}
+
+// Negative bytes are two's complement, so `ADD V0, -1` works.
+fn byte(value: i64) -> Result<u16, String> {
+    in_range(value, -0x80, 0xFF).map(|value| value & 0xFF)
+}
+
+fn address(value: i64) -> Result<u16, String> {
+    in_range(value, 0, 0xFFF)
+}
+
+fn word(value: i64) -> Result<u16, String> {
+    in_range(value, 0, 0xFFFF)
+}
+
+fn encode_instruction(mnemonic: &str, operands: &[Operand]) -> Result<Vec<u8>, String> {
+    use Operand::*;
+
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let opcode = match (mnemonic.as_str(), operands) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("AUDIO", []) => 0xF002,
+        ("SCD", [Value(n)]) => 0x00C0 | nibble(*n)?,
+        ("SCU", [Value(n)]) => 0x00D0 | nibble(*n)?,
+        ("SYS", [Value(nnn)]) => address(*nnn)?,
+        ("JP", [Value(nnn)]) => 0x1000 | address(*nnn)?,
+        ("JP", [V(0), Value(nnn)]) => 0xB000 | address(*nnn)?,
+        ("CALL", [Value(nnn)]) => 0x2000 | address(*nnn)?,
+        ("SE", [V(x), Value(kk)]) => 0x3000 | x << 8 | byte(*kk)?,
+        ("SNE", [V(x), Value(kk)]) => 0x4000 | x << 8 | byte(*kk)?,
+        ("SE", [V(x), V(y)]) => 0x5000 | x << 8 | y << 4,
+        ("LD", [IndirectI, Range(x, y)]) => 0x5002 | x << 8 | y << 4,
+        ("LD", [Range(x, y), IndirectI]) => 0x5003 | x << 8 | y << 4,
+        ("LD", [V(x), Value(kk)]) => 0x6000 | x << 8 | byte(*kk)?,
+        ("ADD", [V(x), Value(kk)]) => 0x7000 | x << 8 | byte(*kk)?,
+        ("LD", [V(x), V(y)]) => 0x8000 | x << 8 | y << 4,
+        ("OR", [V(x), V(y)]) => 0x8001 | x << 8 | y << 4,
+        ("AND", [V(x), V(y)]) => 0x8002 | x << 8 | y << 4,
+        ("XOR", [V(x), V(y)]) => 0x8003 | x << 8 | y << 4,
+        ("ADD", [V(x), V(y)]) => 0x8004 | x << 8 | y << 4,
+        ("SUB", [V(x), V(y)]) => 0x8005 | x << 8 | y << 4,
+        ("SHR", [V(x)]) => 0x8006 | x << 8,
+        ("SHR", [V(x), V(y)]) => 0x8006 | x << 8 | y << 4,
+        ("SUBN", [V(x), V(y)]) => 0x8007 | x << 8 | y << 4,
+        ("SHL", [V(x)]) => 0x800E | x << 8,
+        ("SHL", [V(x), V(y)]) => 0x800E | x << 8 | y << 4,
+        ("SNE", [V(x), V(y)]) => 0x9000 | x << 8 | y << 4,
+        ("LD", [I, Value(nnn)]) => 0xA000 | address(*nnn)?,
+        ("LD", [I, Long(nnnn)]) => {
+            let mut bytes = vec![0xF0, 0x00];
+            bytes.extend_from_slice(&word(*nnnn)?.to_be_bytes());
+            return Ok(bytes);
+        }
+        ("RND", [V(x), Value(kk)]) => 0xC000 | x << 8 | byte(*kk)?,
+        ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | x << 8 | y << 4 | nibble(*n)?,
+        ("SKP", [V(x)]) => 0xE09E | x << 8,
+        ("SKNP", [V(x)]) => 0xE0A1 | x << 8,
+        ("PLANE", [Value(n)]) => 0xF001 | nibble(*n)? << 8,
+        ("LD", [V(x), DelayTimer]) => 0xF007 | x << 8,
+        ("LD", [V(x), Key]) => 0xF00A | x << 8,
+        ("LD", [DelayTimer, V(x)]) => 0xF015 | x << 8,
+        ("LD", [SoundTimer, V(x)]) => 0xF018 | x << 8,
+        ("ADD", [I, V(x)]) => 0xF01E | x << 8,
+        ("LD", [Font, V(x)]) => 0xF029 | x << 8,
+        ("LD", [BigFont, V(x)]) => 0xF030 | x << 8,
+        ("LD", [Bcd, V(x)]) => 0xF033 | x << 8,
+        ("LD", [Pitch, V(x)]) => 0xF03A | x << 8,
+        ("LD", [IndirectI, V(x)]) => 0xF055 | x << 8,
+        ("LD", [V(x), IndirectI]) => 0xF065 | x << 8,
+        ("LD", [Flags, V(x)]) => 0xF075 | x << 8,
+        ("LD", [V(x), Flags]) => 0xF085 | x << 8,
+        (
+            "CLS" | "RET" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "AUDIO" | "SCD" | "SCU"
+            | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND" | "XOR" | "SUB"
+            | "SHR" | "SUBN" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP" | "PLANE",
+            _,
+        ) => return Err(format!("Invalid operands for {mnemonic}")),
+        _ => return Err(format!("Unknown instruction: {mnemonic}")),
+    };
+
+    Ok(opcode.to_be_bytes().to_vec())
+}
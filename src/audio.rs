@@ -0,0 +1,322 @@
+use crate::chip8_machine::TIMER_FREQUENCY;
+use crate::chip8_state::{AUDIO_PATTERN_SIZE, DEFAULT_PITCH};
+use std::{
+    f32::consts::TAU,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+pub const DEFAULT_TONE_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+const WAV_HEADER_SIZE: u32 = 44;
+const PATTERN_BITS: usize = AUDIO_PATTERN_SIZE * 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" | "saw" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+
+    // Amplitude in -1.0..=1.0 at `phase` in 0.0..1.0.
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (phase * TAU).sin(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioConfig {
+    pub sample_rate: u32,
+    pub frequency: f32,
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            frequency: DEFAULT_TONE_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+// Destination for mono samples in -1.0..=1.0 at the beeper's sample rate.
+pub trait AudioSink {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()>;
+}
+
+// Discards samples, for running without a sound card.
+#[derive(Debug, Default)]
+pub struct NullSink {
+    pub samples_written: usize,
+}
+
+impl AudioSink for NullSink {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.samples_written += samples.len();
+        Ok(())
+    }
+}
+
+// Records samples as a 16-bit mono PCM WAV stream. The header sizes are
+// patched in `finish`, which also runs on drop.
+pub struct WavSink<W: Write + Seek> {
+    writer: Option<W>,
+    sample_rate: u32,
+    data_size: u32,
+}
+
+impl WavSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        WavSink::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        write_wav_header(&mut writer, sample_rate, 0)?;
+
+        Ok(Self {
+            writer: Some(writer),
+            sample_rate,
+            data_size: 0,
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.finalize()?;
+        Ok(self.writer.take().expect("WAV writer already finished"))
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.seek(SeekFrom::Start(0))?;
+            write_wav_header(writer, self.sample_rate, self.data_size)?;
+            writer.seek(SeekFrom::End(0))?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let writer = self.writer.as_mut().expect("WAV writer already finished");
+
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        self.data_size += samples.len() as u32 * 2;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for WavSink<W> {
+    fn drop(&mut self) {
+        let _ = self.finalize();
+    }
+}
+
+fn write_wav_header<W: Write>(writer: &mut W, sample_rate: u32, data_size: u32) -> io::Result<()> {
+    let channels: u16 = 1;
+    let bits_per_sample: u16 = 16;
+    let block_align = channels * bits_per_sample / 8;
+    let byte_rate = sample_rate * block_align as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bits_per_sample.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    Ok(())
+}
+
+// XO-CHIP pattern playback rate in bits per second for an Fx3A pitch value.
+pub fn pattern_playback_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+}
+
+// Turns the sound timer into a tone: every 60 Hz timer tick produces
+// `samples_per_tick` samples, audible while the sound timer is non-zero.
+// Once an XO-CHIP pattern is set it replaces the configured waveform.
+pub struct Beeper {
+    pub config: AudioConfig,
+    sink: Box<dyn AudioSink>,
+    phase: f32,
+    buffer: Vec<f32>,
+    pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+}
+
+impl Beeper {
+    pub fn new(config: AudioConfig, sink: Box<dyn AudioSink>) -> Self {
+        Self {
+            config,
+            sink,
+            phase: 0.0,
+            buffer: Vec::new(),
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+        }
+    }
+
+    pub fn set_pattern(&mut self, pattern: Option<[u8; AUDIO_PATTERN_SIZE]>, pitch: u8) {
+        self.pattern = pattern;
+        self.pitch = pitch;
+    }
+
+    pub fn samples_per_tick(&self) -> usize {
+        (self.config.sample_rate / TIMER_FREQUENCY) as usize
+    }
+
+    // `active` is whether the sound timer was non-zero at the start of the ticks.
+    pub fn update(&mut self, active: bool, ticks: u32) -> io::Result<()> {
+        let count = self.samples_per_tick() * ticks as usize;
+        // For patterns a phase of 0.0..1.0 covers all 128 bits
+        let step = match self.pattern {
+            Some(_) => pattern_playback_rate(self.pitch) / PATTERN_BITS as f32,
+            None => self.config.frequency,
+        } / self.config.sample_rate as f32;
+
+        self.buffer.clear();
+        for _ in 0..count {
+            if active {
+                let amplitude = match &self.pattern {
+                    Some(pattern) => {
+                        let bit = (self.phase * PATTERN_BITS as f32) as usize % PATTERN_BITS;
+                        if pattern[bit / 8] >> (7 - bit % 8) & 1 == 1 {
+                            1.0
+                        } else {
+                            -1.0
+                        }
+                    }
+                    None => self.config.waveform.sample(self.phase),
+                };
+                self.buffer.push(amplitude * self.config.volume);
+                self.phase = (self.phase + step).fract();
+            } else {
+                self.buffer.push(0.0);
+                self.phase = 0.0;
+            }
+        }
+
+        self.sink.write_samples(&self.buffer)
+    }
+}
+
+#[cfg(feature = "cpal")]
+pub use device::CpalSink;
+
+#[cfg(feature = "cpal")]
+mod device {
+    use super::AudioSink;
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use std::{
+        collections::VecDeque,
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    // Plays samples on the default output device. Samples are queued for the
+    // stream callback, which pads with silence when the emulator is paused.
+    pub struct CpalSink {
+        _stream: cpal::Stream,
+        queue: Arc<Mutex<VecDeque<f32>>>,
+        sample_rate: u32,
+    }
+
+    impl CpalSink {
+        pub fn open() -> Result<Self, String> {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or("No audio output device available")?;
+            let supported = device
+                .default_output_config()
+                .map_err(|err| err.to_string())?;
+            let sample_rate = supported.sample_rate().0;
+            let channels = supported.channels() as usize;
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+            let source = Arc::clone(&queue);
+
+            let stream = device
+                .build_output_stream(
+                    &supported.into(),
+                    move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                        let mut queue = source.lock().unwrap();
+                        for frame in data.chunks_mut(channels) {
+                            frame.fill(queue.pop_front().unwrap_or(0.0));
+                        }
+                    },
+                    |err| eprintln!("Audio stream error: {err}"),
+                    None,
+                )
+                .map_err(|err| err.to_string())?;
+            stream.play().map_err(|err| err.to_string())?;
+
+            Ok(Self {
+                _stream: stream,
+                queue,
+                sample_rate,
+            })
+        }
+
+        pub fn sample_rate(&self) -> u32 {
+            self.sample_rate
+        }
+    }
+
+    impl AudioSink for CpalSink {
+        fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+            let mut queue = self.queue.lock().unwrap();
+            queue.extend(samples);
+
+            // Keep latency bounded when fast-forwarding: at most 1/4 s queued.
+            let limit = (self.sample_rate / 4) as usize;
+            if queue.len() > limit {
+                let excess = queue.len() - limit;
+                queue.drain(..excess);
+            }
+
+            Ok(())
+        }
+    }
+}
@@ -1,17 +1,65 @@
-use crate::chip8_state::Chip8State;
-use rand::prelude::*;
-use rand_chacha::ChaCha8Rng;
+use crate::chip8_state::{AUDIO_PATTERN_SIZE, Chip8State, STACK_SIZE};
+use crate::error::Chip8Error;
+use crate::framebuffer::{ALL_PLANES, FrameBuffer, PLANE_COUNT};
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::random::RandomSource;
+use crate::trace::{TraceRecord, Tracer};
+use crate::watchpoint::{Access, MemoryAccess, Register, WatchHit, Watchpoint, check_watchpoints};
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
 pub const KEYBOARD_SIZE: usize = 16;
+pub const TIMER_FREQUENCY: u32 = 60;
+// About 700 instructions per second at 60 Hz, see `Scheduler::instructions_per_frame`.
+pub const DEFAULT_CYCLES_PER_TIMER_TICK: u32 = 12;
 
 pub struct Chip8MachineState {
     pub cycles: u64,
-    pub display: [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT],
+    pub display: FrameBuffer,
     pub keyboard: [bool; KEYBOARD_SIZE],
     pub state: Chip8State,
-    pub random: ChaCha8Rng,
+    pub random: RandomSource,
+    pub quirks: Quirks,
+    pub platform: Platform,
+    // XO-CHIP bitplanes affected by drawing, clearing and scrolling (Fn01).
+    pub planes: u8,
+    pub cycles_per_timer_tick: u32,
+    // Set by the SUPER-CHIP 00FD exit instruction.
+    pub halted: bool,
+    pub watchpoints: Vec<Watchpoint>,
+    // Filled while watchpoints are set, for the caller to drain.
+    pub watch_hits: Vec<WatchHit>,
+    pub tracer: Option<Tracer>,
+    pub(crate) timer_cycles: u32,
+    memory_accesses: Vec<MemoryAccess>,
+}
+
+// Failure of a single instruction, turned into a `Chip8Error` by `execute_cycle`
+// once the faulting address and opcode are attached.
+#[derive(Debug)]
+enum Fault {
+    UnknownOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds(usize),
+}
+
+impl Fault {
+    fn at(self, address: u16, opcode: u16) -> Chip8Error {
+        match self {
+            Fault::UnknownOpcode => Chip8Error::UnknownOpcode { address, opcode },
+            Fault::StackOverflow => Chip8Error::StackOverflow { address, opcode },
+            Fault::StackUnderflow => Chip8Error::StackUnderflow { address, opcode },
+            Fault::MemoryOutOfBounds(memory_address) => Chip8Error::MemoryOutOfBounds {
+                address,
+                opcode,
+                memory_address,
+            },
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -29,36 +77,131 @@ struct DecodedInstruction {
 
 impl Chip8MachineState {
     pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Self::with_platform(Platform::Chip8, quirks)
+    }
+
+    pub fn for_platform(platform: Platform) -> Self {
+        Self::with_platform(platform, platform.default_quirks())
+    }
+
+    pub fn with_platform(platform: Platform, quirks: Quirks) -> Self {
+        Self::with_random_source(platform, quirks, RandomSource::from_os())
+    }
+
+    pub fn with_random_source(platform: Platform, quirks: Quirks, random: RandomSource) -> Self {
         Self {
             cycles: 0,
-            display: [[false; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            display: FrameBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT),
             keyboard: [false; KEYBOARD_SIZE],
-            state: Chip8State::new(),
-            random: ChaCha8Rng::from_os_rng(),
+            state: Chip8State::with_memory_size(platform.memory_size()),
+            random,
+            quirks,
+            platform,
+            planes: 1,
+            cycles_per_timer_tick: DEFAULT_CYCLES_PER_TIMER_TICK,
+            halted: false,
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            tracer: None,
+            timer_cycles: 0,
+            memory_accesses: Vec::new(),
         }
     }
 
-    pub fn execute_cycle(&mut self) {
-        let (address, instruction) = self.fetch_instruction();
+    // Runs the CPU until the next 60 Hz timer tick, i.e. one emulated frame.
+    pub fn run_timer_tick(&mut self) -> Result<(), Chip8Error> {
+        loop {
+            self.execute_cycle()?;
+
+            if self.timer_cycles == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.state.delay_timer = self.state.delay_timer.saturating_sub(1);
+        self.state.sound_timer = self.state.sound_timer.saturating_sub(1);
+    }
+
+    // On error the PC is left pointing at the faulting instruction.
+    pub fn execute_cycle(&mut self) -> Result<(), Chip8Error> {
+        if self.halted {
+            // Time keeps passing so run_timer_tick still returns
+            self.advance_timer_clock();
+            return Ok(());
+        }
+
+        let (address, instruction) = match self.fetch_instruction() {
+            Ok(fetched) => fetched,
+            Err(err) => return Err(self.trace_fault(err)),
+        };
+        let opcode = u16::from_be_bytes(instruction);
 
         let decoded = self.decode_instruction(instruction);
 
-        self.state.pc += 2;
+        self.state.pc = self.state.pc.wrapping_add(2);
+
+        let registers_before = (!self.watchpoints.is_empty())
+            .then(|| Register::ALL.map(|register| register.value(&self.state)));
+
+        if let Err(fault) = self.execute_instruction(&decoded) {
+            self.memory_accesses.clear();
+            self.state.pc = address;
+            return Err(self.trace_fault(fault.at(address, opcode)));
+        }
 
-        let result = self.execute_instruction(&decoded);
+        if let Some(registers_before) = registers_before {
+            let accesses = std::mem::take(&mut self.memory_accesses);
+            let hits = check_watchpoints(
+                &self.watchpoints,
+                &accesses,
+                &registers_before,
+                &self.state,
+                address,
+                opcode,
+            );
+            self.watch_hits.extend(hits);
+        }
 
-        if !result {
-            panic!("Unknown or invalid instruction at {address:#X}: {instruction:X?}")
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(TraceRecord::new(self.cycles, address, opcode, &self.state));
         }
 
         self.cycles += 1;
+        self.advance_timer_clock();
+        Ok(())
+    }
+
+    fn trace_fault(&mut self, err: Chip8Error) -> Chip8Error {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.fault(&err);
+        }
+
+        err
+    }
+
+    // Timers follow emulated time: every `cycles_per_timer_tick` cycles is 1/60 s.
+    fn advance_timer_clock(&mut self) {
+        self.timer_cycles += 1;
+
+        if self.timer_cycles >= self.cycles_per_timer_tick.max(1) {
+            self.timer_cycles = 0;
+            self.tick_timers();
+        }
     }
 
-    fn fetch_instruction(&self) -> (usize, [u8; 2]) {
+    fn fetch_instruction(&self) -> Result<(u16, [u8; 2]), Chip8Error> {
         let address: usize = self.state.pc as usize;
 
         if address >= self.state.memory.len() {
-            panic!("Program counter out of bounds: {address:#X}");
+            return Err(Chip8Error::PcOutOfBounds {
+                address: self.state.pc,
+            });
         }
 
         let instruction: [u8; 2] = if address + 1 < self.state.memory.len() {
@@ -67,7 +210,7 @@ impl Chip8MachineState {
             [self.state.memory[address], self.state.memory[0]]
         };
 
-        (address, instruction)
+        Ok((self.state.pc, instruction))
     }
 
     fn decode_instruction(&self, instruction: [u8; 2]) -> DecodedInstruction {
@@ -94,8 +237,9 @@ impl Chip8MachineState {
         }
     }
 
-    fn execute_instruction(&mut self, decoded: &DecodedInstruction) -> bool {
-        let mut result = true;
+    fn execute_instruction(&mut self, decoded: &DecodedInstruction) -> Result<(), Fault> {
+        let schip = self.platform.has_super_chip_instructions();
+        let xo_chip = self.platform.has_xo_chip_instructions();
 
         match (
             decoded.position3,
@@ -103,14 +247,27 @@ impl Chip8MachineState {
             decoded.position1,
             decoded.position0,
         ) {
+            (0x0, 0x0, 0xC, _) if schip => self.execute_scd_nibble(decoded.nibble),
+            (0x0, 0x0, 0xD, _) if xo_chip => self.execute_scu_nibble(decoded.nibble),
             (0x0, 0x0, 0xE, 0x0) => self.execute_cls(),
-            (0x0, 0x0, 0xE, 0xE) => self.execute_ret(),
+            (0x0, 0x0, 0xE, 0xE) => self.execute_ret()?,
+            (0x0, 0x0, 0xF, 0xB) if schip => self.execute_scr(),
+            (0x0, 0x0, 0xF, 0xC) if schip => self.execute_scl(),
+            (0x0, 0x0, 0xF, 0xD) if schip => self.execute_exit(),
+            (0x0, 0x0, 0xF, 0xE) if schip => self.execute_low(),
+            (0x0, 0x0, 0xF, 0xF) if schip => self.execute_high(),
+            // What is left of 00Cn, 00Dn and 00FB-00FF is not SYS either
+            (0x0, 0x0, 0xC..=0xD, _) | (0x0, 0x0, 0xF, 0xB..=0xF) => {
+                return Err(Fault::UnknownOpcode);
+            }
             (0x0, _, _, _) => self.execute_sys_addr(decoded.address),
             (0x1, _, _, _) => self.execute_jp_addr(decoded.address),
-            (0x2, _, _, _) => self.execute_call_addr(decoded.address),
+            (0x2, _, _, _) => self.execute_call_addr(decoded.address)?,
             (0x3, _, _, _) => self.execute_se_vx_byte(decoded.x, decoded.byte),
             (0x4, _, _, _) => self.execute_sne_vx_byte(decoded.x, decoded.byte),
             (0x5, _, _, 0x0) => self.execute_se_vx_vy(decoded.x, decoded.y),
+            (0x5, _, _, 0x2) if xo_chip => self.execute_ld_ref_i_vx_vy(decoded.x, decoded.y)?,
+            (0x5, _, _, 0x3) if xo_chip => self.execute_ld_vx_vy_ref_i(decoded.x, decoded.y)?,
             (0x6, _, _, _) => self.execute_ld_vx_byte(decoded.x, decoded.byte),
             (0x7, _, _, _) => self.execute_add_vx_byte(decoded.x, decoded.byte),
             (0x8, _, _, 0x0) => self.execute_ld_vx_vy(decoded.x, decoded.y),
@@ -119,39 +276,106 @@ impl Chip8MachineState {
             (0x8, _, _, 0x3) => self.execute_xor_vx_vy(decoded.x, decoded.y),
             (0x8, _, _, 0x4) => self.execute_add_vx_vy(decoded.x, decoded.y),
             (0x8, _, _, 0x5) => self.execute_sub_vx_vy(decoded.x, decoded.y),
-            (0x8, _, _, 0x6) => self.execute_shr_vx(decoded.x),
+            (0x8, _, _, 0x6) => self.execute_shr_vx(decoded.x, decoded.y),
             (0x8, _, _, 0x7) => self.execute_subn_vx_vy(decoded.x, decoded.y),
-            (0x8, _, _, 0xE) => self.execute_shl_vx(decoded.x),
+            (0x8, _, _, 0xE) => self.execute_shl_vx(decoded.x, decoded.y),
             (0x9, _, _, 0x0) => self.execute_sne_vx_vy(decoded.x, decoded.y),
             (0xA, _, _, _) => self.execute_ld_i_addr(decoded.address),
-            (0xD, _, _, _) => self.execute_draw_vx_vy_nibble(decoded.x, decoded.y, decoded.nibble),
-            (0xB, _, _, _) => self.execute_jp_v0_addr(decoded.address),
+            (0xD, _, _, _) => {
+                self.execute_draw_vx_vy_nibble(decoded.x, decoded.y, decoded.nibble)?
+            }
+            (0xB, _, _, _) => self.execute_jp_v0_addr(decoded.x, decoded.address),
             (0xC, _, _, _) => self.execute_rnd_vx_byte(decoded.x, decoded.byte),
             (0xE, _, 0x9, 0xE) => self.execute_skp_vx(decoded.x),
             (0xE, _, 0xA, 0x1) => self.execute_sknp_vx(decoded.x),
+            (0xF, 0x0, 0x0, 0x0) if xo_chip => self.execute_ld_i_long()?,
+            (0xF, _, 0x0, 0x1) if xo_chip => self.execute_plane_n(decoded.x),
+            (0xF, 0x0, 0x0, 0x2) if xo_chip => self.execute_audio()?,
             (0xF, _, 0x0, 0x7) => self.execute_ld_vx_dt(decoded.x),
             (0xF, _, 0x0, 0xA) => self.execute_ld_vx_k(decoded.x),
             (0xF, _, 0x1, 0x5) => self.execute_ld_dt_vx(decoded.x),
             (0xF, _, 0x1, 0x8) => self.execute_ld_st_vx(decoded.x),
             (0xF, _, 0x1, 0xE) => self.execute_add_i_vx(decoded.x),
-            (0xF, _, 0x2, 0x9) => self.execute_ld_f_vx(decoded.x),
-            (0xF, _, 0x3, 0x3) => self.execute_ld_b_vx(decoded.x),
-            (0xF, _, 0x5, 0x5) => self.execute_ld_ref_i_vx(decoded.x),
-            (0xF, _, 0x6, 0x5) => self.execute_ld_vx_ref_i(decoded.x),
-            (..) => result = false,
+            (0xF, _, 0x2, 0x9) => self.execute_ld_f_vx(decoded.x)?,
+            (0xF, _, 0x3, 0x0) if schip => self.execute_ld_hf_vx(decoded.x)?,
+            (0xF, _, 0x3, 0x3) => self.execute_ld_b_vx(decoded.x)?,
+            (0xF, _, 0x3, 0xA) if xo_chip => self.execute_ld_pitch_vx(decoded.x),
+            (0xF, _, 0x5, 0x5) => self.execute_ld_ref_i_vx(decoded.x)?,
+            (0xF, _, 0x6, 0x5) => self.execute_ld_vx_ref_i(decoded.x)?,
+            (0xF, _, 0x7, 0x5) if schip => self.execute_ld_r_vx(decoded.x),
+            (0xF, _, 0x8, 0x5) if schip => self.execute_ld_vx_r(decoded.x),
+            (..) => return Err(Fault::UnknownOpcode),
+        }
+
+        Ok(())
+    }
+
+    // Checks that `len` bytes starting at I are inside memory. Every data
+    // access goes through here, which is where watchpoints see it.
+    fn check_memory_range(&mut self, len: usize, access: Access) -> Result<usize, Fault> {
+        let start = self.state.i as usize;
+        let end = start + len;
+
+        if end > self.state.memory.len() {
+            return Err(Fault::MemoryOutOfBounds(end - 1));
+        }
+
+        if !self.watchpoints.is_empty() {
+            self.memory_accesses.push(MemoryAccess {
+                start,
+                access,
+                old: self.state.memory[start..end].to_vec(),
+            });
         }
 
-        result
+        Ok(start)
     }
 
     fn execute_cls(&mut self) {
-        self.display.iter_mut().for_each(|row| row.fill(false));
+        self.display.clear_planes(self.planes);
+    }
+
+    fn execute_scd_nibble(&mut self, nibble: usize) {
+        self.display.scroll_down(nibble, self.planes);
+    }
+
+    fn execute_scu_nibble(&mut self, nibble: usize) {
+        self.display.scroll_up(nibble, self.planes);
+    }
+
+    fn execute_scr(&mut self) {
+        self.display.scroll_right(4, self.planes);
+    }
+
+    fn execute_scl(&mut self) {
+        self.display.scroll_left(4, self.planes);
+    }
+
+    fn execute_exit(&mut self) {
+        self.halted = true;
+    }
+
+    fn execute_low(&mut self) {
+        self.display.resize(SCREEN_WIDTH, SCREEN_HEIGHT);
+    }
+
+    fn execute_high(&mut self) {
+        self.display.resize(HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT);
     }
 
-    fn execute_ret(&mut self) {
+    pub fn is_hires(&self) -> bool {
+        self.display.width() == HIRES_SCREEN_WIDTH
+    }
+
+    fn execute_ret(&mut self) -> Result<(), Fault> {
+        if self.state.sp == 0 {
+            return Err(Fault::StackUnderflow);
+        }
+
         self.state.sp -= 1;
         let index = self.state.sp as usize;
         self.state.pc = self.state.stack[index];
+        Ok(())
     }
 
     // To check differences with CALL NNN - 0x2NNN.
@@ -164,31 +388,76 @@ impl Chip8MachineState {
         self.state.pc = address;
     }
 
-    fn execute_call_addr(&mut self, address: u16) {
+    fn execute_call_addr(&mut self, address: u16) -> Result<(), Fault> {
         let index = self.state.sp as usize;
+
+        if index >= STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
+
         self.state.stack[index] = self.state.pc;
         self.state.sp += 1;
         self.state.pc = address;
+        Ok(())
     }
 
     fn execute_se_vx_byte(&mut self, x: usize, byte: u8) {
         if self.state.v[x] == byte {
-            self.state.pc += 2;
+            self.skip_next_instruction();
         }
     }
 
     fn execute_sne_vx_byte(&mut self, x: usize, byte: u8) {
         if self.state.v[x] != byte {
-            self.state.pc += 2;
+            self.skip_next_instruction();
         }
     }
 
     fn execute_se_vx_vy(&mut self, x: usize, y: usize) {
         if self.state.v[x] == self.state.v[y] {
-            self.state.pc += 2;
+            self.skip_next_instruction();
         }
     }
 
+    // Skips over the next instruction, which is 4 bytes long for F000 nnnn.
+    fn skip_next_instruction(&mut self) {
+        let pc = self.state.pc as usize;
+        let long = self.state.memory.get(pc..pc + 2) == Some(&[0xF0, 0x00]);
+
+        self.state.pc = self.state.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    // Registers from Vx to Vy, in either direction.
+    fn register_range(x: usize, y: usize) -> Vec<usize> {
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    fn execute_ld_ref_i_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Fault> {
+        let registers = Self::register_range(x, y);
+        let index = self.check_memory_range(registers.len(), Access::Write)?;
+
+        for (offset, register) in registers.into_iter().enumerate() {
+            self.state.memory[index + offset] = self.state.v[register];
+        }
+
+        Ok(())
+    }
+
+    fn execute_ld_vx_vy_ref_i(&mut self, x: usize, y: usize) -> Result<(), Fault> {
+        let registers = Self::register_range(x, y);
+        let index = self.check_memory_range(registers.len(), Access::Read)?;
+
+        for (offset, register) in registers.into_iter().enumerate() {
+            self.state.v[register] = self.state.memory[index + offset];
+        }
+
+        Ok(())
+    }
+
     fn execute_ld_vx_byte(&mut self, x: usize, byte: u8) {
         self.state.v[x] = byte;
     }
@@ -203,14 +472,32 @@ impl Chip8MachineState {
 
     fn execute_or_vx_vy(&mut self, x: usize, y: usize) {
         self.state.v[x] |= self.state.v[y];
+        self.reset_vf_after_logic();
     }
 
     fn execute_and_vx_vy(&mut self, x: usize, y: usize) {
         self.state.v[x] &= self.state.v[y];
+        self.reset_vf_after_logic();
     }
 
     fn execute_xor_vx_vy(&mut self, x: usize, y: usize) {
         self.state.v[x] ^= self.state.v[y];
+        self.reset_vf_after_logic();
+    }
+
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.state.v[0xF] = 0;
+        }
+    }
+
+    // Register shifted by 8xy6/8xyE, depending on the shift quirk.
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.state.v[y]
+        } else {
+            self.state.v[x]
+        }
     }
 
     fn execute_add_vx_vy(&mut self, x: usize, y: usize) {
@@ -225,9 +512,10 @@ impl Chip8MachineState {
         self.state.v[0xF] = (!overflow) as u8; // NOT borrow!
     }
 
-    fn execute_shr_vx(&mut self, x: usize) {
-        self.state.v[0xF] = self.state.v[x] & 0x1;
-        self.state.v[x] >>= 1;
+    fn execute_shr_vx(&mut self, x: usize, y: usize) {
+        let source = self.shift_source(x, y);
+        self.state.v[x] = source >> 1;
+        self.state.v[0xF] = source & 0x1;
     }
 
     fn execute_subn_vx_vy(&mut self, x: usize, y: usize) {
@@ -236,96 +524,141 @@ impl Chip8MachineState {
         self.state.v[0xF] = (!overflow) as u8; // NOT borrow!
     }
 
-    fn execute_shl_vx(&mut self, x: usize) {
-        self.state.v[0xF] = (self.state.v[x] & 0x80) >> 7;
-        self.state.v[x] <<= 1;
+    fn execute_shl_vx(&mut self, x: usize, y: usize) {
+        let source = self.shift_source(x, y);
+        self.state.v[x] = source << 1;
+        self.state.v[0xF] = (source & 0x80) >> 7;
     }
 
     fn execute_sne_vx_vy(&mut self, x: usize, y: usize) {
-        self.state.pc += if self.state.v[x] != self.state.v[y] {
-            2
-        } else {
-            0
-        };
+        if self.state.v[x] != self.state.v[y] {
+            self.skip_next_instruction();
+        }
     }
 
     fn execute_ld_i_addr(&mut self, address: u16) {
         self.state.i = address;
     }
 
-    fn execute_jp_v0_addr(&mut self, address: u16) {
-        self.state.pc = address + self.state.v[0] as u16;
-    }
+    fn execute_jp_v0_addr(&mut self, x: usize, address: u16) {
+        let offset = if self.quirks.jump_uses_vx {
+            self.state.v[x]
+        } else {
+            self.state.v[0]
+        };
 
-    fn execute_rnd_vx_byte(&mut self, x: usize, byte: u8) {
-        self.state.v[x] = self.random.random::<u8>() & byte;
+        self.state.pc = address + offset as u16;
     }
 
-    fn execute_draw_vx_vy_nibble(&mut self, x: usize, y: usize, nibble: usize) {
+    fn execute_rnd_vx_byte(&mut self, x: usize, byte: u8) {
+        self.state.v[x] = self.random.next_byte() & byte;
+    }
+
+    fn execute_draw_vx_vy_nibble(
+        &mut self,
+        x: usize,
+        y: usize,
+        nibble: usize,
+    ) -> Result<(), Fault> {
+        // Dxy0 draws a 16x16 SUPER-CHIP sprite made of 2-byte rows, and no
+        // rows at all on CHIP-8
+        let (sprite_width, rows) = if nibble == 0 && self.platform.has_super_chip_instructions() {
+            (16, 16)
+        } else {
+            (8, nibble)
+        };
+        let bytes_per_row = sprite_width / 8;
+        let sprite_size = rows * bytes_per_row;
+
+        // XO-CHIP stores one sprite per selected plane, one after another
+        let selected_planes: Vec<u8> = (0..PLANE_COUNT)
+            .map(|plane| 1 << plane)
+            .filter(|plane| self.planes & plane != 0)
+            .collect();
+        let memory_start_position =
+            self.check_memory_range(sprite_size * selected_planes.len(), Access::Read)?;
+
+        // The start position always wraps, only the sprite itself may be clipped
+        let width = self.display.width();
+        let height = self.display.height();
+        let origin_x = self.state.v[x] as usize % width;
+        let origin_y = self.state.v[y] as usize % height;
+        let clip = self.quirks.clip_sprites;
         let mut collision = false;
-        let memory_start_position = self.state.i as usize;
-        let sprite = &self.state.memory[memory_start_position..memory_start_position + nibble];
-        let copied_sprite = sprite.to_vec();
-        let rows = copied_sprite.len();
 
-        for j in 0..rows {
-            let row = copied_sprite[j];
+        for (index, &plane) in selected_planes.iter().enumerate() {
+            let start = memory_start_position + index * sprite_size;
+            let copied_sprite: Vec<u16> = self.state.memory[start..start + sprite_size]
+                .chunks(bytes_per_row)
+                .map(|row| row.iter().fold(0, |bits, &byte| (bits << 8) | byte as u16))
+                .collect();
 
-            for i in 0..8 {
-                let new_value = row >> (7 - i) & 0x01;
+            for (j, &row) in copied_sprite.iter().enumerate() {
+                if clip && origin_y + j >= height {
+                    break;
+                }
 
-                if new_value == 1 {
-                    let xi = (self.state.v[x] as usize + i) % SCREEN_WIDTH;
-                    let yj = (self.state.v[y] as usize + j) % SCREEN_HEIGHT;
-                    let old_value = self.get_pixel(xi, yj);
+                for i in 0..sprite_width {
+                    let new_value = row >> (sprite_width - 1 - i) & 0x01;
 
-                    if old_value {
-                        collision = true;
-                    }
+                    if new_value == 1 {
+                        if clip && origin_x + i >= width {
+                            break;
+                        }
+
+                        let xi = (origin_x + i) % width;
+                        let yj = (origin_y + j) % height;
+                        let old_planes = self.display.get_planes(xi, yj);
+
+                        if old_planes & plane != 0 {
+                            collision = true;
+                        }
 
-                    self.set_pixel(xi, yj, (new_value == 1) ^ old_value);
+                        self.display.set_planes(xi, yj, old_planes ^ plane);
+                    }
                 }
             }
         }
 
         self.state.v[0xF] = collision as u8;
+
+        if self.quirks.display_wait {
+            // Skip the rest of this frame, the next cycle ticks the timers
+            self.timer_cycles = self.cycles_per_timer_tick.max(1) - 1;
+        }
+
+        Ok(())
     }
 
     pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
-        self.display[y][x] = on;
+        self.display.set(x, y, on);
     }
 
     pub fn get_pixel(&self, x: usize, y: usize) -> bool {
-        self.display[y][x]
+        self.display.get(x, y)
     }
 
     fn execute_skp_vx(&mut self, x: usize) {
-        self.state.pc += if self.keyboard[self.state.v[x] as usize] {
-            2
-        } else {
-            0
-        };
+        if self.keyboard[(self.state.v[x] & 0x0F) as usize] {
+            self.skip_next_instruction();
+        }
     }
 
     fn execute_sknp_vx(&mut self, x: usize) {
-        self.state.pc += if self.keyboard[self.state.v[x] as usize] {
-            0
-        } else {
-            2
-        };
+        if !self.keyboard[(self.state.v[x] & 0x0F) as usize] {
+            self.skip_next_instruction();
+        }
     }
 
     fn execute_ld_vx_dt(&mut self, x: usize) {
         self.state.v[x] = self.state.delay_timer;
     }
 
+    // Waits by running itself again, the PC may have wrapped past 0xFFFF.
     fn execute_ld_vx_k(&mut self, x: usize) {
-        self.state.pc -= 2;
-        let key_press = self.keyboard.iter().position(|&x| x == true);
-
-        if key_press.is_some() {
-            self.state.v[x] = key_press.unwrap() as u8;
-            self.state.pc += 2;
+        match self.keyboard.iter().position(|&pressed| pressed) {
+            Some(key) => self.state.v[x] = key as u8,
+            None => self.state.pc = self.state.pc.wrapping_sub(2),
         }
     }
 
@@ -338,31 +671,101 @@ impl Chip8MachineState {
     }
 
     fn execute_add_i_vx(&mut self, x: usize) {
-        self.state.i += self.state.v[x] as u16;
+        self.state.i = self.state.i.wrapping_add(self.state.v[x] as u16);
+    }
+
+    fn execute_ld_f_vx(&mut self, x: usize) -> Result<(), Fault> {
+        self.state.i = self
+            .state
+            .font_glyph_address(self.state.v[x])
+            .ok_or(Fault::MemoryOutOfBounds(u16::MAX as usize + 1))?;
+        Ok(())
+    }
+
+    fn execute_ld_i_long(&mut self) -> Result<(), Fault> {
+        let pc = self.state.pc as usize;
+
+        if pc + 2 > self.state.memory.len() {
+            return Err(Fault::MemoryOutOfBounds(pc + 1));
+        }
+
+        self.state.i = u16::from_be_bytes([self.state.memory[pc], self.state.memory[pc + 1]]);
+        self.state.pc = self.state.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    fn execute_plane_n(&mut self, n: usize) {
+        self.planes = n as u8 & ALL_PLANES;
+    }
+
+    fn execute_audio(&mut self) -> Result<(), Fault> {
+        let index = self.check_memory_range(AUDIO_PATTERN_SIZE, Access::Read)?;
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+
+        pattern.copy_from_slice(&self.state.memory[index..index + AUDIO_PATTERN_SIZE]);
+        self.state.audio_pattern = Some(pattern);
+        Ok(())
+    }
+
+    fn execute_ld_pitch_vx(&mut self, x: usize) {
+        self.state.pitch = self.state.v[x];
     }
 
-    fn execute_ld_f_vx(&mut self, x: usize) {
-        self.state.i = self.state.v[x] as u16 * 5;
+    fn execute_ld_hf_vx(&mut self, x: usize) -> Result<(), Fault> {
+        self.state.i = self
+            .state
+            .big_font_glyph_address(self.state.v[x])
+            .ok_or(Fault::MemoryOutOfBounds(u16::MAX as usize + 1))?;
+        Ok(())
     }
 
-    fn execute_ld_b_vx(&mut self, x: usize) {
-        let index: usize = self.state.i as usize;
+    fn execute_ld_b_vx(&mut self, x: usize) -> Result<(), Fault> {
+        let index: usize = self.check_memory_range(3, Access::Write)?;
 
         self.state.memory[index] = self.state.v[x] / 100;
         self.state.memory[index + 1] = (self.state.v[x] / 10) % 10;
         self.state.memory[index + 2] = (self.state.v[x] % 100) % 10;
+        Ok(())
     }
 
-    fn execute_ld_ref_i_vx(&mut self, x: usize) {
+    fn execute_ld_ref_i_vx(&mut self, x: usize) -> Result<(), Fault> {
+        let index = self.check_memory_range(x + 1, Access::Write)?;
+
         for i in 0..=x {
-            self.state.memory[self.state.i as usize + i] = self.state.v[i];
+            self.state.memory[index + i] = self.state.v[i];
         }
+
+        self.increment_i_after_memory_access(x);
+        Ok(())
     }
 
-    fn execute_ld_vx_ref_i(&mut self, x: usize) {
+    fn execute_ld_vx_ref_i(&mut self, x: usize) -> Result<(), Fault> {
+        let index = self.check_memory_range(x + 1, Access::Read)?;
+
         for i in 0..=x {
-            self.state.v[i] = self.state.memory[self.state.i as usize + i];
+            self.state.v[i] = self.state.memory[index + i];
         }
+
+        self.increment_i_after_memory_access(x);
+        Ok(())
+    }
+
+    fn execute_ld_r_vx(&mut self, x: usize) {
+        self.state.rpl[..=x].copy_from_slice(&self.state.v[..=x]);
+    }
+
+    fn execute_ld_vx_r(&mut self, x: usize) {
+        self.state.v[..=x].copy_from_slice(&self.state.rpl[..=x]);
+    }
+
+    fn increment_i_after_memory_access(&mut self, x: usize) {
+        let increment = match self.quirks.memory_increment {
+            MemoryIncrement::None => 0,
+            MemoryIncrement::X => x as u16,
+            MemoryIncrement::XPlusOne => x as u16 + 1,
+        };
+
+        self.state.i = self.state.i.wrapping_add(increment);
     }
 
     pub fn set_key(&mut self, key: usize, pressed: bool) {
@@ -371,3 +774,9 @@ impl Chip8MachineState {
         }
     }
 }
+
+impl Default for Chip8MachineState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,8 +1,59 @@
+use crate::disassembly::PROGRAM_START;
 use std::{fs::File, io::Read, path::Path};
 
 pub const V_SIZE: usize = 16;
 pub const STACK_SIZE: usize = 16;
 pub const MEMORY_SIZE: usize = 4096;
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH: u8 = 64;
+pub const FONT_GLYPH_SIZE: usize = 5;
+pub const FONT_SIZE: usize = 16 * FONT_GLYPH_SIZE;
+pub const FONT_ADDRESS: u16 = 0x050;
+pub const BIG_FONT_GLYPH_SIZE: usize = 10;
+pub const BIG_FONT_SIZE: usize = 16 * BIG_FONT_GLYPH_SIZE;
+pub const BIG_FONT_ADDRESS: u16 = FONT_ADDRESS + FONT_SIZE as u16;
+pub const RPL_SIZE: usize = 16;
+
+// Standard 4x5 hexadecimal digits 0-F, one byte per row.
+pub const DEFAULT_FONT: [u8; FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// SUPER-CHIP 8x10 digits used by Fx30, with Octo's A-F extension.
+pub const DEFAULT_BIG_FONT: [u8; BIG_FONT_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
 
 #[derive(Debug)]
 pub struct Chip8State {
@@ -13,12 +64,36 @@ pub struct Chip8State {
     pub pc: u16,
     pub sp: u8,
     pub stack: [u16; STACK_SIZE],
-    pub memory: [u8; MEMORY_SIZE],
+    pub memory: Vec<u8>,
+    pub font_address: u16,
+    pub big_font_address: u16,
+    // SUPER-CHIP RPL user flags, saved and restored by Fx75/Fx85.
+    pub rpl: [u8; RPL_SIZE],
+    // XO-CHIP 1-bit sample loaded by F002, the buzzer is used until then.
+    pub audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    // XO-CHIP playback rate set by Fx3A, 64 is 4000 Hz.
+    pub pitch: u8,
 }
 
 impl Chip8State {
     pub fn new() -> Self {
-        Self {
+        Self::with_font(FONT_ADDRESS, &DEFAULT_FONT).expect("the default font fits")
+    }
+
+    // The big font follows the chosen font, both have to fit in the
+    // interpreter area below the program.
+    pub fn with_font(address: u16, glyphs: &[u8; FONT_SIZE]) -> std::io::Result<Self> {
+        let big_font_address = address as usize + FONT_SIZE;
+        if big_font_address + BIG_FONT_SIZE > PROGRAM_START {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Font address {address:#05X} leaves no room for the fonts below {PROGRAM_START:#05X}"
+                ),
+            ));
+        }
+
+        let mut chip8 = Self {
             v: [0; V_SIZE],
             i: 0,
             delay_timer: 0,
@@ -26,8 +101,66 @@ impl Chip8State {
             pc: 0x200, // Program counter starts at 0x200
             sp: 0,
             stack: [0; STACK_SIZE],
-            memory: [0; MEMORY_SIZE],
+            memory: vec![0; MEMORY_SIZE],
+            font_address: address,
+            big_font_address: big_font_address as u16,
+            rpl: [0; RPL_SIZE],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+        };
+
+        chip8.load_font(address, glyphs)?;
+        chip8.load_big_font(big_font_address as u16, &DEFAULT_BIG_FONT)?;
+        Ok(chip8)
+    }
+
+    // XO-CHIP uses a 64 KiB address space instead of 4 KiB.
+    pub fn with_memory_size(memory_size: usize) -> Self {
+        let mut chip8 = Self::new();
+        chip8.memory.resize(memory_size, 0);
+        chip8
+    }
+
+    pub fn load_font(&mut self, address: u16, glyphs: &[u8; FONT_SIZE]) -> std::io::Result<()> {
+        self.load_glyphs(address, glyphs)?;
+        self.font_address = address;
+        Ok(())
+    }
+
+    pub fn load_big_font(
+        &mut self,
+        address: u16,
+        glyphs: &[u8; BIG_FONT_SIZE],
+    ) -> std::io::Result<()> {
+        self.load_glyphs(address, glyphs)?;
+        self.big_font_address = address;
+        Ok(())
+    }
+
+    fn load_glyphs(&mut self, address: u16, glyphs: &[u8]) -> std::io::Result<()> {
+        let start = address as usize;
+        let end = start + glyphs.len();
+
+        if end > self.memory.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Font at {address:#05X} does not fit in memory"),
+            ));
         }
+
+        self.memory[start..end].copy_from_slice(glyphs);
+        Ok(())
+    }
+
+    // None when the glyph would be past 0xFFFF.
+    pub fn font_glyph_address(&self, digit: u8) -> Option<u16> {
+        self.font_address
+            .checked_add((digit & 0x0F) as u16 * FONT_GLYPH_SIZE as u16)
+    }
+
+    pub fn big_font_glyph_address(&self, digit: u8) -> Option<u16> {
+        self.big_font_address
+            .checked_add((digit & 0x0F) as u16 * BIG_FONT_GLYPH_SIZE as u16)
     }
 
     pub fn with_all_instructions() -> Chip8State {
@@ -55,16 +188,26 @@ impl Chip8State {
         let mut rom_buffer = Vec::new();
         file.read_to_end(&mut rom_buffer)?;
 
-        let end_address = address + rom_buffer.len();
+        self.load_rom_bytes(&rom_buffer, address)
+    }
+
+    pub fn load_rom_bytes(&mut self, rom: &[u8], address: usize) -> std::io::Result<()> {
+        let end_address = address + rom.len();
 
-        if end_address >= MEMORY_SIZE {
+        if end_address >= self.memory.len() {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 "ROM file too large for CHIP-8 memory",
             ));
         }
 
-        self.memory[address..end_address].copy_from_slice(&rom_buffer);
+        self.memory[address..end_address].copy_from_slice(rom);
         Ok(())
     }
 }
+
+impl Default for Chip8State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
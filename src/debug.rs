@@ -0,0 +1,243 @@
+use crate::frontend::{Frontend, Hotkey};
+use chip8::debugger::{
+    Breakpoint, Command, DISASSEMBLY_CONTEXT, Debugger, RunTarget, StopReason, disassembly_window,
+    format_registers,
+};
+use chip8::scheduler::Scheduler;
+use chip8::{Chip8MachineState, SourceMap};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Commands:
+  b, break <addr>     Set a breakpoint
+  b <addr> if <expr>  Break when the expression is non-zero at an address
+  b if <expr>         Break as soon as the expression is non-zero
+  log <addr> <expr>   Print an expression at an address without stopping
+  ignore #<n> <count> Let breakpoint n pass count times
+  d, delete <addr|#n> Clear breakpoints at an address, or breakpoint n
+  bl, breakpoints     List breakpoints with their hit counts
+  p, print <expr>     Evaluate an expression, e.g. mem[I] + V3 or cycles > 500
+  w, watch <target>   Watch a register (V0-VF, I, DT, ST) or memory
+                      (<addr>[-<addr>] [r|w|rw])
+  uw, unwatch <n>     Remove watchpoint n
+  wl, watches         List watchpoints
+  s, step [n]         Execute n instructions (default 1)
+  n, next             Step over a CALL
+  f, finish           Run until the current subroutine returns
+  c, continue         Run until a breakpoint (F12 in the window breaks in)
+  r, regs             Show registers, timers and stack
+  l, list [addr]      Disassemble around PC or an address
+  q, quit             Exit
+An empty line repeats the last command.";
+
+// Debugger REPL on stdin. The window keeps showing the screen, and takes
+// keypad input while the ROM runs.
+pub fn run(
+    chip8: &mut Chip8MachineState,
+    display: &mut dyn Frontend,
+    scheduler: &Scheduler,
+    source_map: Option<SourceMap>,
+) {
+    chip8.cycles_per_timer_tick = scheduler.instructions_per_frame();
+    let mut debugger = Debugger::new();
+    debugger.source_map = source_map;
+    let mut last_command: Option<Command> = None;
+
+    println!("{}", format_registers(chip8));
+    print_window(chip8, &debugger, chip8.state.pc);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(chip8) ");
+        let _ = io::stdout().flush();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => return,
+        };
+        let command = if line.trim().is_empty() {
+            match &last_command {
+                Some(command) => command.clone(),
+                None => continue,
+            }
+        } else {
+            match Command::parse(&line) {
+                Ok(command) => command,
+                Err(err) => {
+                    println!("{err}");
+                    continue;
+                }
+            }
+        };
+        last_command = Some(command.clone());
+
+        match command {
+            Command::Break(address) => {
+                let id = debugger.add_breakpoint(address);
+                println!("Breakpoint #{id} at {:#05X}", address);
+            }
+            Command::BreakIf { address, condition } => {
+                let id = debugger.add(Breakpoint {
+                    address,
+                    condition: Some(condition),
+                    ..Breakpoint::at(0)
+                });
+                println!("Breakpoint #{id}: {}", debugger.breakpoints[&id]);
+            }
+            Command::Log(address, expression) => {
+                let id = debugger.add(Breakpoint {
+                    log: Some(expression),
+                    ..Breakpoint::at(address)
+                });
+                println!("Logpoint #{id}: {}", debugger.breakpoints[&id]);
+            }
+            Command::Ignore(id, count) => match debugger.breakpoints.get_mut(&id) {
+                Some(breakpoint) => breakpoint.ignore_count = count,
+                None => println!("No breakpoint #{id}"),
+            },
+            Command::Delete(address) => {
+                if !debugger.remove_breakpoint(address) {
+                    println!("No breakpoint at {:#05X}", address);
+                }
+            }
+            Command::DeleteId(id) => {
+                if !debugger.remove(id) {
+                    println!("No breakpoint #{id}");
+                }
+            }
+            Command::Breakpoints => {
+                for (id, breakpoint) in &debugger.breakpoints {
+                    println!("#{id} {breakpoint}");
+                }
+            }
+            Command::Print(expression) => {
+                let value = expression.evaluate(chip8);
+                println!("{} = {:#X} ({})", expression, value, value);
+            }
+            Command::Watch(watchpoint) => {
+                println!("Watchpoint {}: {}", chip8.watchpoints.len(), watchpoint);
+                chip8.watchpoints.push(watchpoint);
+            }
+            Command::Unwatch(number) => {
+                if number < chip8.watchpoints.len() {
+                    chip8.watchpoints.remove(number);
+                } else {
+                    println!("No watchpoint {number}");
+                }
+            }
+            Command::Watches => {
+                for (number, watchpoint) in chip8.watchpoints.iter().enumerate() {
+                    println!("{number}: {watchpoint}");
+                }
+            }
+            Command::Step(count) => {
+                let mut reason = StopReason::Stepped;
+                for _ in 0..count {
+                    reason = debugger.run(chip8, RunTarget::Step, 1);
+                    if reason != StopReason::Stepped {
+                        break;
+                    }
+                }
+                stopped(chip8, display, &mut debugger, reason);
+            }
+            Command::Next => {
+                let target = Debugger::step_over_target(chip8);
+                let reason = run_with_display(chip8, display, &mut debugger, scheduler, target);
+                stopped(chip8, display, &mut debugger, reason);
+            }
+            Command::Finish => match Debugger::step_out_target(chip8) {
+                Some(target) => {
+                    let reason = run_with_display(chip8, display, &mut debugger, scheduler, target);
+                    stopped(chip8, display, &mut debugger, reason);
+                }
+                None => println!("Not inside a subroutine"),
+            },
+            Command::Continue => {
+                let reason = run_with_display(
+                    chip8,
+                    display,
+                    &mut debugger,
+                    scheduler,
+                    RunTarget::Continue,
+                );
+                stopped(chip8, display, &mut debugger, reason);
+            }
+            Command::Registers => println!("{}", format_registers(chip8)),
+            Command::List(address) => {
+                print_window(chip8, &debugger, address.unwrap_or(chip8.state.pc))
+            }
+            Command::Help => println!("{HELP}"),
+            Command::Quit => return,
+        }
+    }
+}
+
+// Runs one frame's worth of instructions per window update until the target
+// stops, the window closes or F12 is pressed. Those last two yield `Limit`.
+fn run_with_display(
+    chip8: &mut Chip8MachineState,
+    display: &mut dyn Frontend,
+    debugger: &mut Debugger,
+    scheduler: &Scheduler,
+    target: RunTarget,
+) -> StopReason {
+    while display.update(chip8) {
+        if display.hotkeys().contains(&Hotkey::Break) {
+            break;
+        }
+
+        let reason = debugger.run(chip8, target, scheduler.instructions_per_frame());
+        print_log(debugger);
+        if reason != StopReason::Limit {
+            return reason;
+        }
+    }
+
+    StopReason::Limit
+}
+
+fn stopped(
+    chip8: &mut Chip8MachineState,
+    display: &mut dyn Frontend,
+    debugger: &mut Debugger,
+    reason: StopReason,
+) {
+    print_log(debugger);
+    match reason {
+        StopReason::Breakpoint(address) => println!("Breakpoint hit at {:#05X}", address),
+        StopReason::Watchpoint(hits) => {
+            for hit in hits {
+                println!("Watchpoint {}: {}", hit.watchpoint, hit);
+            }
+        }
+        StopReason::Limit => println!("Interrupted"),
+        StopReason::Halted => println!("Program exited"),
+        StopReason::Fault(err) => println!("CHIP-8 crashed: {err}"),
+        StopReason::Stepped | StopReason::Finished => {}
+    }
+
+    display.update(chip8);
+    print_window(chip8, debugger, chip8.state.pc);
+}
+
+fn print_window(chip8: &Chip8MachineState, debugger: &Debugger, address: u16) {
+    if let Some(line) = debugger.source_line(address) {
+        println!("Source line {line}");
+    }
+    for line in disassembly_window(
+        &chip8.state,
+        &debugger.breakpoint_addresses(),
+        address,
+        DISASSEMBLY_CONTEXT,
+        DISASSEMBLY_CONTEXT,
+    ) {
+        println!("{line}");
+    }
+}
+
+fn print_log(debugger: &mut Debugger) {
+    for line in debugger.log.drain(..) {
+        println!("{line}");
+    }
+}
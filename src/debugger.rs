@@ -0,0 +1,469 @@
+use crate::chip8_machine::Chip8MachineState;
+use crate::chip8_state::Chip8State;
+use crate::disassembly::DisassemblyOutput;
+use crate::error::Chip8Error;
+use crate::expression::Expression;
+use crate::octo::SourceMap;
+use crate::watchpoint::{Register, WatchHit, Watchpoint};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+// Lines shown before and after PC by `list` without an address.
+pub const DISASSEMBLY_CONTEXT: usize = 5;
+
+// How far `Debugger::run` goes before handing control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunTarget {
+    // A single instruction.
+    Step,
+    // Until a breakpoint.
+    Continue,
+    // Until PC is back at `address` with the same stack depth, i.e. a CALL returned.
+    StepOver { address: u16, sp: u8 },
+    // Until a RET drops the stack below `sp`.
+    StepOut { sp: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Stepped,
+    // The target was reached (StepOver/StepOut).
+    Finished,
+    Breakpoint(u16),
+    Watchpoint(Vec<WatchHit>),
+    // The cycle budget ran out before anything else happened.
+    Limit,
+    Halted,
+    Fault(Chip8Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Break(u16),
+    // `break [<addr>] if <expr>`, without an address checked at every instruction.
+    BreakIf {
+        address: Option<u16>,
+        condition: Expression,
+    },
+    Log(u16, Expression),
+    Ignore(usize, u64),
+    Delete(u16),
+    DeleteId(usize),
+    Breakpoints,
+    Print(Expression),
+    Watch(Watchpoint),
+    Unwatch(usize),
+    Watches,
+    Step(u32),
+    Next,
+    Finish,
+    Continue,
+    Registers,
+    List(Option<u16>),
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or("Empty command")?;
+        let argument = words.next();
+        // Expressions take the rest of the line, spaces included
+        let expression = |skip: usize| Expression::parse(after_words(line, skip));
+
+        let address = || {
+            argument
+                .ok_or(format!("Missing address for {name}"))
+                .and_then(parse_address)
+        };
+
+        let number = |text: Option<&str>| {
+            let text = text.ok_or(format!("Missing number for {name}"))?;
+            text.parse::<u64>()
+                .map_err(|_| format!("Invalid number: {text}"))
+        };
+
+        let command = match name {
+            "b" | "break" => match (argument, words.next()) {
+                (Some("if"), _) => {
+                    return Ok(Command::BreakIf {
+                        address: None,
+                        condition: expression(2)?,
+                    });
+                }
+                (_, Some("if")) => {
+                    return Ok(Command::BreakIf {
+                        address: Some(address()?),
+                        condition: expression(3)?,
+                    });
+                }
+                (_, Some(extra)) => return Err(format!("Unexpected argument: {extra}")),
+                (_, None) => Command::Break(address()?),
+            },
+            "log" => return Ok(Command::Log(address()?, expression(2)?)),
+            "p" | "print" => return Ok(Command::Print(expression(1)?)),
+            "ignore" => {
+                let id = argument
+                    .and_then(|id| id.strip_prefix('#'))
+                    .ok_or(format!("Expected a breakpoint number like #1 for {name}"))?;
+                Command::Ignore(number(Some(id))? as usize, number(words.next())?)
+            }
+            "d" | "delete" => match argument.and_then(|id| id.strip_prefix('#')) {
+                Some(id) => Command::DeleteId(number(Some(id))? as usize),
+                None => Command::Delete(address()?),
+            },
+            "bl" | "breakpoints" => Command::Breakpoints,
+            "w" | "watch" => {
+                let target = argument.ok_or(format!("Missing address or register for {name}"))?;
+                Command::Watch(parse_watchpoint(target, words.next())?)
+            }
+            "uw" | "unwatch" => {
+                let number = argument.ok_or(format!("Missing watchpoint number for {name}"))?;
+                Command::Unwatch(
+                    number
+                        .parse()
+                        .map_err(|_| format!("Invalid watchpoint number: {number}"))?,
+                )
+            }
+            "wl" | "watches" => Command::Watches,
+            "s" | "step" => match argument {
+                Some(count) => Command::Step(
+                    count
+                        .parse()
+                        .map_err(|_| format!("Invalid step count: {count}"))?,
+                ),
+                None => Command::Step(1),
+            },
+            "n" | "next" => Command::Next,
+            "f" | "finish" => Command::Finish,
+            "c" | "continue" => Command::Continue,
+            "r" | "regs" => Command::Registers,
+            "l" | "list" => Command::List(argument.map(parse_address).transpose()?),
+            "h" | "help" | "?" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ => return Err(format!("Unknown command: {name}")),
+        };
+
+        let takes_argument = matches!(
+            command,
+            Command::Break(_)
+                | Command::Delete(_)
+                | Command::DeleteId(_)
+                | Command::Ignore(..)
+                | Command::Watch(_)
+                | Command::Unwatch(_)
+                | Command::Step(_)
+                | Command::List(_)
+        );
+        let extra = if takes_argument {
+            words.next()
+        } else {
+            argument
+        };
+
+        match extra {
+            Some(extra) => Err(format!("Unexpected argument: {extra}")),
+            None => Ok(command),
+        }
+    }
+}
+
+// The text after the first `count` words.
+fn after_words(text: &str, count: usize) -> &str {
+    let mut rest = text.trim_start();
+
+    for _ in 0..count {
+        rest = rest
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, rest)| rest)
+            .trim_start();
+    }
+
+    rest
+}
+
+// Accepts hex with or without 0x, as printed by the disassembler.
+pub fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address: {text}"))
+}
+
+// `V3`, `I`, `DT` or `ST` watch for changes, `0x3F0` or `0x3F0-0x3F4` for
+// accesses, optionally limited by a mode of `r`, `w` or `rw` (the default).
+pub fn parse_watchpoint(target: &str, mode: Option<&str>) -> Result<Watchpoint, String> {
+    if let Some(register) = Register::from_name(target) {
+        return match mode {
+            Some(mode) => Err(format!("Unexpected argument: {mode}")),
+            None => Ok(Watchpoint::Register(register)),
+        };
+    }
+
+    let (start, end) = match target.split_once('-') {
+        Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+        None => (parse_address(target)?, parse_address(target)?),
+    };
+    let (read, write) = match mode {
+        None | Some("rw") => (true, true),
+        Some("r") => (true, false),
+        Some("w") => (false, true),
+        Some(mode) => return Err(format!("Invalid watch mode: {mode}")),
+    };
+
+    Ok(Watchpoint::memory(
+        start as usize,
+        end as usize,
+        read,
+        write,
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    // None checks the condition at every instruction.
+    pub address: Option<u16>,
+    pub condition: Option<Expression>,
+    // Logpoints record this expression instead of stopping.
+    pub log: Option<Expression>,
+    // Hits to let pass before stopping or logging, like gdb's ignore count.
+    pub ignore_count: u64,
+    // Times the breakpoint was reached with its condition true.
+    pub hits: u64,
+}
+
+impl Breakpoint {
+    pub fn at(address: u16) -> Self {
+        Self {
+            address: Some(address),
+            condition: None,
+            log: None,
+            ignore_count: 0,
+            hits: 0,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "{:#05X}", address)?,
+            None => write!(f, "any")?,
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " if {condition}")?;
+        }
+        if let Some(log) = &self.log {
+            write!(f, " log {log}")?;
+        }
+        if self.ignore_count > 0 {
+            write!(f, " ignore {}", self.ignore_count)?;
+        }
+        write!(f, " (hits: {})", self.hits)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    // By breakpoint number, which stays stable when others are deleted.
+    pub breakpoints: BTreeMap<usize, Breakpoint>,
+    // Logpoint output, for the frontend to drain.
+    pub log: Vec<String>,
+    // Set when the ROM was compiled from source, to show source lines.
+    pub source_map: Option<SourceMap>,
+    next_id: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn source_line(&self, address: u16) -> Option<usize> {
+        self.source_map.as_ref()?.line(address)
+    }
+
+    // Returns the breakpoint number.
+    pub fn add(&mut self, breakpoint: Breakpoint) -> usize {
+        self.next_id += 1;
+        self.breakpoints.insert(self.next_id, breakpoint);
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        self.breakpoints.remove(&id).is_some()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) -> usize {
+        self.add(Breakpoint::at(address))
+    }
+
+    // Removes every breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints
+            .retain(|_, breakpoint| breakpoint.address != Some(address));
+        self.breakpoints.len() != count
+    }
+
+    pub fn breakpoint_addresses(&self) -> BTreeSet<u16> {
+        self.breakpoints
+            .values()
+            .filter_map(|breakpoint| breakpoint.address)
+            .collect()
+    }
+
+    // Counts hits and records logpoints for the instruction at PC. Returns
+    // whether a breakpoint wants to stop there.
+    fn check_breakpoints(&mut self, chip8: &Chip8MachineState) -> bool {
+        let pc = chip8.state.pc;
+        let mut stop = false;
+
+        for breakpoint in self.breakpoints.values_mut() {
+            if breakpoint.address.is_some_and(|address| address != pc)
+                || !breakpoint
+                    .condition
+                    .as_ref()
+                    .is_none_or(|condition| condition.is_true(chip8))
+            {
+                continue;
+            }
+
+            breakpoint.hits += 1;
+            if breakpoint.hits <= breakpoint.ignore_count {
+                continue;
+            }
+
+            match &breakpoint.log {
+                Some(expression) => {
+                    let value = expression.evaluate(chip8);
+                    self.log.push(format!(
+                        "{:#05X}: {} = {:#X} ({})",
+                        pc, expression, value, value
+                    ));
+                }
+                None => stop = true,
+            }
+        }
+
+        stop
+    }
+
+    // Steps over the instruction at PC if it is a CALL, otherwise a plain step.
+    pub fn step_over_target(chip8: &Chip8MachineState) -> RunTarget {
+        let pc = chip8.state.pc as usize;
+
+        match chip8.state.memory.get(pc) {
+            Some(byte) if byte >> 4 == 0x2 => RunTarget::StepOver {
+                address: chip8.state.pc.wrapping_add(2),
+                sp: chip8.state.sp,
+            },
+            _ => RunTarget::Step,
+        }
+    }
+
+    // None at the top level, where there is nothing to return from.
+    pub fn step_out_target(chip8: &Chip8MachineState) -> Option<RunTarget> {
+        (chip8.state.sp > 0).then_some(RunTarget::StepOut { sp: chip8.state.sp })
+    }
+
+    // Executes at most `budget` instructions. The instruction at PC always runs,
+    // so continuing from a breakpoint does not stop on it again straight away.
+    pub fn run(
+        &mut self,
+        chip8: &mut Chip8MachineState,
+        target: RunTarget,
+        budget: u32,
+    ) -> StopReason {
+        for _ in 0..budget {
+            if let Err(err) = chip8.execute_cycle() {
+                return StopReason::Fault(err);
+            }
+            if !chip8.watch_hits.is_empty() {
+                return StopReason::Watchpoint(std::mem::take(&mut chip8.watch_hits));
+            }
+            if chip8.halted {
+                return StopReason::Halted;
+            }
+
+            let pc = chip8.state.pc;
+            if self.check_breakpoints(chip8) {
+                return StopReason::Breakpoint(pc);
+            }
+
+            match target {
+                RunTarget::Step => return StopReason::Stepped,
+                RunTarget::StepOver { address, sp } if pc == address && chip8.state.sp == sp => {
+                    return StopReason::Finished;
+                }
+                RunTarget::StepOut { sp } if chip8.state.sp < sp => return StopReason::Finished,
+                _ => {}
+            }
+        }
+
+        StopReason::Limit
+    }
+}
+
+pub fn format_registers(chip8: &Chip8MachineState) -> String {
+    let state = &chip8.state;
+    let registers = |range: std::ops::Range<usize>| {
+        range
+            .map(|index| format!("V{:X}={:02X}", index, state.v[index]))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let stack = state.stack[..state.sp as usize]
+        .iter()
+        .map(|address| format!("{:#05X}", address))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(
+        "PC={:#05X} I={:#05X} DT={:02X} ST={:02X} SP={} cycles={}\n{}\n{}\nStack: [{}]",
+        state.pc,
+        state.i,
+        state.delay_timer,
+        state.sound_timer,
+        state.sp,
+        chip8.cycles,
+        registers(0..8),
+        registers(8..16),
+        stack
+    )
+}
+
+// Disassembles `before` instructions before `address` up to `after` ones past
+// it. "=>" marks PC and "*" a breakpoint.
+pub fn disassembly_window(
+    state: &Chip8State,
+    breakpoints: &BTreeSet<u16>,
+    address: u16,
+    before: usize,
+    after: usize,
+) -> Vec<String> {
+    let last = state.memory.len().saturating_sub(2);
+    let start = (address as usize).saturating_sub(before * 2);
+    let end = (address as usize + after * 2).min(last);
+
+    (start..=end)
+        .step_by(2)
+        .map(|line| {
+            let marker = if line == state.pc as usize {
+                "=>"
+            } else {
+                "  "
+            };
+            let breakpoint = if breakpoints.contains(&(line as u16)) {
+                "*"
+            } else {
+                " "
+            };
+
+            format!("{}{} {}", marker, breakpoint, state.disassemble(line))
+        })
+        .collect()
+}
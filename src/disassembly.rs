@@ -1,4 +1,5 @@
 use crate::chip8_state::Chip8State;
+use std::collections::{BTreeMap, BTreeSet};
 
 pub trait DisassemblyOutput {
     fn disassemble(&self, address: usize) -> String;
@@ -6,7 +7,28 @@ pub trait DisassemblyOutput {
 
 impl DisassemblyOutput for Chip8State {
     fn disassemble(&self, address: usize) -> String {
-        let instruction = &self.memory[address..address + 2];
+        // A lone byte at the end of memory is not a whole instruction
+        let Some(instruction) = self.memory.get(address..address + 2) else {
+            return match self.memory.get(address) {
+                Some(byte) => format!("{:#04X}: {:02X} -> Incomplete instruction", address, byte),
+                None => format!("{:#04X}: Outside memory", address),
+            };
+        };
+
+        // XO-CHIP F000 nnnn carries its address in the following word
+        if instruction == [0xF0, 0x00] && address + 4 <= self.memory.len() {
+            let instruction = &self.memory[address..address + 4];
+            return format!(
+                "{:#04X}: {:02X}{:02X}{:02X}{:02X} -> {}",
+                address,
+                instruction[0],
+                instruction[1],
+                instruction[2],
+                instruction[3],
+                decrypt_chip8_instruction(instruction)
+            );
+        }
+
         format!(
             "{:#04X}: {:02X}{:02X} -> {}",
             address,
@@ -17,7 +39,7 @@ impl DisassemblyOutput for Chip8State {
     }
 }
 
-fn decrypt_chip8_instruction(instruction: &[u8]) -> String {
+pub fn decrypt_chip8_instruction(instruction: &[u8]) -> String {
     let position3 = instruction[0] >> 4;
     let position2 = instruction[0] & 0x0F;
     let position1 = instruction[1] >> 4;
@@ -29,14 +51,23 @@ fn decrypt_chip8_instruction(instruction: &[u8]) -> String {
     let byte = instruction[1];
 
     match (position3, position2, position1, position0) {
+        (0x0, 0x0, 0xC, _) => format!("SCD {:#X}", nibble),
+        (0x0, 0x0, 0xD, _) => format!("SCU {:#X}", nibble),
         (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
         (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+        (0x0, 0x0, 0xF, 0xB) => "SCR".to_string(),
+        (0x0, 0x0, 0xF, 0xC) => "SCL".to_string(),
+        (0x0, 0x0, 0xF, 0xD) => "EXIT".to_string(),
+        (0x0, 0x0, 0xF, 0xE) => "LOW".to_string(),
+        (0x0, 0x0, 0xF, 0xF) => "HIGH".to_string(),
         (0x0, _, _, _) => format!("SYS {:#05X}", address),
         (0x1, _, _, _) => format!("JP {:#05X}", address),
         (0x2, _, _, _) => format!("CALL {:#05X}", address),
         (0x3, _, _, _) => format!("SE V{:X}, {:#04X}", x, byte),
         (0x4, _, _, _) => format!("SNE V{:X}, {:#04X}", x, byte),
         (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+        (0x5, _, _, 0x2) => format!("LD [I], V{:X}-V{:X}", x, y),
+        (0x5, _, _, 0x3) => format!("LD V{:X}-V{:X}, [I]", x, y),
         (0x6, _, _, _) => format!("LD V{:X}, {:#04X}", x, byte),
         (0x7, _, _, _) => format!("ADD V{:X}, {:#04X}", x, byte),
         (0x8, _, _, 0x0) => format!("LD V{:X}, V{:X}", x, y),
@@ -51,19 +82,253 @@ fn decrypt_chip8_instruction(instruction: &[u8]) -> String {
         (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
         (0xA, _, _, _) => format!("LD I, {:#05X}", address),
         (0xB, _, _, _) => format!("JP V0, {:#05X}", address),
-        (0xC, _, _, _) => format!("RND V{:X}, {:X}", x, byte),
+        (0xC, _, _, _) => format!("RND V{:X}, {:#04X}", x, byte),
         (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {:#X}", x, y, nibble),
         (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
         (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+        (0xF, 0x0, 0x0, 0x0) if instruction.len() >= 4 => format!(
+            "LD I, LONG {:#06X}",
+            u16::from_be_bytes([instruction[2], instruction[3]])
+        ),
+        (0xF, 0x0, 0x0, 0x0) => "LD I, LONG".to_string(),
+        (0xF, _, 0x0, 0x1) => format!("PLANE {:#X}", x),
+        (0xF, 0x0, 0x0, 0x2) => "AUDIO".to_string(),
         (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
         (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
         (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
         (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
         (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
         (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+        (0xF, _, 0x3, 0x0) => format!("LD HF, V{:X}", x),
         (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+        (0xF, _, 0x3, 0xA) => format!("LD PITCH, V{:X}", x),
         (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
         (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+        (0xF, _, 0x7, 0x5) => format!("LD R, V{:X}", x),
+        (0xF, _, 0x8, 0x5) => format!("LD V{:X}, R", x),
         _ => "Unknown instruction".to_string(),
     }
 }
+
+// Where ROMs are loaded and start executing.
+pub const PROGRAM_START: usize = 0x200;
+// Bytes per `DB` line in a listing.
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    // Only loaded into I
+    Data,
+    Jump,
+    Subroutine,
+}
+
+// Disassembles a whole ROM by following the control flow from its entry point,
+// so bytes that are never executed (sprites, tables) come out as `DB` data.
+// Jump, call and `LD I` targets inside the ROM get labels, and the listing
+// assembles back to the same bytes.
+#[derive(Debug, Clone)]
+pub struct ProgramDisassembly {
+    rom: Vec<u8>,
+    // Addresses of the reachable instructions.
+    pub instructions: BTreeSet<usize>,
+    labels: BTreeMap<usize, LabelKind>,
+}
+
+impl ProgramDisassembly {
+    pub fn new(rom: &[u8]) -> Self {
+        let mut disassembly = Self {
+            rom: rom.to_vec(),
+            instructions: BTreeSet::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembly.trace(PROGRAM_START);
+        // Labels only go where a line starts
+        let labels = std::mem::take(&mut disassembly.labels);
+        disassembly.labels = labels
+            .into_iter()
+            .filter(|(address, _)| !disassembly.inside_instruction(*address))
+            .collect();
+        disassembly
+    }
+
+    pub fn label(&self, address: usize) -> Option<String> {
+        self.labels.get(&address).map(|kind| match kind {
+            _ if address == PROGRAM_START => "start".to_string(),
+            LabelKind::Subroutine => format!("sub_{:03X}", address),
+            LabelKind::Jump => format!("label_{:03X}", address),
+            LabelKind::Data => format!("data_{:03X}", address),
+        })
+    }
+
+    fn bytes(&self, address: usize, len: usize) -> Option<&[u8]> {
+        let offset = address.checked_sub(PROGRAM_START)?;
+        self.rom.get(offset..offset + len)
+    }
+
+    fn in_rom(&self, address: usize) -> bool {
+        self.bytes(address, 1).is_some()
+    }
+
+    // F000 nnnn is the only four-byte instruction.
+    fn instruction_size(&self, address: usize) -> usize {
+        match self.bytes(address, 2) {
+            Some([0xF0, 0x00]) => 4,
+            _ => 2,
+        }
+    }
+
+    fn inside_instruction(&self, address: usize) -> bool {
+        self.instructions
+            .range(address.saturating_sub(3)..address)
+            .any(|&start| start + self.instruction_size(start) > address)
+    }
+
+    fn add_label(&mut self, address: usize, kind: LabelKind) {
+        if self.in_rom(address) {
+            let entry = self.labels.entry(address).or_insert(kind);
+            *entry = (*entry).max(kind);
+        }
+    }
+
+    fn trace(&mut self, entry: usize) {
+        self.add_label(entry, LabelKind::Jump);
+        let mut pending = vec![entry];
+
+        while let Some(address) = pending.pop() {
+            let size = self.instruction_size(address);
+            let Some(bytes) = self.bytes(address, size) else {
+                continue;
+            };
+            let bytes = bytes.to_vec();
+            let overlaps = (address..address + size).any(|byte| {
+                self.inside_instruction(byte)
+                    || (byte > address && self.instructions.contains(&byte))
+            });
+            if self.instructions.contains(&address)
+                || overlaps
+                || decrypt_chip8_instruction(&bytes) == "Unknown instruction"
+            {
+                continue;
+            }
+            self.instructions.insert(address);
+
+            let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+            let target = (opcode & 0x0FFF) as usize;
+            let next = address + size;
+            match opcode >> 12 {
+                // RET and EXIT end the path
+                0x0 if opcode == 0x00EE || opcode == 0x00FD => {}
+                0x1 => {
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                }
+                0x2 => {
+                    self.add_label(target, LabelKind::Subroutine);
+                    pending.extend([next, target]);
+                }
+                0xA => {
+                    self.add_label(target, LabelKind::Data);
+                    pending.push(next);
+                }
+                // Usually a jump table, whose first entry is all we can know
+                0xB => {
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                }
+                0x3 | 0x4 | 0x5 | 0x9 | 0xE if is_skip(opcode) => {
+                    pending.extend([next, next + self.instruction_size(next)]);
+                }
+                0xF if opcode == 0xF000 => {
+                    let long = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+                    self.add_label(long, LabelKind::Data);
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+    }
+
+    fn operand(&self, address: usize, width: usize) -> String {
+        self.label(address)
+            .unwrap_or_else(|| format!("{:#0width$X}", address, width = width + 2))
+    }
+
+    // The source text of the instruction at `address`.
+    fn instruction(&self, bytes: &[u8]) -> String {
+        let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let target = (opcode & 0x0FFF) as usize;
+        let x = (opcode >> 8) & 0xF;
+        let y = (opcode >> 4) & 0xF;
+
+        match opcode >> 12 {
+            0x1 => format!("JP {}", self.operand(target, 3)),
+            0x2 => format!("CALL {}", self.operand(target, 3)),
+            0xA => format!("LD I, {}", self.operand(target, 3)),
+            0xB => format!("JP V0, {}", self.operand(target, 3)),
+            0xF if opcode == 0xF000 => {
+                let long = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+                format!("LD I, LONG {}", self.operand(long, 4))
+            }
+            // Keep the otherwise ignored Vy so the bytes round-trip
+            0x8 if y != 0 && opcode & 0xF == 0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x8 if y != 0 && opcode & 0xF == 0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => decrypt_chip8_instruction(bytes),
+        }
+    }
+
+    pub fn listing(&self) -> String {
+        let mut lines = Vec::new();
+        let end = PROGRAM_START + self.rom.len();
+        let mut address = PROGRAM_START;
+
+        while address < end {
+            if let Some(label) = self.label(address) {
+                lines.push(format!("{label}:"));
+            }
+
+            if self.instructions.contains(&address) {
+                let size = self.instruction_size(address);
+                let bytes = self.bytes(address, size).unwrap();
+                let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                lines.push(format!(
+                    "    {:<24}; {:03X}: {}",
+                    self.instruction(bytes),
+                    address,
+                    hex
+                ));
+                address += size;
+                continue;
+            }
+
+            // Data runs up to the next label or instruction
+            let start = address;
+            while address < end
+                && address - start < DATA_BYTES_PER_LINE
+                && !self.instructions.contains(&address)
+                && (address == start || !self.labels.contains_key(&address))
+            {
+                address += 1;
+            }
+            let bytes = self
+                .bytes(start, address - start)
+                .unwrap()
+                .iter()
+                .map(|byte| format!("{:#04X}", byte))
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push(format!("    {:<24}; {:03X}", format!("DB {bytes}"), start));
+        }
+
+        lines.join("\n") + "\n"
+    }
+}
+
+fn is_skip(opcode: u16) -> bool {
+    match opcode >> 12 {
+        0x3 | 0x4 => true,
+        0x5 | 0x9 => opcode & 0xF == 0,
+        0xE => matches!(opcode & 0xFF, 0x9E | 0xA1),
+        _ => false,
+    }
+}
@@ -1,5 +1,8 @@
-use crate::chip8_machine::{Chip8MachineState, KEYBOARD_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
-use minifb::{Key, Window, WindowOptions};
+use crate::frontend::{Frontend, Hotkey};
+use chip8::{
+    Chip8MachineState, DEFAULT_PALETTE, KEYBOARD_SIZE, Palette, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
 
 const SCALE: usize = 10;
 const WINDOW_WIDTH: usize = SCREEN_WIDTH * SCALE;
@@ -23,9 +26,21 @@ const KEY_MAP: [(Key, usize); KEYBOARD_SIZE] = [
     (Key::V, 0xF),    // F
 ];
 
+const HOTKEY_MAP: [(Key, Hotkey); 6] = [
+    (Key::Space, Hotkey::TogglePause),
+    (Key::Backspace, Hotkey::ToggleSlowMotion),
+    (Key::F2, Hotkey::Screenshot),
+    (Key::F5, Hotkey::SaveState),
+    (Key::F9, Hotkey::LoadState),
+    (Key::F12, Hotkey::Break),
+];
+const FAST_FORWARD_KEY: Key = Key::Tab;
+const REWIND_KEY: Key = Key::Backquote;
+
 pub struct Display {
     window: Window,
     buffer: Vec<u32>,
+    palette: Palette,
 }
 
 impl Display {
@@ -48,10 +63,13 @@ impl Display {
         Self {
             window,
             buffer: vec![0; WINDOW_WIDTH * WINDOW_HEIGHT],
+            palette: DEFAULT_PALETTE,
         }
     }
+}
 
-    pub fn update(&mut self, chip8: &mut Chip8MachineState) -> bool {
+impl Frontend for Display {
+    fn update(&mut self, chip8: &mut Chip8MachineState) -> bool {
         if !self.window.is_open() {
             return false;
         }
@@ -62,17 +80,19 @@ impl Display {
         }
 
         // Clear buffer
-        self.buffer.fill(0);
-
-        // Draw scaled pixels
-        for y in 0..SCREEN_HEIGHT {
-            for x in 0..SCREEN_WIDTH {
-                if chip8.get_pixel(x, y) {
-                    // Draw a SCALE x SCALE pixel
-                    for dy in 0..SCALE {
-                        for dx in 0..SCALE {
-                            let buffer_index = (y * SCALE + dy) * WINDOW_WIDTH + (x * SCALE + dx);
-                            self.buffer[buffer_index] = 0xFFFFFF; // White color
+        self.buffer.fill(self.palette[0]);
+
+        // Draw scaled pixels, hires mode uses half the scale of lores
+        let scale = WINDOW_WIDTH / chip8.display.width();
+        for y in 0..chip8.display.height() {
+            for x in 0..chip8.display.width() {
+                let planes = chip8.display.get_planes(x, y);
+                if planes != 0 {
+                    // Draw a scale x scale pixel
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            let buffer_index = (y * scale + dy) * WINDOW_WIDTH + (x * scale + dx);
+                            self.buffer[buffer_index] = self.palette[planes as usize];
                         }
                     }
                 }
@@ -86,4 +106,36 @@ impl Display {
 
         true
     }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn show_message(&mut self, message: &str) {
+        eprintln!("{message}");
+    }
+
+    fn hotkeys(&self) -> Vec<Hotkey> {
+        HOTKEY_MAP
+            .iter()
+            .filter(|(key, _)| self.window.is_key_pressed(*key, KeyRepeat::No))
+            .map(|(_, hotkey)| *hotkey)
+            .collect()
+    }
+
+    fn fast_forward_held(&self) -> bool {
+        self.window.is_key_down(FAST_FORWARD_KEY)
+    }
+
+    fn rewind_held(&self) -> bool {
+        self.window.is_key_down(REWIND_KEY)
+    }
+
+    fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
 }
@@ -0,0 +1,81 @@
+use std::fmt;
+
+// Faults raised by `Chip8MachineState::execute_cycle`. `address` is the PC of
+// the faulting instruction and `opcode` its raw 16-bit value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    UnknownOpcode {
+        address: u16,
+        opcode: u16,
+    },
+    StackOverflow {
+        address: u16,
+        opcode: u16,
+    },
+    StackUnderflow {
+        address: u16,
+        opcode: u16,
+    },
+    MemoryOutOfBounds {
+        address: u16,
+        opcode: u16,
+        memory_address: usize,
+    },
+    PcOutOfBounds {
+        address: u16,
+    },
+}
+
+impl Chip8Error {
+    pub fn address(&self) -> u16 {
+        match *self {
+            Chip8Error::UnknownOpcode { address, .. }
+            | Chip8Error::StackOverflow { address, .. }
+            | Chip8Error::StackUnderflow { address, .. }
+            | Chip8Error::MemoryOutOfBounds { address, .. }
+            | Chip8Error::PcOutOfBounds { address } => address,
+        }
+    }
+
+    pub fn opcode(&self) -> Option<u16> {
+        match *self {
+            Chip8Error::UnknownOpcode { opcode, .. }
+            | Chip8Error::StackOverflow { opcode, .. }
+            | Chip8Error::StackUnderflow { opcode, .. }
+            | Chip8Error::MemoryOutOfBounds { opcode, .. } => Some(opcode),
+            Chip8Error::PcOutOfBounds { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { address, opcode } => {
+                write!(
+                    f,
+                    "Unknown or invalid instruction at {address:#X}: {opcode:04X}"
+                )
+            }
+            Chip8Error::StackOverflow { address, opcode } => {
+                write!(f, "Stack overflow at {address:#X}: {opcode:04X}")
+            }
+            Chip8Error::StackUnderflow { address, opcode } => {
+                write!(f, "Stack underflow at {address:#X}: {opcode:04X}")
+            }
+            Chip8Error::MemoryOutOfBounds {
+                address,
+                opcode,
+                memory_address,
+            } => write!(
+                f,
+                "Memory access out of bounds at {address:#X}: {opcode:04X} accessed {memory_address:#X}"
+            ),
+            Chip8Error::PcOutOfBounds { address } => {
+                write!(f, "Program counter out of bounds: {address:#X}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
@@ -0,0 +1,386 @@
+use crate::chip8_machine::Chip8MachineState;
+use std::fmt;
+
+// Parentheses, brackets and unary operators nest this deep at most, so a typed
+// condition cannot run the parser out of stack.
+const MAX_NESTING_DEPTH: usize = 64;
+
+// Debugger expressions such as `V3 == 0x10 && I > 0x300` or `mem[0x3F0] != 0`,
+// with C operators and precedence. Names are case-insensitive: V0-VF, I, PC,
+// SP, DT, ST, cycles, halted, plus mem[..], stack[..] and key[..]. Out of range
+// indexes read as 0 and division by zero gives 0, so evaluation cannot fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+    Cycles,
+    Halted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Table {
+    Memory,
+    Stack,
+    Key,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOperator {
+    Not,
+    Complement,
+    Negate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOperator {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Variable(Variable),
+    Index(Table, Box<Node>),
+    Unary(UnaryOperator, Box<Node>),
+    Binary(BinaryOperator, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// Longest first, so `<=` is not read as `<`.
+const SYMBOLS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")", "[", "]",
+];
+
+// Binary operators by precedence level, lowest first.
+const PRECEDENCE: [&[(&str, BinaryOperator)]; 10] = [
+    &[("||", BinaryOperator::Or)],
+    &[("&&", BinaryOperator::And)],
+    &[("|", BinaryOperator::BitOr)],
+    &[("^", BinaryOperator::BitXor)],
+    &[("&", BinaryOperator::BitAnd)],
+    &[
+        ("==", BinaryOperator::Equal),
+        ("!=", BinaryOperator::NotEqual),
+    ],
+    &[
+        ("<", BinaryOperator::Less),
+        ("<=", BinaryOperator::LessEqual),
+        (">", BinaryOperator::Greater),
+        (">=", BinaryOperator::GreaterEqual),
+    ],
+    &[
+        ("<<", BinaryOperator::ShiftLeft),
+        (">>", BinaryOperator::ShiftRight),
+    ],
+    &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)],
+    &[
+        ("*", BinaryOperator::Multiply),
+        ("/", BinaryOperator::Divide),
+        ("%", BinaryOperator::Remainder),
+    ],
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+
+            if word.starts_with(|c: char| c.is_ascii_digit()) {
+                tokens.push(Token::Number(parse_number(word)?));
+            } else {
+                tokens.push(Token::Name(word.to_ascii_lowercase()));
+            }
+            rest = &rest[end..];
+        } else {
+            return Err(format!(
+                "Unexpected character: {}",
+                rest.chars().next().unwrap()
+            ));
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Result<i64, String> {
+    let lower = word.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2)
+    } else {
+        lower.parse()
+    };
+
+    parsed.map_err(|_| format!("Invalid number: {word}"))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    // Parses with `parse` one level deeper.
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        if self.depth == MAX_NESTING_DEPTH {
+            return Err("Expression is nested too deeply".to_string());
+        }
+
+        self.depth += 1;
+        let node = parse(self);
+        self.depth -= 1;
+        node
+    }
+
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(symbol)) => Some(symbol),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.peek_symbol() == Some(symbol) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected {symbol}"))
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(&(_, operator)) = PRECEDENCE[level]
+            .iter()
+            .find(|(symbol, _)| self.peek_symbol() == Some(symbol))
+        {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let operator = match self.peek_symbol() {
+            Some("!") => UnaryOperator::Not,
+            Some("~") => UnaryOperator::Complement,
+            Some("-") => UnaryOperator::Negate,
+            _ => return self.primary(),
+        };
+
+        self.position += 1;
+        let operand = self.nested(Self::unary)?;
+        Ok(Node::Unary(operator, Box::new(operand)))
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("Unexpected end of expression")?;
+        self.position += 1;
+
+        match token {
+            Token::Number(value) => Ok(Node::Number(value)),
+            Token::Symbol("(") => {
+                let node = self.nested(|parser| parser.binary(0))?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Symbol(symbol) => Err(format!("Unexpected {symbol}")),
+            Token::Name(name) => {
+                let table = match name.as_str() {
+                    "mem" => Some(Table::Memory),
+                    "stack" => Some(Table::Stack),
+                    "key" => Some(Table::Key),
+                    _ => None,
+                };
+                if let Some(table) = table {
+                    self.expect("[")?;
+                    let index = self.nested(|parser| parser.binary(0))?;
+                    self.expect("]")?;
+                    return Ok(Node::Index(table, Box::new(index)));
+                }
+
+                let variable = match name.as_str() {
+                    "i" => Variable::I,
+                    "pc" => Variable::Pc,
+                    "sp" => Variable::Sp,
+                    "dt" => Variable::DelayTimer,
+                    "st" => Variable::SoundTimer,
+                    "cycles" => Variable::Cycles,
+                    "halted" => Variable::Halted,
+                    _ => match name.strip_prefix('v') {
+                        Some(digit) if digit.len() == 1 => Variable::V(
+                            usize::from_str_radix(digit, 16)
+                                .map_err(|_| format!("Unknown name: {name}"))?,
+                        ),
+                        _ => return Err(format!("Unknown name: {name}")),
+                    },
+                };
+                Ok(Node::Variable(variable))
+            }
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        };
+        let root = parser.binary(0)?;
+
+        match parser.tokens.get(parser.position) {
+            Some(token) => Err(format!("Unexpected {token:?} in expression")),
+            None => Ok(Expression {
+                source: source.trim().to_string(),
+                root,
+            }),
+        }
+    }
+
+    pub fn evaluate(&self, chip8: &Chip8MachineState) -> i64 {
+        evaluate(&self.root, chip8)
+    }
+
+    pub fn is_true(&self, chip8: &Chip8MachineState) -> bool {
+        self.evaluate(chip8) != 0
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn evaluate(node: &Node, chip8: &Chip8MachineState) -> i64 {
+    let state = &chip8.state;
+
+    match node {
+        Node::Number(value) => *value,
+        Node::Variable(variable) => match variable {
+            Variable::V(index) => state.v[*index] as i64,
+            Variable::I => state.i as i64,
+            Variable::Pc => state.pc as i64,
+            Variable::Sp => state.sp as i64,
+            Variable::DelayTimer => state.delay_timer as i64,
+            Variable::SoundTimer => state.sound_timer as i64,
+            Variable::Cycles => chip8.cycles as i64,
+            Variable::Halted => chip8.halted as i64,
+        },
+        Node::Index(table, index) => {
+            let index = usize::try_from(evaluate(index, chip8)).ok();
+            let value = match table {
+                Table::Memory => index
+                    .and_then(|index| state.memory.get(index))
+                    .map(|&b| b as i64),
+                Table::Stack => index
+                    .and_then(|index| state.stack.get(index))
+                    .map(|&a| a as i64),
+                Table::Key => index
+                    .and_then(|index| chip8.keyboard.get(index))
+                    .map(|&k| k as i64),
+            };
+            value.unwrap_or(0)
+        }
+        Node::Unary(operator, operand) => {
+            let value = evaluate(operand, chip8);
+            match operator {
+                UnaryOperator::Not => (value == 0) as i64,
+                UnaryOperator::Complement => !value,
+                UnaryOperator::Negate => value.wrapping_neg(),
+            }
+        }
+        Node::Binary(operator, left, right) => {
+            let left = evaluate(left, chip8);
+            // Short-circuit like C
+            match operator {
+                BinaryOperator::Or if left != 0 => return 1,
+                BinaryOperator::And if left == 0 => return 0,
+                _ => {}
+            }
+            let right = evaluate(right, chip8);
+
+            match operator {
+                BinaryOperator::Or | BinaryOperator::And => (right != 0) as i64,
+                BinaryOperator::BitOr => left | right,
+                BinaryOperator::BitXor => left ^ right,
+                BinaryOperator::BitAnd => left & right,
+                BinaryOperator::Equal => (left == right) as i64,
+                BinaryOperator::NotEqual => (left != right) as i64,
+                BinaryOperator::Less => (left < right) as i64,
+                BinaryOperator::LessEqual => (left <= right) as i64,
+                BinaryOperator::Greater => (left > right) as i64,
+                BinaryOperator::GreaterEqual => (left >= right) as i64,
+                BinaryOperator::ShiftLeft => left.wrapping_shl(right as u32),
+                BinaryOperator::ShiftRight => left.wrapping_shr(right as u32),
+                BinaryOperator::Add => left.wrapping_add(right),
+                BinaryOperator::Subtract => left.wrapping_sub(right),
+                BinaryOperator::Multiply => left.wrapping_mul(right),
+                BinaryOperator::Divide => left.checked_div(right).unwrap_or(0),
+                BinaryOperator::Remainder => left.checked_rem(right).unwrap_or(0),
+            }
+        }
+    }
+}
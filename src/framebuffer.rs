@@ -0,0 +1,109 @@
+// Display whose resolution can change at runtime (SUPER-CHIP lores/hires).
+// Each pixel is a bitmask of the XO-CHIP bitplanes it is lit in: bit 0 is
+// the first plane, bit 1 the second. Plain CHIP-8 only uses the first plane.
+pub const PLANE_COUNT: usize = 2;
+pub const ALL_PLANES: u8 = (1 << PLANE_COUNT) - 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    // Whether the pixel is lit in any plane.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x] != 0
+    }
+
+    // Lights or clears the pixel in the first plane.
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        self.set_planes(x, y, on as u8);
+    }
+
+    pub fn get_planes(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_planes(&mut self, x: usize, y: usize, planes: u8) {
+        self.pixels[y * self.width + x] = planes & ALL_PLANES;
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(0);
+    }
+
+    pub fn clear_planes(&mut self, planes: u8) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel &= !planes);
+    }
+
+    // Changes the resolution, clearing the screen.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width * height];
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    pub fn scroll_down(&mut self, lines: usize, planes: u8) {
+        self.scroll(0, lines as isize, planes);
+    }
+
+    pub fn scroll_up(&mut self, lines: usize, planes: u8) {
+        self.scroll(0, -(lines as isize), planes);
+    }
+
+    pub fn scroll_right(&mut self, columns: usize, planes: u8) {
+        self.scroll(columns as isize, 0, planes);
+    }
+
+    pub fn scroll_left(&mut self, columns: usize, planes: u8) {
+        self.scroll(-(columns as isize), 0, planes);
+    }
+
+    // Moves the selected planes by (dx, dy), filling uncovered pixels with 0.
+    fn scroll(&mut self, dx: isize, dy: isize, planes: u8) {
+        let source = self.pixels.clone();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let from_x = x as isize - dx;
+                let from_y = y as isize - dy;
+                let inside = (0..self.width as isize).contains(&from_x)
+                    && (0..self.height as isize).contains(&from_y);
+                let moved = if inside {
+                    source[from_y as usize * self.width + from_x as usize] & planes
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.pixels[y * self.width + x];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use chip8::{Chip8MachineState, Palette};
+
+// Built without any frontend, nothing sends these.
+#[cfg_attr(not(any(feature = "minifb", feature = "tui")), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    TogglePause,
+    ToggleSlowMotion,
+    Screenshot,
+    SaveState,
+    LoadState,
+    // Stops a running `--debug` session.
+    Break,
+}
+
+// What the main loop and debugger need from the window or the terminal.
+pub trait Frontend {
+    // Shows the screen and reads the keypad, pacing calls to ~60 per second.
+    // False once the user has closed the frontend.
+    fn update(&mut self, chip8: &mut Chip8MachineState) -> bool;
+    fn set_title(&mut self, title: &str);
+    // Tells the user about saved files, crashes and the like while running.
+    fn show_message(&mut self, message: &str);
+    // Hotkeys pressed since the last update.
+    fn hotkeys(&self) -> Vec<Hotkey>;
+    fn fast_forward_held(&self) -> bool;
+    fn rewind_held(&self) -> bool;
+    fn palette(&self) -> Palette;
+    fn set_palette(&mut self, palette: Palette);
+}
@@ -0,0 +1,310 @@
+// Runs a ROM without a window, for CI and automated tests. Prints why it
+// stopped, the registers and the screen, and reports pass/fail via the exit
+// code.
+
+use chip8::debugger::{format_registers, parse_address};
+use chip8::rom_database::{DEFAULT_DATABASE_PATH, DEFAULT_OVERRIDES_PATH};
+use chip8::runner::{Runner, parse_key_script};
+use chip8::scheduler::{DEFAULT_CLOCK_HZ, Scheduler};
+use chip8::screenshot::{self, parse_palette};
+use chip8::{
+    Chip8MachineState, Expression, Movie, Platform, ProgramDisassembly, Quirks, RandomSource,
+    RomDatabase, RomSettings, RunOutcome, ScreenshotFormat, TIMER_FREQUENCY, load_rom_file,
+};
+use std::{env, fs, process};
+
+const EXIT_PASS: i32 = 0;
+const EXIT_USAGE: i32 = 1;
+const EXIT_ROM: i32 = 2;
+const EXIT_FAIL: i32 = 3;
+const EXIT_CRASH: i32 = 4;
+const EXIT_SCREENSHOT: i32 = 5;
+const EXIT_MOVIE: i32 = 6;
+const EXIT_DATABASE: i32 = 7;
+// 10 seconds of emulated time when no limit is given.
+const DEFAULT_FRAMES: u64 = 600;
+
+struct Options {
+    rom_path: String,
+    // Given on the command line, over those of the ROM file.
+    settings: RomSettings,
+    seed: u64,
+    runner: Runner,
+    expect: Option<Expression>,
+    quiet: bool,
+    screenshot_path: Option<String>,
+    screenshot_format: Option<ScreenshotFormat>,
+    scale: usize,
+    movie_path: Option<String>,
+    database_path: Option<String>,
+    overrides_path: Option<String>,
+    disassemble: bool,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut options = parse_options(&args).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        print_usage(&args[0]);
+        process::exit(EXIT_USAGE);
+    });
+
+    // Test ROMs can be run straight from their assembly or Octo source
+    let loaded = load_rom_file(&options.rom_path).unwrap_or_else(|err| {
+        eprintln!("Failed to load ROM: {}", err);
+        process::exit(EXIT_ROM);
+    });
+    let rom = loaded.rom;
+    let open = |path: &Option<String>, default| {
+        RomDatabase::open(path.as_deref(), default).unwrap_or_else(|err| {
+            eprintln!("Failed to load ROM database: {}", err);
+            process::exit(EXIT_DATABASE);
+        })
+    };
+    let database = open(&options.database_path, DEFAULT_DATABASE_PATH);
+    let overrides = open(&options.overrides_path, DEFAULT_OVERRIDES_PATH);
+    let mut settings = options
+        .settings
+        .or(overrides.settings(&rom))
+        .or(loaded.settings)
+        .or(database.settings(&rom));
+    if options.disassemble {
+        print!("{}", ProgramDisassembly::new(&rom).listing());
+        return;
+    }
+    let movie = options.movie_path.as_ref().map(|path| {
+        Movie::load(path).unwrap_or_else(|err| {
+            eprintln!("Failed to load movie: {}", err);
+            process::exit(EXIT_MOVIE);
+        })
+    });
+
+    // A movie brings its own configuration and keypad input
+    let mut chip8 = match &movie {
+        Some(movie) => {
+            options.runner.keys = movie.key_events();
+            settings.clock_hz = Some(movie.cycles_per_timer_tick * TIMER_FREQUENCY);
+            movie.start(&rom).unwrap_or_else(|err| {
+                eprintln!("Failed to play movie: {}", err);
+                process::exit(EXIT_MOVIE);
+            })
+        }
+        None => {
+            let mut chip8 = Chip8MachineState::with_random_source(
+                settings.platform(),
+                settings.quirks(),
+                RandomSource::seeded(options.seed),
+            );
+            chip8
+                .state
+                .load_rom_bytes(&rom, 0x200)
+                .unwrap_or_else(|err| {
+                    eprintln!("Failed to load ROM: {}", err);
+                    process::exit(EXIT_ROM);
+                });
+            chip8
+        }
+    };
+    chip8.cycles_per_timer_tick = Scheduler::new(settings.clock_hz()).instructions_per_frame();
+    if options.runner.max_cycles.is_none() && options.runner.max_frames.is_none() {
+        options.runner.max_frames = Some(match &movie {
+            Some(movie) => movie.frames.len() as u64,
+            None => DEFAULT_FRAMES,
+        });
+    }
+
+    let report = options.runner.run(&mut chip8);
+    println!(
+        "Stopped: {} after {} cycles, {} frames",
+        report.outcome, report.cycles, report.frames
+    );
+    if !options.quiet {
+        println!("{}", format_registers(&chip8));
+        print!("{}", screenshot::ascii(&chip8.display));
+    }
+    if let Some(path) = &options.screenshot_path {
+        let format = options
+            .screenshot_format
+            .or(ScreenshotFormat::from_path(path))
+            .unwrap_or(ScreenshotFormat::Png);
+        let result = screenshot::save_screenshot(
+            &chip8.display,
+            path,
+            format,
+            options.scale,
+            &settings.palette(),
+        );
+        if let Err(err) = result {
+            eprintln!("Failed to save screenshot: {}", err);
+            process::exit(EXIT_SCREENSHOT);
+        }
+    }
+
+    // Only a replay of the whole movie can be checked against its end state
+    let out_of_sync = movie.as_ref().is_some_and(|movie| {
+        report.outcome == RunOutcome::FrameLimit
+            && report.frames == movie.frames.len() as u64
+            && movie.matches_end_state(&chip8) == Some(false)
+    });
+
+    let code = match (&report.outcome, &options.expect) {
+        (RunOutcome::Fault(_), _) => EXIT_CRASH,
+        _ if out_of_sync => {
+            println!("FAIL: replay out of sync with the movie");
+            EXIT_FAIL
+        }
+        (_, Some(expect)) if !expect.is_true(&chip8) => {
+            println!("FAIL: {expect}");
+            EXIT_FAIL
+        }
+        (_, Some(expect)) => {
+            println!("PASS: {expect}");
+            EXIT_PASS
+        }
+        (_, None) => EXIT_PASS,
+    };
+    process::exit(code);
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut settings = RomSettings::default();
+    let mut seed = 0;
+    let mut runner = Runner::new();
+    let mut expect = None;
+    let mut quiet = false;
+    let mut screenshot_path = None;
+    let mut screenshot_format = None;
+    let mut scale = 1;
+    let mut movie_path = None;
+    let mut database_path = None;
+    let mut overrides_path = None;
+    let mut disassemble = false;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or(format!("Missing value for {arg}"));
+        let invalid = |value: &str| format!("Invalid value for {arg}: {value}");
+
+        match arg.as_str() {
+            "--platform" => {
+                let name = value()?;
+                settings.platform =
+                    Some(Platform::from_name(name).ok_or(format!("Unknown platform: {name}"))?);
+            }
+            "--quirks" => {
+                let name = value()?;
+                settings.quirks =
+                    Some(Quirks::from_name(name).ok_or(format!("Unknown quirks preset: {name}"))?);
+            }
+            "--clock" => {
+                let text = value()?;
+                settings.clock_hz = Some(text.parse().map_err(|_| invalid(text))?);
+            }
+            "--seed" => {
+                let text = value()?;
+                seed = text.parse().map_err(|_| invalid(text))?;
+            }
+            "--cycles" => {
+                let text = value()?;
+                runner.max_cycles = Some(text.parse().map_err(|_| invalid(text))?);
+            }
+            "--frames" => {
+                let text = value()?;
+                runner.max_frames = Some(text.parse().map_err(|_| invalid(text))?);
+            }
+            "--until-self-jump" => runner.stop_on_self_jump = true,
+            "--until-pc" => {
+                let text = value()?;
+                runner.stop_addresses.insert(parse_address(text)?);
+            }
+            "--keys" => runner.keys = parse_key_script(value()?)?,
+            "--keys-file" => {
+                let path = value()?;
+                let text = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+                runner.keys = parse_key_script(&text)?;
+            }
+            "--expect" => expect = Some(Expression::parse(value()?)?),
+            "--quiet" => quiet = true,
+            "--screenshot" => screenshot_path = Some(value()?.clone()),
+            "--screenshot-format" => {
+                let name = value()?;
+                screenshot_format = Some(ScreenshotFormat::from_name(name).ok_or(invalid(name))?);
+            }
+            "--scale" => {
+                let text = value()?;
+                scale = text
+                    .parse()
+                    .ok()
+                    .filter(|&scale| scale > 0)
+                    .ok_or(invalid(text))?;
+            }
+            "--palette" => settings.palette = Some(parse_palette(value()?)?),
+            "--movie" => movie_path = Some(value()?.clone()),
+            "--database" => database_path = Some(value()?.clone()),
+            "--overrides" => overrides_path = Some(value()?.clone()),
+            "--disassemble" => disassemble = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {arg}")),
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {arg}")),
+        }
+    }
+
+    Ok(Options {
+        rom_path: rom_path.ok_or("Missing ROM path")?,
+        settings,
+        seed,
+        runner,
+        expect,
+        quiet,
+        screenshot_path,
+        screenshot_format,
+        scale,
+        movie_path,
+        database_path,
+        overrides_path,
+        disassemble,
+    })
+}
+
+fn print_usage(program: &str) {
+    eprintln!("Usage: {} [options] <rom_path>", program);
+    eprintln!("A ROM path ending in .asm is assembled first, one ending in .8o compiled as Octo.");
+    eprintln!("Octo cartridge GIFs, Intel HEX, hex dumps (.hex) and zipped ROMs load too.");
+    eprintln!("Options:");
+    eprintln!("  --platform <name>     chip8, schip or xochip (default chip8)");
+    eprintln!(
+        "  --quirks <preset>     vip, chip48, schip, xochip or modern (default per platform:"
+    );
+    eprintln!("                        modern for chip8, schip for schip, xochip for xochip)");
+    eprintln!("  --clock <hz>          CPU instructions per second (default {DEFAULT_CLOCK_HZ})");
+    eprintln!("  --seed <n>            Seed for the random source (default 0)");
+    eprintln!("  --cycles <n>          Stop after n instructions");
+    eprintln!(
+        "  --frames <n>          Stop after n frames (default {DEFAULT_FRAMES} without --cycles)"
+    );
+    eprintln!("  --until-self-jump     Stop at a jump to its own address");
+    eprintln!("  --until-pc <addr>     Stop before executing at a hex address (repeatable)");
+    eprintln!(
+        "  --keys <script>       Key events such as \"30:5+ 45:5-\" (frame:key, + press, - release)"
+    );
+    eprintln!("  --keys-file <path>    Read key events from a file, # starts a comment");
+    eprintln!(
+        "  --expect <expr>       Pass only if the expression is true at the end, e.g. \"V0 == 1\""
+    );
+    eprintln!("  --quiet               Do not print registers and screen");
+    eprintln!("  --screenshot <path>   Save the final screen, format from the extension");
+    eprintln!("  --screenshot-format <png|ppm|ascii|unicode>");
+    eprintln!("                        Screenshot format when the extension does not tell");
+    eprintln!("  --scale <n>           Screenshot pixel size (default 1)");
+    eprintln!("  --palette <rgb,...>   Hex colours for off, plane 1, plane 2 and both planes");
+    eprintln!("  --movie <path>        Replay a recorded movie and check it stays in sync");
+    eprintln!("  --database <path>     chip-8-database programs.json to look ROMs up in by SHA-1");
+    eprintln!("                        (default {DEFAULT_DATABASE_PATH} if present)");
+    eprintln!("  --overrides <path>    Your own ROM settings, over the database's");
+    eprintln!("                        (default {DEFAULT_OVERRIDES_PATH} if present)");
+    eprintln!("  --disassemble         Print a listing of the ROM instead of running it");
+    eprintln!("Exit codes:");
+    eprintln!("  0 pass, 1 bad options, 2 ROM not loaded, 3 expectation failed, 4 CHIP-8 crashed,");
+    eprintln!("  5 screenshot not saved, 6 movie not loaded, 7 ROM database not loaded");
+}
@@ -0,0 +1,66 @@
+//! CHIP-8 emulator core: CPU, memory, display buffer, keypad and disassembler.
+//!
+//! The core has no windowing dependencies so it can be embedded in tools and
+//! run headless. The `minifb` and terminal frontends live in the binary crate.
+
+pub mod assembler;
+pub mod audio;
+pub mod chip8_machine;
+pub mod chip8_state;
+pub mod debugger;
+pub mod disassembly;
+pub mod error;
+pub mod expression;
+pub mod framebuffer;
+pub mod movie;
+pub mod octo;
+pub mod platform;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod rom_database;
+pub mod rom_file;
+pub mod runner;
+pub mod save_state;
+pub mod scheduler;
+pub mod screenshot;
+pub mod trace;
+pub mod tui;
+pub mod watchpoint;
+
+// tests.rs keeps its own `mod tests` wrapper from before the library split
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests;
+
+pub use assembler::{AssemblyError, assemble, assemble_file};
+pub use audio::{AudioConfig, AudioSink, Beeper, NullSink, WavSink, Waveform};
+pub use chip8_machine::{
+    Chip8MachineState, DEFAULT_CYCLES_PER_TIMER_TICK, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH,
+    KEYBOARD_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH, TIMER_FREQUENCY,
+};
+pub use chip8_state::{
+    AUDIO_PATTERN_SIZE, BIG_FONT_ADDRESS, BIG_FONT_GLYPH_SIZE, BIG_FONT_SIZE, Chip8State,
+    DEFAULT_BIG_FONT, DEFAULT_FONT, DEFAULT_PITCH, FONT_ADDRESS, FONT_GLYPH_SIZE, FONT_SIZE,
+    MEMORY_SIZE, RPL_SIZE, STACK_SIZE, V_SIZE, XO_CHIP_MEMORY_SIZE,
+};
+pub use debugger::{Breakpoint, Debugger, RunTarget, StopReason};
+pub use disassembly::{DisassemblyOutput, ProgramDisassembly, decrypt_chip8_instruction};
+pub use error::Chip8Error;
+pub use expression::Expression;
+pub use framebuffer::FrameBuffer;
+pub use movie::{MOVIE_MAGIC, MOVIE_VERSION, Movie, MovieError};
+pub use octo::{OctoProgram, SourceMap};
+pub use platform::Platform;
+pub use quirks::{MemoryIncrement, Quirks};
+pub use random::RandomSource;
+pub use rewind::{DEFAULT_REWIND_DEPTH, DEFAULT_REWIND_INTERVAL, Rewind};
+pub use rom_database::{DatabaseError, RomDatabase, RomEntry};
+pub use rom_file::{LoadedRom, RomFileError, RomSettings, decode_rom, load_rom_file};
+pub use runner::{KeyEvent, RunOutcome, RunReport, Runner};
+pub use save_state::{SAVE_STATE_MAGIC, SAVE_STATE_VERSION, SaveStateError};
+pub use scheduler::{Scheduler, SpeedMode};
+pub use screenshot::{DEFAULT_PALETTE, Palette, ScreenshotFormat, save_screenshot};
+pub use trace::{TraceFormat, TraceRecord, Tracer};
+pub use tui::{DEFAULT_KEY_HOLD_FRAMES, KeyHold, THEMES};
+pub use watchpoint::{Access, Register, WatchEvent, WatchHit, Watchpoint};
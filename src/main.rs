@@ -1,43 +1,349 @@
-mod chip8_machine;
-mod chip8_state;
-mod disassembly;
+mod debug;
+#[cfg(feature = "minifb")]
 mod display;
-pub mod tests;
+mod frontend;
+mod options;
+#[cfg(feature = "tui")]
+mod terminal;
 
-use chip8_machine::Chip8MachineState;
-use display::Display;
+use chip8::audio::{AudioConfig, AudioSink, Beeper, NullSink, WavSink};
+use chip8::rom_database::{DEFAULT_DATABASE_PATH, DEFAULT_OVERRIDES_PATH};
+use chip8::scheduler::{Scheduler, SpeedMode};
+use chip8::{
+    Chip8MachineState, Movie, Rewind, RomDatabase, TIMER_FREQUENCY, Tracer, load_rom_file,
+    save_screenshot,
+};
+use frontend::{Frontend, Hotkey};
+use options::{AudioOutput, FrontendKind, Options};
+use std::fs::File;
+use std::io::{self, BufWriter};
 use std::{env, process};
 
+enum MovieSession {
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <rom_path>", args[0]);
-        eprintln!("Available ROMs in res/ folder:");
-        eprintln!("  - 15puzzle.rom");
-        eprintln!("  - blitz.rom");
-        eprintln!("  - breakout.rom");
-        eprintln!("  - brix.rom");
-        eprintln!("  - connect4.rom");
-        eprintln!("  - guess.rom");
-        eprintln!("  - invaders.rom");
-        eprintln!("  - maze.rom");
-        eprintln!("  - merlin.rom");
-        eprintln!("  - missile.rom");
-        eprintln!("  - pong.rom");
-        eprintln!("  - tetris.rom");
+    let options = Options::parse(&args).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        Options::print_usage(&args[0]);
         process::exit(1);
-    }
+    });
 
-    let mut chip8 = Chip8MachineState::new();
-    chip8.state.load_rom(&args[1], 0x200).unwrap_or_else(|err| {
+    // Sources are compiled and containers unpacked here. Settings from the
+    // command line win over your overrides, then those a cartridge brings,
+    // then the ROM database's.
+    let loaded = load_rom_file(&options.rom_path).unwrap_or_else(|err| {
         eprintln!("Failed to load ROM: {}", err);
         process::exit(2);
     });
-    let mut display = Display::new("CHIP-8 Emulator");
+    let rom = loaded.rom;
+    let open = |path: &Option<String>, default| {
+        RomDatabase::open(path.as_deref(), default).unwrap_or_else(|err| {
+            eprintln!("Failed to load ROM database: {}", err);
+            process::exit(6);
+        })
+    };
+    let database = open(&options.database_path, DEFAULT_DATABASE_PATH);
+    let overrides = open(&options.overrides_path, DEFAULT_OVERRIDES_PATH);
+    if let Some(title) = database.lookup(&rom).and_then(|entry| entry.title.as_ref()) {
+        eprintln!("Found {} in the ROM database", title);
+    }
+    let settings = options
+        .settings
+        .or(overrides.settings(&rom))
+        .or(loaded.settings)
+        .or(database.settings(&rom));
+    let replay = options.play_path.as_ref().map(|path| {
+        Movie::load(path).unwrap_or_else(|err| {
+            eprintln!("Failed to load movie: {}", err);
+            process::exit(5);
+        })
+    });
+
+    let mut chip8 = match &replay {
+        Some(movie) => movie.start(&rom).unwrap_or_else(|err| {
+            eprintln!("Failed to play movie: {}", err);
+            process::exit(5);
+        }),
+        None => Chip8MachineState::with_random_source(
+            settings.platform(),
+            settings.quirks(),
+            options.random.clone(),
+        ),
+    };
+    if replay.is_none() {
+        chip8
+            .state
+            .load_rom_bytes(&rom, 0x200)
+            .unwrap_or_else(|err| {
+                eprintln!("Failed to load ROM: {}", err);
+                process::exit(2);
+            });
+    }
+    chip8.tracer = create_tracer(&options).unwrap_or_else(|err| {
+        eprintln!("Failed to create trace file: {}", err);
+        process::exit(4);
+    });
+    let mut beeper = create_beeper(&options).unwrap_or_else(|err| {
+        eprintln!("Failed to open audio output: {}", err);
+        process::exit(3);
+    });
+    let mut display = create_frontend(&options).unwrap_or_else(|err| {
+        eprintln!("Failed to open the frontend: {}", err);
+        process::exit(7);
+    });
+    display.set_palette(settings.palette());
+
+    // A movie replays at the speed it was recorded at
+    let clock_hz = match &replay {
+        Some(movie) => movie.cycles_per_timer_tick * TIMER_FREQUENCY,
+        None => settings.clock_hz(),
+    };
+    let mut scheduler = Scheduler::new(clock_hz);
+    scheduler.fast_forward_factor = options.fast_forward_factor;
+    scheduler.slow_motion_divisor = options.slow_motion_divisor;
+    if options.start_paused {
+        scheduler.set_mode(SpeedMode::Paused);
+    }
+
+    chip8.cycles_per_timer_tick = scheduler.instructions_per_frame();
+    let mut movie = match replay {
+        Some(movie) => Some(MovieSession::Playing { movie, frame: 0 }),
+        None if options.record_path.is_some() => {
+            Some(MovieSession::Recording(Movie::record(&chip8, &rom)))
+        }
+        None => None,
+    };
+
+    if options.debug {
+        debug::run(&mut chip8, display.as_mut(), &scheduler, loaded.source_map);
+    } else {
+        run(
+            &mut chip8,
+            display.as_mut(),
+            &mut scheduler,
+            &mut beeper,
+            &mut movie,
+            &options,
+        );
+    }
+    // Gives the terminal back before printing anything else
+    drop(display);
+
+    if let (Some(MovieSession::Recording(mut movie)), Some(path)) = (movie, &options.record_path) {
+        movie.finish(&chip8);
+        match movie.save(path) {
+            Ok(()) => eprintln!("Saved {} frames of input to {}", movie.frames.len(), path),
+            Err(err) => eprintln!("Failed to save movie: {}", err),
+        }
+    }
 
-    // Main loop
-    while display.update(&mut chip8) {
-        chip8.execute_cycle();
-        // Add any additional logic here (input handling, etc.)
+    if let Some(Err(err)) = chip8.tracer.take().map(Tracer::finish) {
+        eprintln!("Failed to write trace: {}", err);
     }
 }
+
+fn run(
+    chip8: &mut Chip8MachineState,
+    display: &mut dyn Frontend,
+    scheduler: &mut Scheduler,
+    beeper: &mut Beeper,
+    movie: &mut Option<MovieSession>,
+    options: &Options,
+) {
+    let mut rewind = Rewind::new(options.rewind_depth, options.rewind_interval);
+
+    // Main loop: the display paces frames at ~60 fps, the scheduler runs the CPU
+    while display.update(chip8) {
+        for hotkey in display.hotkeys() {
+            match hotkey {
+                Hotkey::TogglePause => scheduler.toggle_mode(SpeedMode::Paused),
+                Hotkey::ToggleSlowMotion => scheduler.toggle_mode(SpeedMode::SlowMotion),
+                Hotkey::Screenshot => {
+                    let format = options.screenshot_format;
+                    let path = format!(
+                        "{}-{}.{}",
+                        options.rom_path,
+                        chip8.cycles,
+                        format.extension()
+                    );
+                    match save_screenshot(
+                        &chip8.display,
+                        &path,
+                        format,
+                        options.screenshot_scale,
+                        &display.palette(),
+                    ) {
+                        Ok(()) => display.show_message(&format!("Saved screenshot to {path}")),
+                        Err(err) => {
+                            display.show_message(&format!("Failed to save screenshot: {err}"))
+                        }
+                    }
+                }
+                Hotkey::SaveState => {
+                    let message = match chip8.save_state_file(&options.state_path) {
+                        Ok(()) => format!("Saved state to {}", options.state_path),
+                        Err(err) => format!("Failed to save state: {err}"),
+                    };
+                    display.show_message(&message);
+                }
+                Hotkey::LoadState if movie.is_some() => display
+                    .show_message("Loading a state is disabled while recording or playing a movie"),
+                Hotkey::LoadState => {
+                    let message = match chip8.load_state_file(&options.state_path) {
+                        Ok(()) => format!("Loaded state from {}", options.state_path),
+                        Err(err) => format!("Failed to load state: {err}"),
+                    };
+                    display.show_message(&message);
+                }
+                Hotkey::Break => {}
+            }
+        }
+
+        match (display.fast_forward_held(), scheduler.mode) {
+            (true, SpeedMode::Normal) => scheduler.set_mode(SpeedMode::FastForward),
+            (false, SpeedMode::FastForward) => scheduler.set_mode(SpeedMode::Normal),
+            _ => {}
+        }
+
+        // Rewinding would desync a movie
+        let (sound_active, ticks) = if display.rewind_held() && movie.is_none() {
+            // Step back one snapshot per frame, silently, while the key is held
+            rewind.step_back(chip8);
+            (false, 1)
+        } else {
+            rewind.record(chip8);
+            let sound_active = chip8.state.sound_timer > 0;
+            let mut movie_message = None;
+            let result = scheduler.run_frame_with(chip8, |chip8| {
+                movie_message = movie_message.or(movie_tick(movie, chip8));
+            });
+            if let Some(message) = movie_message {
+                display.show_message(message);
+            }
+            let ticks = match result {
+                Ok(ticks) => ticks,
+                Err(err) => {
+                    // Freeze on the faulting instruction instead of exiting
+                    display.show_message(&format!(
+                        "CHIP-8 crashed after {} cycles: {err}",
+                        chip8.cycles
+                    ));
+                    display.set_title(&format!("CHIP-8 Emulator - crashed: {err}"));
+                    scheduler.set_mode(SpeedMode::Paused);
+                    0
+                }
+            };
+            (sound_active, ticks)
+        };
+        beeper.set_pattern(chip8.state.audio_pattern, chip8.state.pitch);
+        if let Err(err) = beeper.update(sound_active, ticks) {
+            display.show_message(&format!("Audio output failed: {err}"));
+            *beeper = Beeper::new(beeper.config, Box::new(NullSink::default()));
+        }
+
+        // SUPER-CHIP 00FD exits the interpreter
+        if chip8.halted {
+            break;
+        }
+    }
+}
+
+// Records or replays the keypad before each emulated frame. Returns a message
+// for the user once a replay is over.
+fn movie_tick(
+    session: &mut Option<MovieSession>,
+    chip8: &mut Chip8MachineState,
+) -> Option<&'static str> {
+    match session {
+        Some(MovieSession::Recording(movie)) => {
+            movie.record_frame(&chip8.keyboard);
+            None
+        }
+        Some(MovieSession::Playing { movie, frame }) => {
+            if movie.play_frame(*frame, chip8) {
+                *frame += 1;
+                return None;
+            }
+
+            // Past the last frame the keypad is live again
+            let message = match movie.matches_end_state(chip8) {
+                Some(true) => "Movie finished, replay in sync",
+                Some(false) => "Movie finished, replay out of sync",
+                None => "Movie finished",
+            };
+            *session = None;
+            Some(message)
+        }
+        None => None,
+    }
+}
+
+fn create_frontend(options: &Options) -> Result<Box<dyn Frontend>, String> {
+    match options.frontend {
+        #[cfg(feature = "minifb")]
+        FrontendKind::Window => Ok(Box::new(display::Display::new("CHIP-8 Emulator"))),
+        #[cfg(feature = "tui")]
+        FrontendKind::Terminal => {
+            let terminal = terminal::Terminal::new("CHIP-8 Emulator", options.key_hold_frames)
+                .map_err(|err| err.to_string())?;
+            Ok(Box::new(terminal))
+        }
+        #[cfg(not(feature = "tui"))]
+        FrontendKind::Terminal => Err("built without the `tui` feature".to_string()),
+    }
+}
+
+fn create_tracer(options: &Options) -> io::Result<Option<Tracer>> {
+    let Some(path) = &options.trace_path else {
+        return Ok(None);
+    };
+
+    let mut tracer = match options.trace_last {
+        Some(capacity) => Tracer::ring_buffer(
+            Box::new(BufWriter::new(File::create(path)?)),
+            options.trace_format,
+            capacity,
+        ),
+        None => Tracer::create(path, options.trace_format)?,
+    };
+    tracer.addresses = options.trace_addresses.clone();
+    tracer.cycles = options.trace_cycles.clone();
+    Ok(Some(tracer))
+}
+
+fn create_beeper(options: &Options) -> Result<Beeper, String> {
+    let config = AudioConfig {
+        frequency: options.tone_frequency as f32,
+        volume: options.volume_percent as f32 / 100.0,
+        waveform: options.waveform,
+        ..AudioConfig::default()
+    };
+
+    let (sink, sample_rate): (Box<dyn AudioSink>, u32) = match &options.audio_output {
+        AudioOutput::None => (Box::new(NullSink::default()), config.sample_rate),
+        AudioOutput::Wav(path) => {
+            let sink = WavSink::create(path, config.sample_rate).map_err(|err| err.to_string())?;
+            (Box::new(sink), config.sample_rate)
+        }
+        #[cfg(feature = "cpal")]
+        AudioOutput::Device => {
+            let sink = chip8::audio::CpalSink::open()?;
+            let sample_rate = sink.sample_rate();
+            (Box::new(sink), sample_rate)
+        }
+        #[cfg(not(feature = "cpal"))]
+        AudioOutput::Device => return Err("built without the `cpal` feature".to_string()),
+    };
+
+    Ok(Beeper::new(
+        AudioConfig {
+            sample_rate,
+            ..config
+        },
+        sink,
+    ))
+}
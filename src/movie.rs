@@ -0,0 +1,234 @@
+use crate::chip8_machine::{Chip8MachineState, KEYBOARD_SIZE};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::random::RandomSource;
+use crate::runner::KeyEvent;
+use crate::save_state::{
+    Reader, SaveStateError, Writer, platform_from_u8, platform_to_u8, read_quirks, read_random,
+    write_quirks, write_random,
+};
+use sha1_smol::Sha1;
+use std::{fmt, fs, io, path::Path};
+
+pub const MOVIE_MAGIC: &[u8; 4] = b"C8MV";
+pub const MOVIE_VERSION: u16 = 1;
+const ROM_ADDRESS: usize = 0x200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    Invalid(&'static str),
+    // The ROM is not the one the movie was recorded with.
+    RomMismatch,
+    RomTooLarge,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "Not a CHIP-8 movie"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported movie version {version}")
+            }
+            MovieError::Truncated => write!(f, "Movie is truncated"),
+            MovieError::Invalid(what) => write!(f, "Invalid movie: {what}"),
+            MovieError::RomMismatch => write!(f, "Movie was recorded with a different ROM"),
+            MovieError::RomTooLarge => write!(f, "ROM file too large for CHIP-8 memory"),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<SaveStateError> for MovieError {
+    fn from(err: SaveStateError) -> Self {
+        match err {
+            SaveStateError::BadMagic => MovieError::BadMagic,
+            SaveStateError::UnsupportedVersion(version) => MovieError::UnsupportedVersion(version),
+            SaveStateError::Truncated => MovieError::Truncated,
+            SaveStateError::Invalid(what) => MovieError::Invalid(what),
+        }
+    }
+}
+
+impl From<MovieError> for io::Error {
+    fn from(err: MovieError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+// Bit n is set when key n is down.
+pub fn keypad_mask(keyboard: &[bool; KEYBOARD_SIZE]) -> u16 {
+    keyboard
+        .iter()
+        .enumerate()
+        .fold(0, |mask, (key, &pressed)| mask | ((pressed as u16) << key))
+}
+
+pub fn sha1(bytes: &[u8]) -> [u8; 20] {
+    Sha1::from(bytes).digest().bytes()
+}
+
+// The keypad is left out, as keys held when the movie ends are live input by
+// then and not part of the replay.
+fn end_state_sha1(chip8: &Chip8MachineState) -> [u8; 20] {
+    sha1(&chip8.save_state_with_keypad(&[false; KEYBOARD_SIZE]))
+}
+
+// The keypad for every emulated frame of a session, with everything else it
+// needs to replay exactly: the configuration, the random source as it was at
+// the start (an OS-seeded generator is captured too) and the ROM's SHA-1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub cycles_per_timer_tick: u32,
+    pub random: RandomSource,
+    pub rom_sha1: [u8; 20],
+    pub frames: Vec<u16>,
+    // SHA-1 of the save state when recording ended, to check a replay.
+    pub end_state_sha1: Option<[u8; 20]>,
+}
+
+impl Movie {
+    // Starts recording a machine that has loaded `rom` and not run yet.
+    pub fn record(chip8: &Chip8MachineState, rom: &[u8]) -> Self {
+        Self {
+            platform: chip8.platform,
+            quirks: chip8.quirks,
+            cycles_per_timer_tick: chip8.cycles_per_timer_tick,
+            random: chip8.random.clone(),
+            rom_sha1: sha1(rom),
+            frames: Vec::new(),
+            end_state_sha1: None,
+        }
+    }
+
+    // Called before every emulated frame while recording.
+    pub fn record_frame(&mut self, keyboard: &[bool; KEYBOARD_SIZE]) {
+        self.frames.push(keypad_mask(keyboard));
+    }
+
+    pub fn finish(&mut self, chip8: &Chip8MachineState) {
+        self.end_state_sha1 = Some(end_state_sha1(chip8));
+    }
+
+    // A fresh machine in the recorded configuration, with `rom` loaded.
+    pub fn start(&self, rom: &[u8]) -> Result<Chip8MachineState, MovieError> {
+        if sha1(rom) != self.rom_sha1 {
+            return Err(MovieError::RomMismatch);
+        }
+
+        let mut chip8 =
+            Chip8MachineState::with_random_source(self.platform, self.quirks, self.random.clone());
+        chip8.cycles_per_timer_tick = self.cycles_per_timer_tick;
+        chip8
+            .state
+            .load_rom_bytes(rom, ROM_ADDRESS)
+            .map_err(|_| MovieError::RomTooLarge)?;
+        Ok(chip8)
+    }
+
+    // Sets the keypad for `frame`. Returns false past the end of the movie.
+    pub fn play_frame(&self, frame: usize, chip8: &mut Chip8MachineState) -> bool {
+        let Some(&mask) = self.frames.get(frame) else {
+            return false;
+        };
+
+        for key in 0..KEYBOARD_SIZE {
+            chip8.set_key(key, mask & (1 << key) != 0);
+        }
+        true
+    }
+
+    // Whether a replay that played every frame ended where the recording did.
+    // None if the movie has no end state.
+    pub fn matches_end_state(&self, chip8: &Chip8MachineState) -> Option<bool> {
+        self.end_state_sha1
+            .map(|end_state| end_state == end_state_sha1(chip8))
+    }
+
+    // The keypad changes as scripted key events for the headless runner.
+    pub fn key_events(&self) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        let mut previous = 0;
+
+        for (frame, &mask) in self.frames.iter().enumerate() {
+            for key in (0..KEYBOARD_SIZE).filter(|key| (mask ^ previous) & (1 << key) != 0) {
+                events.push(KeyEvent {
+                    frame: frame as u64,
+                    key,
+                    pressed: mask & (1 << key) != 0,
+                });
+            }
+            previous = mask;
+        }
+
+        events
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer { bytes: Vec::new() };
+
+        writer.bytes(MOVIE_MAGIC);
+        writer.u16(MOVIE_VERSION);
+        writer.u8(platform_to_u8(self.platform));
+        write_quirks(&mut writer, &self.quirks);
+        writer.u32(self.cycles_per_timer_tick);
+        write_random(&mut writer, &self.random);
+        writer.bytes(&self.rom_sha1);
+        writer.bool(self.end_state_sha1.is_some());
+        writer.bytes(&self.end_state_sha1.unwrap_or_default());
+
+        writer.u32(self.frames.len() as u32);
+        self.frames.iter().for_each(|&mask| writer.u16(mask));
+
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieError> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(MOVIE_MAGIC.len())? != MOVIE_MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let platform = platform_from_u8(reader.u8()?)?;
+        let quirks = read_quirks(&mut reader)?;
+        let cycles_per_timer_tick = reader.u32()?;
+        let random = read_random(&mut reader)?;
+        let rom_sha1 = reader.array()?;
+        let has_end_state = reader.bool()?;
+        let end_state_sha1 = reader.array()?;
+
+        let len = reader.u32()? as usize;
+        if reader.bytes.len() - reader.position != len * 2 {
+            return Err(MovieError::Invalid("frame count"));
+        }
+        let frames = (0..len).map(|_| reader.u16()).collect::<Result<_, _>>()?;
+
+        Ok(Movie {
+            platform,
+            quirks,
+            cycles_per_timer_tick,
+            random,
+            rom_sha1,
+            frames,
+            end_state_sha1: has_end_state.then_some(end_state_sha1),
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Movie> {
+        Ok(Movie::from_bytes(&fs::read(path)?)?)
+    }
+}
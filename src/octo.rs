@@ -0,0 +1,994 @@
+use crate::assembler::AssemblyError;
+use crate::disassembly::PROGRAM_START;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+// Macros nested deeper than this are assumed to expand themselves.
+const MAX_MACRO_DEPTH: usize = 64;
+// Highest address of the 64K XO-CHIP memory.
+const MAX_ADDRESS: usize = 0xFFFF;
+const UNARY_OPERATORS: [&str; 13] = [
+    "-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor",
+];
+
+// Maps code addresses back to the source lines they were compiled from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    lines: BTreeMap<u16, usize>,
+    end: usize,
+}
+
+impl SourceMap {
+    // The line that emitted the byte at `address`.
+    pub fn line(&self, address: u16) -> Option<usize> {
+        if address as usize >= self.end {
+            return None;
+        }
+        self.lines
+            .range(..=address)
+            .next_back()
+            .map(|(_, &line)| line)
+    }
+
+    // The first address emitted by `line`.
+    pub fn address(&self, line: usize) -> Option<u16> {
+        self.lines
+            .iter()
+            .find(|&(_, &mapped)| mapped == line)
+            .map(|(&address, _)| address)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OctoProgram {
+    // Loaded at 0x200.
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+    pub source_map: SourceMap,
+}
+
+// Compiles Octo source, the language most modern CHIP-8 homebrew is written
+// in. Supports labels, `:alias`, `:const`, `:calc`, `:macro`, `:byte`, `:org`,
+// `:call`, `:next`, `:unpack`, `if ... then`, `if ... begin ... else ... end`,
+// `loop ... while ... again` and the CHIP-8, SUPER-CHIP and XO-CHIP
+// statements. Execution starts at the `main` label.
+pub fn compile(source: &str) -> Result<OctoProgram, AssemblyError> {
+    Compiler::new(source).compile()
+}
+
+pub fn compile_file<P: AsRef<Path>>(path: P) -> Result<OctoProgram, AssemblyError> {
+    let path = path.as_ref();
+    let with_path = |err: AssemblyError| AssemblyError {
+        path: Some(path.to_path_buf()),
+        ..err
+    };
+
+    let source = fs::read_to_string(path).map_err(|err| {
+        with_path(AssemblyError {
+            path: None,
+            line: 0,
+            message: err.to_string(),
+        })
+    })?;
+    compile(&source).map_err(with_path)
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    // How many macro expansions produced it.
+    depth: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Patch {
+    // The low 12 bits of the opcode
+    Address,
+    // The word after F000
+    Long,
+    // The two `:unpack` loads, with the high nibble of the first one
+    Unpack(u16),
+}
+
+#[derive(Debug, Clone)]
+struct Fixup {
+    offset: usize,
+    name: String,
+    patch: Patch,
+    line: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Block {
+    // The jump taken when the condition is false.
+    If {
+        jump: usize,
+        line: usize,
+    },
+    // The jump over the else branch.
+    Else {
+        jump: usize,
+        line: usize,
+    },
+    Loop {
+        start: usize,
+        breaks: Vec<usize>,
+        line: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Register(u16),
+    Byte(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Condition {
+    Equal(u16, u16),
+    NotEqual(u16, u16),
+    EqualRegister(u16, u16),
+    NotEqualRegister(u16, u16),
+    Key(u16),
+    NotKey(u16),
+}
+
+impl Condition {
+    fn negated(self) -> Condition {
+        match self {
+            Condition::Equal(x, n) => Condition::NotEqual(x, n),
+            Condition::NotEqual(x, n) => Condition::Equal(x, n),
+            Condition::EqualRegister(x, y) => Condition::NotEqualRegister(x, y),
+            Condition::NotEqualRegister(x, y) => Condition::EqualRegister(x, y),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+
+    // The instruction that skips the next one when the condition holds.
+    fn skip_opcode(self) -> u16 {
+        match self {
+            Condition::Equal(x, n) => 0x3000 | x << 8 | n,
+            Condition::NotEqual(x, n) => 0x4000 | x << 8 | n,
+            Condition::EqualRegister(x, y) => 0x5000 | x << 8 | y << 4,
+            Condition::NotEqualRegister(x, y) => 0x9000 | x << 8 | y << 4,
+            Condition::Key(x) => 0xE09E | x << 8,
+            Condition::NotKey(x) => 0xE0A1 | x << 8,
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(number, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace().map(move |text| Token {
+                text: text.to_string(),
+                line: number + 1,
+                depth: 0,
+            })
+        })
+        .collect()
+}
+
+// Decimal (possibly fractional), 0x and 0b numbers, optionally negative.
+fn parse_number(text: &str) -> Option<f64> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(sign * value)
+}
+
+fn register_number(text: &str) -> Option<u16> {
+    match text.as_bytes() {
+        [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|x| x as u16),
+        _ => None,
+    }
+}
+
+fn unary(operator: &str, value: f64) -> f64 {
+    match operator {
+        "-" => -value,
+        "~" => !(value as i64) as f64,
+        "!" => (value == 0.0) as u8 as f64,
+        "sin" => value.sin(),
+        "cos" => value.cos(),
+        "tan" => value.tan(),
+        "exp" => value.exp(),
+        "log" => value.ln(),
+        "abs" => value.abs(),
+        "sqrt" => value.sqrt(),
+        "sign" => value.signum(),
+        "ceil" => value.ceil(),
+        "floor" => value.floor(),
+        _ => unreachable!("not a unary operator: {operator}"),
+    }
+}
+
+fn binary(operator: &str, left: f64, right: f64) -> Option<f64> {
+    let integers = (left as i64, right as i64);
+    let result = match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "&" => (integers.0 & integers.1) as f64,
+        "|" => (integers.0 | integers.1) as f64,
+        "^" => (integers.0 ^ integers.1) as f64,
+        "<<" => integers.0.checked_shl(integers.1 as u32).unwrap_or(0) as f64,
+        ">>" => integers.0.checked_shr(integers.1 as u32).unwrap_or(0) as f64,
+        "<" => (left < right) as u8 as f64,
+        "<=" => (left <= right) as u8 as f64,
+        "==" => (left == right) as u8 as f64,
+        "!=" => (left != right) as u8 as f64,
+        ">=" => (left >= right) as u8 as f64,
+        ">" => (left > right) as u8 as f64,
+        _ => return None,
+    };
+    Some(result)
+}
+
+struct Compiler {
+    // Reversed, so the next token is popped off the end.
+    tokens: Vec<Token>,
+    // Line of the statement being compiled.
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    // Whether 0x200 holds the jump to `main`.
+    jump_to_main: bool,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u16>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    source_map: BTreeMap<u16, usize>,
+    // Line to map at the next emitted byte.
+    unmapped_line: Option<usize>,
+}
+
+impl Compiler {
+    fn new(source: &str) -> Self {
+        let mut tokens = tokenize(source);
+        tokens.reverse();
+
+        Self {
+            tokens,
+            line: 1,
+            // Reserved for the jump to main
+            rom: vec![0x10, 0x00],
+            here: PROGRAM_START + 2,
+            jump_to_main: true,
+            labels: BTreeMap::new(),
+            constants: HashMap::new(),
+            // The registers `:unpack` loads
+            aliases: HashMap::from([("unpack-hi".to_string(), 0), ("unpack-lo".to_string(), 1)]),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            source_map: BTreeMap::new(),
+            unmapped_line: None,
+        }
+    }
+
+    fn compile(mut self) -> Result<OctoProgram, AssemblyError> {
+        while let Some(token) = self.tokens.pop() {
+            self.line = token.line;
+            self.unmapped_line = Some(token.line);
+            self.statement(token)?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let (line, missing) = match block {
+                Block::If { line, .. } | Block::Else { line, .. } => (*line, "end"),
+                Block::Loop { line, .. } => (*line, "again"),
+            };
+            return Err(self.error_at(line, format!("Missing {missing}")));
+        }
+
+        let main = *self
+            .labels
+            .get("main")
+            .ok_or_else(|| self.error("Missing main label".to_string()))?;
+        if self.jump_to_main {
+            self.patch_address(0, main as usize)?;
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = *self.labels.get(&fixup.name).ok_or_else(|| {
+                self.error_at(fixup.line, format!("Undefined name: {}", fixup.name))
+            })?;
+            self.line = fixup.line;
+            self.apply(fixup.offset, fixup.patch, address as usize)?;
+        }
+
+        let end = PROGRAM_START + self.rom.len();
+        Ok(OctoProgram {
+            rom: self.rom,
+            labels: self.labels,
+            source_map: SourceMap {
+                lines: self.source_map,
+                end,
+            },
+        })
+    }
+
+    fn error(&self, message: String) -> AssemblyError {
+        self.error_at(self.line, message)
+    }
+
+    fn error_at(&self, line: usize, message: String) -> AssemblyError {
+        AssemblyError {
+            path: None,
+            line,
+            message,
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, AssemblyError> {
+        self.tokens
+            .pop()
+            .ok_or_else(|| self.error("Unexpected end of file".to_string()))
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AssemblyError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(format!("Expected {text} but found {}", token.text)));
+        }
+        Ok(())
+    }
+
+    fn offset(&self) -> usize {
+        self.here - PROGRAM_START
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), AssemblyError> {
+        if self.here > MAX_ADDRESS {
+            return Err(self.error("Program does not fit in memory".to_string()));
+        }
+        if let Some(line) = self.unmapped_line.take() {
+            self.source_map.insert(self.here as u16, line);
+        }
+
+        let offset = self.offset();
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn instruction(&mut self, opcode: u16) -> Result<(), AssemblyError> {
+        let [high, low] = opcode.to_be_bytes();
+        self.emit(high)?;
+        self.emit(low)
+    }
+
+    // Reads a name being defined.
+    fn name(&mut self) -> Result<String, AssemblyError> {
+        let token = self.next()?;
+        if parse_number(&token.text).is_some()
+            || register_number(&token.text).is_some()
+            || token.text.starts_with([':', '{', '}'])
+        {
+            return Err(self.error(format!("Invalid name: {}", token.text)));
+        }
+        Ok(token.text)
+    }
+
+    fn define_label(&mut self, name: String, address: usize) -> Result<(), AssemblyError> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(self.error(format!("Name already defined: {name}")));
+        }
+        self.labels.insert(name, address as u16);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: String, value: f64) -> Result<(), AssemblyError> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(self.error(format!("Name already defined: {name}")));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    fn register_of(&self, text: &str) -> Option<u16> {
+        self.aliases
+            .get(text)
+            .copied()
+            .or_else(|| register_number(text))
+    }
+
+    fn register(&mut self) -> Result<u16, AssemblyError> {
+        let token = self.next()?;
+        self.register_of(&token.text)
+            .ok_or_else(|| self.error(format!("Expected a register but found {}", token.text)))
+    }
+
+    // A number, constant or already defined label.
+    fn known_value(&self, text: &str) -> Option<f64> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|&address| address as f64))
+    }
+
+    fn value(&mut self, min: i64, max: i64) -> Result<u16, AssemblyError> {
+        let token = self.next()?;
+        let value = self
+            .known_value(&token.text)
+            .ok_or_else(|| self.error(format!("Undefined name: {}", token.text)))?
+            .floor() as i64;
+        if !(min..=max).contains(&value) {
+            return Err(self.error(format!("Value out of range: {}", token.text)));
+        }
+        Ok((value & 0xFFFF) as u16)
+    }
+
+    // Negative bytes are two's complement, as in `v0 += -1`.
+    fn byte(&mut self) -> Result<u16, AssemblyError> {
+        self.value(-0x80, 0xFF).map(|value| value & 0xFF)
+    }
+
+    fn nibble(&mut self) -> Result<u16, AssemblyError> {
+        self.value(0, 0xF)
+    }
+
+    // Reads an address for the instruction about to be emitted. Labels that
+    // are not defined yet are patched in at the end.
+    fn address(&mut self, patch: Patch) -> Result<u16, AssemblyError> {
+        let max = match patch {
+            Patch::Long => MAX_ADDRESS as i64,
+            _ => 0xFFF,
+        };
+        let token = self.next()?;
+        if self.known_value(&token.text).is_none() {
+            if token.text.starts_with([':', '{', '}']) || self.register_of(&token.text).is_some() {
+                return Err(self.error(format!("Expected an address but found {}", token.text)));
+            }
+            self.fixups.push(Fixup {
+                offset: self.offset(),
+                name: token.text,
+                patch,
+                line: self.line,
+            });
+            return Ok(0);
+        }
+
+        self.tokens.push(token);
+        self.value(0, max)
+    }
+
+    fn apply(&mut self, offset: usize, patch: Patch, address: usize) -> Result<(), AssemblyError> {
+        match patch {
+            Patch::Address => self.patch_address(offset, address),
+            Patch::Long => {
+                self.rom[offset + 2..offset + 4].copy_from_slice(&(address as u16).to_be_bytes());
+                Ok(())
+            }
+            Patch::Unpack(nibble) => {
+                if address > 0xFFF {
+                    return Err(self.error(format!("Address out of range: {:#X}", address)));
+                }
+                self.rom[offset + 1] = (nibble << 4 | (address as u16) >> 8) as u8;
+                self.rom[offset + 3] = address as u8;
+                Ok(())
+            }
+        }
+    }
+
+    // Sets the low 12 bits of the instruction at `offset`.
+    fn patch_address(&mut self, offset: usize, address: usize) -> Result<(), AssemblyError> {
+        if address > 0xFFF {
+            return Err(self.error(format!("Address out of range: {:#X}", address)));
+        }
+        let opcode = u16::from_be_bytes([self.rom[offset], self.rom[offset + 1]]);
+        let opcode = opcode & 0xF000 | address as u16;
+        self.rom[offset..offset + 2].copy_from_slice(&opcode.to_be_bytes());
+        Ok(())
+    }
+
+    // The tokens between `{` and the matching `}`.
+    fn braced(&mut self) -> Result<Vec<Token>, AssemblyError> {
+        self.expect("{")?;
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(tokens),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    // `:calc` expressions have no precedence: operators apply right to left,
+    // so `1 + 2 * 3` is 7 and `2 * 3 + 1` is 8. Use parentheses otherwise.
+    fn calc(&self, tokens: &[Token]) -> Result<f64, AssemblyError> {
+        let mut position = 0;
+        let value = self
+            .calc_expression(tokens, &mut position)
+            .map_err(|message| self.error(message))?;
+        match tokens.get(position) {
+            Some(token) => Err(self.error(format!("Unexpected {} in expression", token.text))),
+            None => Ok(value),
+        }
+    }
+
+    fn calc_expression(&self, tokens: &[Token], position: &mut usize) -> Result<f64, String> {
+        let left = self.calc_term(tokens, position)?;
+        let Some(operator) = tokens.get(*position).filter(|token| token.text != ")") else {
+            return Ok(left);
+        };
+        *position += 1;
+        let right = self.calc_expression(tokens, position)?;
+        binary(&operator.text, left, right).ok_or(format!("Unknown operator: {}", operator.text))
+    }
+
+    fn calc_term(&self, tokens: &[Token], position: &mut usize) -> Result<f64, String> {
+        let token = tokens.get(*position).ok_or("Missing value in expression")?;
+        *position += 1;
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(tokens, position)?;
+                match tokens.get(*position) {
+                    Some(token) if token.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => Err("Missing ) in expression".to_string()),
+                }
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text if UNARY_OPERATORS.contains(&text) => {
+                let value = self.calc_term(tokens, position)?;
+                Ok(unary(text, value))
+            }
+            text => self
+                .known_value(text)
+                .ok_or(format!("Undefined name: {text}")),
+        }
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AssemblyError> {
+        if self.macros.contains_key(&token.text) {
+            return self.expand(token);
+        }
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                // Code starting right at 0x200 needs no jump to main
+                if name == "main" && self.jump_to_main && self.here == PROGRAM_START + 2 {
+                    self.rom.clear();
+                    self.here = PROGRAM_START;
+                    self.jump_to_main = false;
+                }
+                self.define_label(name, self.here)?;
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let value = self
+                    .known_value(&token.text)
+                    .ok_or_else(|| self.error(format!("Undefined name: {}", token.text)))?;
+                self.define_constant(name, value)?;
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let tokens = self.braced()?;
+                let value = self.calc(&tokens)?;
+                self.define_constant(name, value)?;
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    let tokens = self.braced()?;
+                    self.calc(&tokens)?.floor() as i64
+                } else {
+                    self.byte()? as i64
+                };
+                if !(-0x80..=0xFF).contains(&value) {
+                    return Err(self.error(format!("Value out of range: {value}")));
+                }
+                self.emit(value as u8)?;
+            }
+            ":org" => {
+                self.here = self.value(PROGRAM_START as i64, MAX_ADDRESS as i64)? as usize;
+            }
+            ":call" => {
+                let address = self.address(Patch::Address)?;
+                self.instruction(0x2000 | address)?;
+            }
+            ":next" => {
+                // Labels the operand byte of the next instruction
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)?;
+            }
+            ":unpack" => {
+                let nibble = self.nibble()?;
+                let address = self.address(Patch::Unpack(nibble))?;
+                let high = self.aliases["unpack-hi"];
+                let low = self.aliases["unpack-lo"];
+                self.instruction(0x6000 | high << 8 | nibble << 4 | address >> 8)?;
+                self.instruction(0x6000 | low << 8 | (address & 0xFF))?;
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut parameters = Vec::new();
+                while self.peek().is_some_and(|text| text != "{") {
+                    parameters.push(self.next()?.text);
+                }
+                let body = self.braced()?;
+                self.macros.insert(name, Macro { parameters, body });
+            }
+            "return" | ";" => self.instruction(0x00EE)?,
+            "clear" => self.instruction(0x00E0)?,
+            "exit" => self.instruction(0x00FD)?,
+            "lores" => self.instruction(0x00FE)?,
+            "hires" => self.instruction(0x00FF)?,
+            "scroll-right" => self.instruction(0x00FB)?,
+            "scroll-left" => self.instruction(0x00FC)?,
+            "audio" => self.instruction(0xF002)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(0x00D0 | n)?;
+            }
+            "plane" => {
+                let n = self.value(0, 3)?;
+                self.instruction(0xF001 | n << 8)?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.instruction(0xF033 | x << 8)?;
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.instruction(0xF075 | x << 8)?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.instruction(0xF085 | x << 8)?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let last = if self.peek() == Some("-") {
+                    self.next()?;
+                    Some(self.register()?)
+                } else {
+                    None
+                };
+                let opcode = match (token.text == "save", last) {
+                    (true, None) => 0xF055 | x << 8,
+                    (false, None) => 0xF065 | x << 8,
+                    (true, Some(y)) => 0x5002 | x << 8 | y << 4,
+                    (false, Some(y)) => 0x5003 | x << 8 | y << 4,
+                };
+                self.instruction(opcode)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.instruction(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "jump" => {
+                let address = self.address(Patch::Address)?;
+                self.instruction(0x1000 | address)?;
+            }
+            "jump0" => {
+                let address = self.address(Patch::Address)?;
+                self.instruction(0xB000 | address)?;
+            }
+            "native" => {
+                let address = self.address(Patch::Address)?;
+                self.instruction(address)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let opcode = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.instruction(opcode | x << 8)?;
+            }
+            "i" => self.index_statement()?,
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text.as_str() {
+                    "then" => self.instruction(condition.negated().skip_opcode())?,
+                    "begin" => {
+                        self.instruction(condition.skip_opcode())?;
+                        let jump = self.offset();
+                        self.instruction(0x1000)?;
+                        self.blocks.push(Block::If {
+                            jump,
+                            line: self.line,
+                        });
+                    }
+                    text => {
+                        return Err(self.error(format!("Expected then or begin but found {text}")));
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, line }) => {
+                    let end = self.offset();
+                    self.instruction(0x1000)?;
+                    self.patch_address(jump, self.here)?;
+                    self.blocks.push(Block::Else { jump: end, line });
+                }
+                _ => return Err(self.error("else without if ... begin".to_string())),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. } | Block::Else { jump, .. }) => {
+                    self.patch_address(jump, self.here)?;
+                }
+                _ => return Err(self.error("end without if ... begin".to_string())),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here,
+                breaks: Vec::new(),
+                line: self.line,
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                self.instruction(condition.skip_opcode())?;
+                let jump = self.offset();
+                self.instruction(0x1000)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return Err(self.error("while outside a loop".to_string())),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.instruction(0x1000)?;
+                    self.patch_address(self.offset() - 2, start)?;
+                    for jump in breaks {
+                        self.patch_address(jump, self.here)?;
+                    }
+                }
+                _ => return Err(self.error("again without loop".to_string())),
+            },
+            text => {
+                if let Some(x) = self.register_of(text) {
+                    self.assignment(x)?;
+                } else if parse_number(text).is_some() || self.constants.contains_key(text) {
+                    // Bare numbers are data
+                    self.tokens.push(token);
+                    let byte = self.byte()?;
+                    self.emit(byte as u8)?;
+                } else if text.starts_with([':', '{', '}']) {
+                    return Err(self.error(format!("Unexpected {text}")));
+                } else {
+                    // Any other name calls a subroutine
+                    self.tokens.push(token);
+                    let address = self.address(Patch::Address)?;
+                    self.instruction(0x2000 | address)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn expand(&mut self, token: Token) -> Result<(), AssemblyError> {
+        if token.depth >= MAX_MACRO_DEPTH {
+            return Err(self.error(format!("Macro expands itself: {}", token.text)));
+        }
+
+        let definition = self.macros[&token.text].clone();
+        let mut arguments = Vec::new();
+        for _ in &definition.parameters {
+            arguments.push(self.next()?.text);
+        }
+
+        for body in definition.body.iter().rev() {
+            let text = match definition
+                .parameters
+                .iter()
+                .position(|parameter| *parameter == body.text)
+            {
+                Some(index) => arguments[index].clone(),
+                None => body.text.clone(),
+            };
+            self.tokens.push(Token {
+                text,
+                line: body.line,
+                depth: token.depth + 1,
+            });
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), AssemblyError> {
+        let operator = self.next()?;
+        match operator.text.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.instruction(0xF01E | x << 8)
+            }
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let opcode = match self.next()?.text.as_str() {
+                        "hex" => 0xF029,
+                        _ => 0xF030,
+                    };
+                    let x = self.register()?;
+                    self.instruction(opcode | x << 8)
+                }
+                Some("long") => {
+                    self.next()?;
+                    let address = self.address(Patch::Long)?;
+                    self.instruction(0xF000)?;
+                    self.instruction(address)
+                }
+                _ => {
+                    let address = self.address(Patch::Address)?;
+                    self.instruction(0xA000 | address)
+                }
+            },
+            text => Err(self.error(format!("Expected := or += but found {text}"))),
+        }
+    }
+
+    fn assignment(&mut self, x: u16) -> Result<(), AssemblyError> {
+        let operator = self.next()?;
+        let source = self.peek().and_then(|text| self.register_of(text));
+        if source.is_some() {
+            self.next()?;
+        }
+
+        let opcode = match (operator.text.as_str(), source) {
+            (":=", Some(y)) => 0x8000 | x << 8 | y << 4,
+            (":=", None) => match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    0xC000 | x << 8 | self.byte()?
+                }
+                Some("key") => {
+                    self.next()?;
+                    0xF00A | x << 8
+                }
+                Some("delay") => {
+                    self.next()?;
+                    0xF007 | x << 8
+                }
+                _ => 0x6000 | x << 8 | self.byte()?,
+            },
+            ("+=", Some(y)) => 0x8004 | x << 8 | y << 4,
+            ("+=", None) => 0x7000 | x << 8 | self.byte()?,
+            ("-=", Some(y)) => 0x8005 | x << 8 | y << 4,
+            ("-=", None) => 0x7000 | x << 8 | (0x100 - self.byte()?) & 0xFF,
+            ("=-", Some(y)) => 0x8007 | x << 8 | y << 4,
+            ("|=", Some(y)) => 0x8001 | x << 8 | y << 4,
+            ("&=", Some(y)) => 0x8002 | x << 8 | y << 4,
+            ("^=", Some(y)) => 0x8003 | x << 8 | y << 4,
+            (">>=", Some(y)) => 0x8006 | x << 8 | y << 4,
+            ("<<=", Some(y)) => 0x800E | x << 8 | y << 4,
+            (text, _) => return Err(self.error(format!("Invalid assignment: {text}"))),
+        };
+        self.instruction(opcode)
+    }
+
+    fn side(&mut self) -> Result<Side, AssemblyError> {
+        match self.peek().and_then(|text| self.register_of(text)) {
+            Some(y) => {
+                self.next()?;
+                Ok(Side::Register(y))
+            }
+            None => Ok(Side::Byte(self.byte()?)),
+        }
+    }
+
+    // Parses a condition, emitting the VF arithmetic that `<`, `>`, `<=` and
+    // `>=` need beforehand.
+    fn condition(&mut self) -> Result<Condition, AssemblyError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        let condition = match operator.text.as_str() {
+            "key" => Condition::Key(x),
+            "-key" => Condition::NotKey(x),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                let side = self.side()?;
+                match (operator.text.as_str(), side) {
+                    ("==", Side::Register(y)) => Condition::EqualRegister(x, y),
+                    ("==", Side::Byte(n)) => Condition::Equal(x, n),
+                    ("!=", Side::Register(y)) => Condition::NotEqualRegister(x, y),
+                    ("!=", Side::Byte(n)) => Condition::NotEqual(x, n),
+                    ("<", _) => {
+                        self.greater_or_equal_flag(Side::Register(x), side)?;
+                        Condition::Equal(0xF, 0)
+                    }
+                    (">=", _) => {
+                        self.greater_or_equal_flag(Side::Register(x), side)?;
+                        Condition::Equal(0xF, 1)
+                    }
+                    (">", _) => {
+                        self.greater_or_equal_flag(side, Side::Register(x))?;
+                        Condition::Equal(0xF, 0)
+                    }
+                    _ => {
+                        self.greater_or_equal_flag(side, Side::Register(x))?;
+                        Condition::Equal(0xF, 1)
+                    }
+                }
+            }
+            text => return Err(self.error(format!("Invalid condition: {text}"))),
+        };
+        Ok(condition)
+    }
+
+    // Leaves VF at 1 when `left >= right` and 0 otherwise, from the borrow of
+    // a subtraction.
+    fn greater_or_equal_flag(&mut self, left: Side, right: Side) -> Result<(), AssemblyError> {
+        match (left, right) {
+            (Side::Register(x), Side::Register(y)) => {
+                self.instruction(0x8F00 | x << 4)?;
+                self.instruction(0x8F05 | y << 4)
+            }
+            (Side::Byte(n), Side::Register(y)) => {
+                self.instruction(0x6F00 | n)?;
+                self.instruction(0x8F05 | y << 4)
+            }
+            (Side::Register(x), Side::Byte(n)) => {
+                self.instruction(0x6F00 | n)?;
+                self.instruction(0x8F07 | x << 4)
+            }
+            (Side::Byte(_), Side::Byte(_)) => unreachable!("conditions start with a register"),
+        }
+    }
+}
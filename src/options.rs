@@ -0,0 +1,467 @@
+use chip8::audio::{DEFAULT_TONE_FREQUENCY, DEFAULT_VOLUME, Waveform};
+use chip8::rom_database::{DEFAULT_DATABASE_PATH, DEFAULT_OVERRIDES_PATH};
+use chip8::scheduler::{
+    DEFAULT_CLOCK_HZ, DEFAULT_FAST_FORWARD_FACTOR, DEFAULT_SLOW_MOTION_DIVISOR,
+};
+use chip8::screenshot::parse_palette;
+use chip8::tui::{DEFAULT_KEY_HOLD_FRAMES, THEMES, theme_from_name};
+use chip8::{
+    DEFAULT_REWIND_DEPTH, DEFAULT_REWIND_INTERVAL, Platform, Quirks, RandomSource, RomSettings,
+    ScreenshotFormat, TraceFormat,
+};
+use std::ops::{Range, RangeInclusive};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioOutput {
+    None,
+    Device,
+    Wav(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontendKind {
+    #[cfg(feature = "minifb")]
+    Window,
+    Terminal,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub rom_path: String,
+    // Only what was given on the command line, a ROM file's settings fill in
+    // the rest.
+    pub settings: RomSettings,
+    pub fast_forward_factor: u32,
+    pub slow_motion_divisor: u32,
+    pub start_paused: bool,
+    pub audio_output: AudioOutput,
+    pub tone_frequency: u32,
+    pub volume_percent: u32,
+    pub waveform: Waveform,
+    pub state_path: String,
+    pub random: RandomSource,
+    pub debug: bool,
+    pub trace_path: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_addresses: Option<RangeInclusive<u16>>,
+    pub trace_cycles: Option<Range<u64>>,
+    pub trace_last: Option<usize>,
+    pub rewind_depth: usize,
+    pub rewind_interval: u32,
+    pub screenshot_format: ScreenshotFormat,
+    pub screenshot_scale: usize,
+    pub record_path: Option<String>,
+    pub play_path: Option<String>,
+    pub database_path: Option<String>,
+    pub overrides_path: Option<String>,
+    pub frontend: FrontendKind,
+    #[cfg_attr(not(feature = "tui"), allow(dead_code))]
+    pub key_hold_frames: u32,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut rom_path = None;
+        let mut settings = RomSettings::default();
+        let mut fast_forward_factor = DEFAULT_FAST_FORWARD_FACTOR;
+        let mut slow_motion_divisor = DEFAULT_SLOW_MOTION_DIVISOR;
+        let mut start_paused = false;
+        let mut debug = false;
+        let mut trace_path = None;
+        let mut trace_format = TraceFormat::Text;
+        let mut trace_addresses = None;
+        let mut trace_cycles = None;
+        let mut trace_last = None;
+        let mut rewind_depth = DEFAULT_REWIND_DEPTH;
+        let mut rewind_interval = DEFAULT_REWIND_INTERVAL;
+        let mut screenshot_format = ScreenshotFormat::Png;
+        let mut screenshot_scale = 1;
+        let mut record_path = None;
+        let mut play_path = None;
+        let mut database_path = None;
+        let mut overrides_path = None;
+        #[cfg(feature = "minifb")]
+        let mut frontend = FrontendKind::Window;
+        // The terminal stands in where there is no window to open
+        #[cfg(not(feature = "minifb"))]
+        let mut frontend = FrontendKind::Terminal;
+        let mut key_hold_frames = DEFAULT_KEY_HOLD_FRAMES;
+        let mut audio_output = if cfg!(feature = "cpal") {
+            AudioOutput::Device
+        } else {
+            AudioOutput::None
+        };
+        let mut tone_frequency = DEFAULT_TONE_FREQUENCY as u32;
+        let mut volume_percent = (DEFAULT_VOLUME * 100.0) as u32;
+        let mut waveform = Waveform::Square;
+        let mut state_path = None;
+        let mut seed = None;
+        let mut random_sequence = None;
+
+        let mut iter = args.iter().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--clock" => settings.clock_hz = Some(parse_number(arg, iter.next())?),
+                "--fast-forward" => fast_forward_factor = parse_number(arg, iter.next())?,
+                "--slow-motion" => slow_motion_divisor = parse_number(arg, iter.next())?,
+                "--paused" => start_paused = true,
+                "--debug" => debug = true,
+                "--audio" => {
+                    audio_output = match iter.next().map(String::as_str) {
+                        Some("none") => AudioOutput::None,
+                        Some("device") => AudioOutput::Device,
+                        value => return Err(format!("Invalid value for {arg}: {value:?}")),
+                    }
+                }
+                "--wav" => {
+                    let path = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    audio_output = AudioOutput::Wav(path.clone());
+                }
+                "--tone" => tone_frequency = parse_number(arg, iter.next())?,
+                "--volume" => {
+                    volume_percent = parse_number(arg, iter.next())?;
+                    if volume_percent > 100 {
+                        return Err(format!("Invalid value for {arg}: {volume_percent}"));
+                    }
+                }
+                "--waveform" => {
+                    let name = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    waveform = Waveform::from_name(name)
+                        .ok_or(format!("Invalid value for {arg}: {name}"))?;
+                }
+                "--quirks" => {
+                    let name = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    settings.quirks = Some(
+                        Quirks::from_name(name).ok_or(format!("Unknown quirks preset: {name}"))?,
+                    );
+                }
+                "--platform" => {
+                    let name = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    settings.platform =
+                        Some(Platform::from_name(name).ok_or(format!("Unknown platform: {name}"))?);
+                }
+                "--state-file" => {
+                    let path = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    state_path = Some(path.clone());
+                }
+                "--seed" => {
+                    let value = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    seed = Some(
+                        value
+                            .parse::<u64>()
+                            .map_err(|_| format!("Invalid value for {arg}: {value}"))?,
+                    );
+                }
+                "--random-sequence" => {
+                    let value = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    random_sequence = Some(parse_hex_bytes(arg, value)?);
+                }
+                "--trace" => {
+                    let path = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    trace_path = Some(path.clone());
+                }
+                "--trace-format" => {
+                    let name = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    trace_format = TraceFormat::from_name(name)
+                        .ok_or(format!("Invalid value for {arg}: {name}"))?;
+                }
+                "--trace-pc" => {
+                    let (start, end) = parse_range(arg, iter.next(), 16)?;
+                    let start = u16::try_from(start);
+                    let end = u16::try_from(end);
+                    match (start, end) {
+                        (Ok(start), Ok(end)) => trace_addresses = Some(start..=end),
+                        _ => return Err(format!("Invalid value for {arg}")),
+                    }
+                }
+                "--trace-cycles" => {
+                    let (start, end) = parse_range(arg, iter.next(), 10)?;
+                    trace_cycles = Some(start..end);
+                }
+                "--trace-last" => trace_last = Some(parse_number(arg, iter.next())? as usize),
+                "--rewind-depth" => rewind_depth = parse_number(arg, iter.next())? as usize,
+                "--rewind-interval" => rewind_interval = parse_number(arg, iter.next())?,
+                "--record" => {
+                    let path = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    record_path = Some(path.clone());
+                }
+                "--play" => {
+                    let path = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    play_path = Some(path.clone());
+                }
+                "--database" => {
+                    let path = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    database_path = Some(path.clone());
+                }
+                "--overrides" => {
+                    let path = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    overrides_path = Some(path.clone());
+                }
+                "--frontend" => {
+                    frontend = match iter.next().map(String::as_str) {
+                        #[cfg(feature = "minifb")]
+                        Some("window") => FrontendKind::Window,
+                        #[cfg(not(feature = "minifb"))]
+                        Some("window") => {
+                            return Err(
+                                "The window frontend needs the `minifb` feature".to_string()
+                            );
+                        }
+                        Some("terminal") => FrontendKind::Terminal,
+                        value => return Err(format!("Invalid value for {arg}: {value:?}")),
+                    }
+                }
+                "--key-hold" => key_hold_frames = parse_number(arg, iter.next())?,
+                "--theme" => {
+                    let name = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    settings.palette =
+                        Some(theme_from_name(name).ok_or(format!("Unknown theme: {name}"))?);
+                }
+                "--palette" => {
+                    let value = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    settings.palette = Some(parse_palette(value)?);
+                }
+                "--screenshot-format" => {
+                    let name = iter.next().ok_or(format!("Missing value for {arg}"))?;
+                    screenshot_format = ScreenshotFormat::from_name(name)
+                        .ok_or(format!("Invalid value for {arg}: {name}"))?;
+                }
+                "--screenshot-scale" => {
+                    screenshot_scale = parse_number(arg, iter.next())? as usize;
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option: {arg}")),
+                _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+                _ => return Err(format!("Unexpected argument: {arg}")),
+            }
+        }
+
+        let rom_path: String = rom_path.ok_or("Missing ROM path")?;
+        if (record_path.is_some() || play_path.is_some()) && debug {
+            return Err("Movies cannot be used with --debug".to_string());
+        }
+        // The REPL shares the terminal with the keypad, so it only runs in a window.
+        #[cfg(feature = "minifb")]
+        if debug && frontend == FrontendKind::Terminal {
+            return Err("The debugger needs the window frontend".to_string());
+        }
+        #[cfg(not(feature = "minifb"))]
+        if debug {
+            return Err("The debugger needs the `minifb` feature".to_string());
+        }
+        if record_path.is_some() && play_path.is_some() {
+            return Err("Use either --record or --play".to_string());
+        }
+        let random = match (random_sequence, seed) {
+            (Some(bytes), _) => RandomSource::scripted(bytes),
+            (None, Some(seed)) => RandomSource::seeded(seed),
+            (None, None) => RandomSource::from_os(),
+        };
+        Ok(Options {
+            state_path: state_path.unwrap_or(format!("{rom_path}.state")),
+            random,
+            debug,
+            trace_path,
+            trace_format,
+            trace_addresses,
+            trace_cycles,
+            trace_last,
+            rewind_depth,
+            rewind_interval,
+            screenshot_format,
+            screenshot_scale,
+            record_path,
+            play_path,
+            database_path,
+            overrides_path,
+            frontend,
+            key_hold_frames,
+            rom_path,
+            settings,
+            fast_forward_factor,
+            slow_motion_divisor,
+            start_paused,
+            audio_output,
+            tone_frequency,
+            volume_percent,
+            waveform,
+        })
+    }
+
+    pub fn print_usage(program: &str) {
+        eprintln!("Usage: {} [options] <rom_path>", program);
+        eprintln!("ROMs can be raw binaries, Octo cartridge GIFs, Octo source (.8o), Intel HEX or");
+        eprintln!("hex dumps (.hex), or a zip holding one of those. A cartridge's options apply");
+        eprintln!("unless overridden.");
+        eprintln!("Options:");
+        eprintln!(
+            "  --clock <hz>          CPU instructions per second (default {DEFAULT_CLOCK_HZ})"
+        );
+        eprintln!(
+            "  --fast-forward <n>    Speed multiplier while fast-forwarding (default {DEFAULT_FAST_FORWARD_FACTOR})"
+        );
+        eprintln!(
+            "  --slow-motion <n>     Speed divisor in slow motion (default {DEFAULT_SLOW_MOTION_DIVISOR})"
+        );
+        eprintln!("  --paused              Start with emulation paused");
+        eprintln!("  --debug               Start in the command-line debugger");
+        eprintln!("  --audio <device|none> Sound output (device needs the `cpal` feature)");
+        eprintln!("  --wav <path>          Record sound to a WAV file instead of playing it");
+        eprintln!("  --tone <hz>           Beep frequency (default {DEFAULT_TONE_FREQUENCY})");
+        eprintln!("  --volume <0-100>      Beep volume in percent");
+        eprintln!("  --waveform <name>     square, triangle, sawtooth or sine (default square)");
+        eprintln!("  --platform <name>     chip8, schip or xochip (default chip8)");
+        eprintln!(
+            "  --quirks <preset>     vip, chip48, schip, xochip or modern (default per platform:"
+        );
+        eprintln!("                        modern for chip8, schip for schip, xochip for xochip)");
+        eprintln!("  --state-file <path>   Save state file (default <rom_path>.state)");
+        eprintln!("  --seed <n>            Seed for the random source (default: from the OS)");
+        eprintln!("  --random-sequence <hex,...>");
+        eprintln!("                        Repeat fixed random bytes, e.g. 00,7F,FF");
+        eprintln!("  --trace <path>        Log every executed instruction to a file");
+        eprintln!("  --trace-format <text|binary>");
+        eprintln!("                        Trace file format (default text)");
+        eprintln!("  --trace-pc <start-end>");
+        eprintln!("                        Only trace instructions in this hex address range");
+        eprintln!("  --trace-cycles <start-end>");
+        eprintln!("                        Only trace this cycle window (end exclusive)");
+        eprintln!(
+            "  --trace-last <n>      Only write the last n instructions when the ROM crashes"
+        );
+        eprintln!(
+            "  --rewind-depth <n>    Snapshots kept for rewinding (default {DEFAULT_REWIND_DEPTH})"
+        );
+        eprintln!(
+            "  --rewind-interval <n> Frames between rewind snapshots (default {DEFAULT_REWIND_INTERVAL})"
+        );
+        eprintln!("  --palette <rgb,...>   Hex colours for off, plane 1, plane 2 and both planes");
+        eprintln!("  --theme <name>        Colour theme: {}", theme_names());
+        eprintln!("  --frontend <window|terminal>");
+        eprintln!(
+            "                        Draw in a window or in the terminal (default {})",
+            if cfg!(feature = "minifb") {
+                "window"
+            } else {
+                "terminal"
+            }
+        );
+        eprintln!(
+            "  --key-hold <frames>   Terminal key press length (default {DEFAULT_KEY_HOLD_FRAMES})"
+        );
+        eprintln!("  --screenshot-format <png|ppm|ascii|unicode>");
+        eprintln!("                        Format of F2 screenshots (default png)");
+        eprintln!("  --screenshot-scale <n>");
+        eprintln!("                        Pixel size of F2 screenshots (default 1)");
+        eprintln!("  --record <path>       Record the keypad to a movie file");
+        eprintln!(
+            "  --play <path>         Replay a movie, with the platform, quirks, seed and speed it"
+        );
+        eprintln!("                        was recorded with");
+        eprintln!(
+            "  --database <path>     chip-8-database programs.json to look ROMs up in by SHA-1"
+        );
+        eprintln!("                        (default {DEFAULT_DATABASE_PATH} if present)");
+        eprintln!("  --overrides <path>    Your own ROM settings, over the database's");
+        eprintln!("                        (default {DEFAULT_OVERRIDES_PATH} if present)");
+        eprintln!("Hotkeys:");
+        eprintln!("  Space                 Pause / resume");
+        eprintln!("  Tab                   Fast-forward while held");
+        eprintln!("  Backspace             Toggle slow motion");
+        eprintln!("  `                     Rewind while held");
+        eprintln!("  F2                    Save a screenshot next to the ROM");
+        eprintln!("  F5 / F9               Save / load state");
+        eprintln!("  F12                   Break into the debugger (--debug)");
+        eprintln!("  Esc                   Quit the terminal frontend");
+        eprintln!("Available ROMs in res/ folder:");
+        eprintln!("  - 15puzzle.rom");
+        eprintln!("  - blitz.rom");
+        eprintln!("  - breakout.rom");
+        eprintln!("  - brix.rom");
+        eprintln!("  - connect4.rom");
+        eprintln!("  - guess.rom");
+        eprintln!("  - invaders.rom");
+        eprintln!("  - maze.rom");
+        eprintln!("  - merlin.rom");
+        eprintln!("  - missile.rom");
+        eprintln!("  - pong.rom");
+        eprintln!("  - tetris.rom");
+    }
+}
+
+fn theme_names() -> String {
+    THEMES
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn parse_number(option: &str, value: Option<&String>) -> Result<u32, String> {
+    let value = value.ok_or(format!("Missing value for {option}"))?;
+
+    match value.parse::<u32>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(format!("Invalid value for {option}: {value}")),
+    }
+}
+
+fn parse_hex_bytes(option: &str, value: &str) -> Result<Vec<u8>, String> {
+    value
+        .split(',')
+        .map(|byte| {
+            let byte = byte.trim();
+            u8::from_str_radix(byte.strip_prefix("0x").unwrap_or(byte), 16)
+                .map_err(|_| format!("Invalid value for {option}: {value}"))
+        })
+        .collect()
+}
+
+// `<start>-<end>` in the given radix, hex values may have a 0x prefix.
+fn parse_range(option: &str, value: Option<&String>, radix: u32) -> Result<(u64, u64), String> {
+    let value = value.ok_or(format!("Missing value for {option}"))?;
+    let parse = |text: &str| {
+        let text = text.trim();
+        let digits = match radix {
+            16 => text.strip_prefix("0x").unwrap_or(text),
+            _ => text,
+        };
+        u64::from_str_radix(digits, radix).ok()
+    };
+
+    match value
+        .split_once('-')
+        .map(|(start, end)| (parse(start), parse(end)))
+    {
+        Some((Some(start), Some(end))) if start <= end => Ok((start, end)),
+        _ => Err(format!("Invalid value for {option}: {value}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Options::parse(&args)
+    }
+
+    #[cfg(feature = "minifb")]
+    #[test]
+    fn should_only_debug_in_the_window() {
+        assert!(parse(&["chip8", "--debug", "rom.ch8"]).is_ok());
+        assert_eq!(
+            parse(&["chip8", "--debug", "--frontend", "terminal", "rom.ch8"]).err(),
+            Some("The debugger needs the window frontend".to_string())
+        );
+    }
+
+    #[cfg(not(feature = "minifb"))]
+    #[test]
+    fn should_need_minifb_to_debug() {
+        assert_eq!(
+            parse(&["chip8", "--debug", "rom.ch8"]).err(),
+            Some("The debugger needs the `minifb` feature".to_string())
+        );
+    }
+}
@@ -0,0 +1,108 @@
+// Behaviour differences between CHIP-8 interpreters. Many ROMs only work with
+// the quirks of the interpreter they were written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryIncrement {
+    // Fx55/Fx65 leave I unchanged.
+    None,
+    // I is increased by x (CHIP-48).
+    X,
+    // I is increased by x + 1 (COSMAC VIP).
+    XPlusOne,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    // How Fx55/Fx65 change I.
+    pub memory_increment: MemoryIncrement,
+    // Bxnn jumps to xnn + Vx instead of Bnnn jumping to nnn + V0.
+    pub jump_uses_vx: bool,
+    // 8xy1/8xy2/8xy3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    // Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+    // Dxyn waits for the next 60 Hz tick, limiting drawing to once per frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        memory_increment: MemoryIncrement::XPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        clip_sprites: true,
+        display_wait: true,
+    };
+
+    pub const CHIP48: Quirks = Quirks {
+        shift_uses_vy: false,
+        memory_increment: MemoryIncrement::X,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        display_wait: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        memory_increment: MemoryIncrement::None,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        display_wait: false,
+    };
+
+    pub const MODERN: Quirks = Quirks {
+        shift_uses_vy: false,
+        memory_increment: MemoryIncrement::None,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+        display_wait: false,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        memory_increment: MemoryIncrement::XPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+        display_wait: false,
+    };
+
+    pub const PRESETS: [(&'static str, Quirks); 5] = [
+        ("vip", Quirks::COSMAC_VIP),
+        ("chip48", Quirks::CHIP48),
+        ("schip", Quirks::SUPER_CHIP),
+        ("xochip", Quirks::XO_CHIP),
+        ("modern", Quirks::MODERN),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        let name = name.to_ascii_lowercase();
+        // "chip8" is left out on purpose, it would not mean the chip8
+        // platform's default, which is "modern"
+        let name = match name.as_str() {
+            "cosmac" | "cosmac-vip" => "vip",
+            "chip-48" => "chip48",
+            "superchip" | "super-chip" => "schip",
+            "xo-chip" => "xochip",
+            "octo" => "modern",
+            other => other,
+        };
+
+        Quirks::PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|(_, quirks)| *quirks)
+    }
+}
+
+// Matches the behaviour this emulator always had.
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::MODERN
+    }
+}
@@ -0,0 +1,46 @@
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+// Where Cxkk gets its random bytes from. Every source is deterministic once
+// created, so runs can be replayed and saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RandomSource {
+    ChaCha(Box<ChaCha8Rng>),
+    // Repeats `bytes` forever, for tests and reproducing bug reports.
+    Scripted { bytes: Vec<u8>, position: usize },
+}
+
+impl RandomSource {
+    pub fn from_os() -> Self {
+        RandomSource::ChaCha(Box::new(ChaCha8Rng::from_os_rng()))
+    }
+
+    pub fn seeded(seed: u64) -> Self {
+        RandomSource::ChaCha(Box::new(ChaCha8Rng::seed_from_u64(seed)))
+    }
+
+    pub fn scripted(bytes: Vec<u8>) -> Self {
+        RandomSource::Scripted { bytes, position: 0 }
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        match self {
+            RandomSource::ChaCha(random) => random.random::<u8>(),
+            RandomSource::Scripted { bytes, position } => {
+                if bytes.is_empty() {
+                    return 0;
+                }
+
+                let byte = bytes[*position % bytes.len()];
+                *position = (*position + 1) % bytes.len();
+                byte
+            }
+        }
+    }
+}
+
+impl Default for RandomSource {
+    fn default() -> Self {
+        RandomSource::from_os()
+    }
+}
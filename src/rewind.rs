@@ -0,0 +1,172 @@
+use crate::chip8_machine::Chip8MachineState;
+use std::collections::VecDeque;
+
+// 10 seconds of history at 60 fps.
+pub const DEFAULT_REWIND_DEPTH: usize = 600;
+pub const DEFAULT_REWIND_INTERVAL: u32 = 1;
+
+// Rolling history of save states for playing a game backwards. Only the newest
+// snapshot is kept whole. Every older one is stored as a run-length encoded
+// XOR against the snapshot after it, so unchanged memory, display and
+// registers cost next to nothing.
+#[derive(Debug, Clone)]
+pub struct Rewind {
+    // Maximum number of snapshots kept.
+    pub depth: usize,
+    // Frames between snapshots.
+    pub interval: u32,
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(depth: usize, interval: u32) -> Self {
+        Self {
+            depth,
+            interval: interval.max(1),
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // Called once per frame before running it. Takes a snapshot every
+    // `interval` frames, skipping it if nothing changed since the last one.
+    pub fn record(&mut self, chip8: &Chip8MachineState) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+
+        self.frames = 0;
+        self.push(chip8.save_state());
+    }
+
+    // Restores the newest snapshot and drops it, so repeated calls walk back
+    // through history. Returns false once the history is exhausted.
+    pub fn step_back(&mut self, chip8: &mut Chip8MachineState) -> bool {
+        let Some(latest) = self.latest.take() else {
+            return false;
+        };
+
+        self.latest = self
+            .deltas
+            .pop_back()
+            .map(|delta| apply_delta(&latest, &delta));
+        self.frames = 0;
+        chip8
+            .load_state(&latest)
+            .expect("rewind snapshots are always valid");
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    // Bytes used by the stored snapshots.
+    pub fn size(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    fn push(&mut self, snapshot: Vec<u8>) {
+        if self.depth == 0 || self.latest.as_ref() == Some(&snapshot) {
+            return;
+        }
+
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&previous, &snapshot));
+        }
+        self.latest = Some(snapshot);
+
+        // The oldest delta only leads to the oldest snapshot, so it can go
+        while self.len() > self.depth {
+            self.deltas.pop_front();
+        }
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Rewind::new(DEFAULT_REWIND_DEPTH, DEFAULT_REWIND_INTERVAL)
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// Encodes `older` relative to `newer`: the length of `older`, then pairs of
+// (run of unchanged bytes, run of changed bytes) with the XORed changed bytes.
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = older
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| byte ^ newer.get(index).copied().unwrap_or(0))
+        .collect();
+    let mut bytes = Vec::new();
+    write_varint(&mut bytes, older.len());
+
+    let mut position = 0;
+    while position < xor.len() {
+        let unchanged = xor[position..].iter().take_while(|&&b| b == 0).count();
+        position += unchanged;
+        let changed = xor[position..].iter().take_while(|&&b| b != 0).count();
+        write_varint(&mut bytes, unchanged);
+        write_varint(&mut bytes, changed);
+        bytes.extend_from_slice(&xor[position..position + changed]);
+        position += changed;
+    }
+
+    bytes
+}
+
+fn apply_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let len = read_varint(delta, &mut position);
+    let mut older: Vec<u8> = (0..len)
+        .map(|index| newer.get(index).copied().unwrap_or(0))
+        .collect();
+
+    let mut index = 0;
+    while position < delta.len() {
+        index += read_varint(delta, &mut position);
+        let changed = read_varint(delta, &mut position);
+        for byte in &mut older[index..index + changed] {
+            *byte ^= delta[position];
+            position += 1;
+        }
+        index += changed;
+    }
+
+    older
+}
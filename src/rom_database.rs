@@ -0,0 +1,211 @@
+use crate::chip8_machine::TIMER_FREQUENCY;
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rom_file::RomSettings;
+use crate::screenshot::DEFAULT_PALETTE;
+use serde_json::Value;
+use sha1_smol::Sha1;
+use std::collections::HashMap;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+// Where a checkout of the community chip-8-database keeps its program list.
+pub const DEFAULT_DATABASE_PATH: &str = "chip-8-database/database/programs.json";
+pub const DEFAULT_OVERRIDES_PATH: &str = "chip8-overrides.json";
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Json(serde_json::Error),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Json(err) => write!(f, "Invalid ROM database: {err}"),
+            DatabaseError::Invalid(what) => write!(f, "Invalid ROM database: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(err: serde_json::Error) -> Self {
+        DatabaseError::Json(err)
+    }
+}
+
+impl From<DatabaseError> for io::Error {
+    fn from(err: DatabaseError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RomEntry {
+    pub title: Option<String>,
+    pub settings: RomSettings,
+}
+
+// ROM settings keyed by the lowercase hex SHA-1 of the ROM.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RomDatabase {
+    pub entries: HashMap<String, RomEntry>,
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Reads the chip-8-database `programs.json` array, or an overrides object
+    // mapping SHA-1 hashes straight to ROM entries of the same schema, each
+    // with an optional "title".
+    pub fn parse(json: &str) -> Result<Self, DatabaseError> {
+        let mut database = Self::new();
+        match serde_json::from_str(json)? {
+            Value::Array(programs) => {
+                for program in &programs {
+                    let roms = program["roms"]
+                        .as_object()
+                        .ok_or(DatabaseError::Invalid("program without roms"))?;
+                    for (hash, rom) in roms {
+                        database.insert(hash, program["title"].as_str(), rom);
+                    }
+                }
+            }
+            Value::Object(roms) => {
+                for (hash, rom) in &roms {
+                    database.insert(hash, rom["title"].as_str(), rom);
+                }
+            }
+            _ => return Err(DatabaseError::Invalid("expected an array or an object")),
+        }
+        Ok(database)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?)?)
+    }
+
+    // Loads `path` when given. Otherwise the file at `default` is optional and
+    // an empty database stands in for it.
+    pub fn open(path: Option<&str>, default: &str) -> io::Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None if Path::new(default).exists() => Self::load(default),
+            None => Ok(Self::new()),
+        }
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomEntry> {
+        self.entries.get(&Sha1::from(rom).digest().to_string())
+    }
+
+    // What the database knows about `rom`, nothing when it is not listed.
+    pub fn settings(&self, rom: &[u8]) -> RomSettings {
+        self.lookup(rom)
+            .map(|entry| entry.settings)
+            .unwrap_or_default()
+    }
+
+    fn insert(&mut self, hash: &str, title: Option<&str>, rom: &Value) {
+        self.entries.insert(
+            hash.to_ascii_lowercase(),
+            RomEntry {
+                title: title.map(str::to_string),
+                settings: rom_settings(rom),
+            },
+        );
+    }
+}
+
+// The first of the ROM's platforms we can emulate decides the platform and
+// quirks, with that platform's "quirkyPlatforms" changes on top.
+fn rom_settings(rom: &Value) -> RomSettings {
+    let platforms = rom["platforms"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let preset = platforms
+        .iter()
+        .filter_map(Value::as_str)
+        .find_map(|id| platform_preset(id).map(|preset| (id, preset)));
+    let (platform, quirks) = match preset {
+        Some((id, (platform, quirks))) => (
+            Some(platform),
+            Some(quirky_platform(quirks, &rom["quirkyPlatforms"][id])),
+        ),
+        None => (None, None),
+    };
+
+    let colours: Vec<Option<u32>> = rom["colors"]["pixels"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|colour| {
+            let text = colour.as_str()?;
+            u32::from_str_radix(text.strip_prefix('#').unwrap_or(text), 16).ok()
+        })
+        .collect();
+    let palette = (!colours.is_empty()).then(|| {
+        let mut palette = DEFAULT_PALETTE;
+        for (entry, colour) in palette.iter_mut().zip(colours) {
+            *entry = colour.unwrap_or(*entry);
+        }
+        palette
+    });
+
+    RomSettings {
+        platform,
+        quirks,
+        clock_hz: rom["tickrate"]
+            .as_u64()
+            .filter(|&tickrate| tickrate > 0)
+            .map(|tickrate| tickrate as u32 * TIMER_FREQUENCY),
+        palette,
+    }
+}
+
+// Database platform ids, from platforms.json. CHIP-8X and MegaChip are not
+// emulated.
+fn platform_preset(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::Chip8, Quirks::COSMAC_VIP)),
+        "modernChip8" => Some((Platform::Chip8, Quirks::MODERN)),
+        "chip48" => Some((Platform::Chip8, Quirks::CHIP48)),
+        "superchip1" | "superchip" => Some((Platform::SuperChip, Quirks::SUPER_CHIP)),
+        "xochip" => Some((Platform::XoChip, Quirks::XO_CHIP)),
+        _ => None,
+    }
+}
+
+// Applies quirks.json flags. Note "shift" and "wrap" are the opposites of
+// our shift_uses_vy and clip_sprites.
+fn quirky_platform(mut quirks: Quirks, flags: &Value) -> Quirks {
+    let flag = |name: &str| flags[name].as_bool();
+    if let Some(shift) = flag("shift") {
+        quirks.shift_uses_vy = !shift;
+    }
+    match (flag("memoryIncrementByX"), flag("memoryLeaveIUnchanged")) {
+        (None, None) => {}
+        (_, Some(true)) => quirks.memory_increment = MemoryIncrement::None,
+        (Some(true), _) => quirks.memory_increment = MemoryIncrement::X,
+        _ => quirks.memory_increment = MemoryIncrement::XPlusOne,
+    }
+    if let Some(wrap) = flag("wrap") {
+        quirks.clip_sprites = !wrap;
+    }
+    if let Some(jump) = flag("jump") {
+        quirks.jump_uses_vx = jump;
+    }
+    if let Some(vblank) = flag("vblank") {
+        quirks.display_wait = vblank;
+    }
+    if let Some(logic) = flag("logic") {
+        quirks.logic_resets_vf = logic;
+    }
+    quirks
+}
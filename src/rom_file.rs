@@ -0,0 +1,599 @@
+use crate::assembler::{AssemblyError, assemble, assemble_file};
+use crate::chip8_machine::TIMER_FREQUENCY;
+use crate::chip8_state::MEMORY_SIZE;
+use crate::disassembly::PROGRAM_START;
+use crate::octo::{self, SourceMap};
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::scheduler::DEFAULT_CLOCK_HZ;
+use crate::screenshot::{DEFAULT_PALETTE, Palette};
+use serde_json::Value;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+const GIF_MAGIC: &[u8] = b"GIF8";
+// Far more than any cartridge needs: a 64 KiB program takes 256K pixels.
+const MAX_GIF_PIXELS: usize = 1 << 22;
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const ZIP_END_MAGIC: &[u8] = b"PK\x05\x06";
+const ZIP_ENTRY_MAGIC: &[u8] = b"PK\x01\x02";
+// Extensions picked out of a zip holding more than one file.
+const ROM_EXTENSIONS: [&str; 8] = ["ch8", "c8", "sc8", "xo8", "rom", "8o", "gif", "hex"];
+
+#[derive(Debug)]
+pub enum RomFileError {
+    InvalidGif(&'static str),
+    InvalidCartridge(String),
+    InvalidZip(&'static str),
+    // Number of candidate ROMs found in the archive.
+    ZipEntries(usize),
+    InvalidHex { line: usize, message: String },
+    Source(AssemblyError),
+}
+
+impl fmt::Display for RomFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomFileError::InvalidGif(what) => write!(f, "Invalid GIF: {what}"),
+            RomFileError::InvalidCartridge(what) => write!(f, "Invalid Octo cartridge: {what}"),
+            RomFileError::InvalidZip(what) => write!(f, "Invalid zip archive: {what}"),
+            RomFileError::ZipEntries(count) => {
+                write!(f, "Zip archive holds {count} ROMs, expected one")
+            }
+            RomFileError::InvalidHex { line, message } => write!(f, "Line {line}: {message}"),
+            RomFileError::Source(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for RomFileError {}
+
+impl From<AssemblyError> for RomFileError {
+    fn from(err: AssemblyError) -> Self {
+        RomFileError::Source(err)
+    }
+}
+
+impl From<RomFileError> for io::Error {
+    fn from(err: RomFileError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+// Machine configuration that comes with a ROM. Unset fields leave the choice
+// to whoever has lower priority, down to the defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RomSettings {
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub clock_hz: Option<u32>,
+    pub palette: Option<Palette>,
+}
+
+impl RomSettings {
+    // Fills the fields missing here from `fallback`.
+    pub fn or(self, fallback: RomSettings) -> RomSettings {
+        RomSettings {
+            platform: self.platform.or(fallback.platform),
+            quirks: self.quirks.or(fallback.quirks),
+            clock_hz: self.clock_hz.or(fallback.clock_hz),
+            palette: self.palette.or(fallback.palette),
+        }
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform.unwrap_or_default()
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks.unwrap_or(self.platform().default_quirks())
+    }
+
+    pub fn clock_hz(&self) -> u32 {
+        self.clock_hz.unwrap_or(DEFAULT_CLOCK_HZ)
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette.unwrap_or(DEFAULT_PALETTE)
+    }
+}
+
+// A ROM as it is loaded at 0x200, with whatever its file said about running it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LoadedRom {
+    pub rom: Vec<u8>,
+    pub settings: RomSettings,
+    // Set for ROMs compiled from Octo source.
+    pub source_map: Option<SourceMap>,
+}
+
+impl LoadedRom {
+    fn raw(rom: Vec<u8>) -> Self {
+        Self {
+            rom,
+            ..Self::default()
+        }
+    }
+}
+
+// Loads a raw binary, an Octo cartridge GIF, a zip with one ROM, Intel HEX or
+// a text hex dump (.hex, .ihx, .txt), Octo source (.8o) or assembly (.asm).
+pub fn load_rom_file<P: AsRef<Path>>(path: P) -> io::Result<LoadedRom> {
+    let path = path.as_ref();
+    // Sources are compiled from their path so includes and errors name files
+    match extension(&path.to_string_lossy()).as_str() {
+        "asm" => Ok(LoadedRom::raw(assemble_file(path)?)),
+        "8o" => {
+            let program = octo::compile_file(path)?;
+            Ok(LoadedRom {
+                rom: program.rom,
+                source_map: Some(program.source_map),
+                ..LoadedRom::default()
+            })
+        }
+        _ => Ok(decode_rom(&fs::read(path)?, &path.to_string_lossy())?),
+    }
+}
+
+// Decodes ROM file contents. GIFs and zips are recognized by their magic,
+// everything else by the extension of `name`.
+pub fn decode_rom(bytes: &[u8], name: &str) -> Result<LoadedRom, RomFileError> {
+    if bytes.starts_with(GIF_MAGIC) {
+        return decode_cartridge(bytes);
+    }
+    if bytes.starts_with(ZIP_MAGIC) {
+        let (name, contents) = unzip_rom(bytes)?;
+        return decode_rom(&contents, &name);
+    }
+
+    let text = || String::from_utf8_lossy(bytes);
+    match extension(name).as_str() {
+        "8o" => {
+            let program = octo::compile(&text())?;
+            Ok(LoadedRom {
+                rom: program.rom,
+                source_map: Some(program.source_map),
+                ..LoadedRom::default()
+            })
+        }
+        "asm" => Ok(LoadedRom::raw(assemble(&text())?)),
+        "hex" | "ihx" | "txt" => {
+            let text = text();
+            if text.trim_start().starts_with(':') {
+                parse_intel_hex(&text).map(LoadedRom::raw)
+            } else {
+                parse_hex_dump(&text).map(LoadedRom::raw)
+            }
+        }
+        _ => Ok(LoadedRom::raw(bytes.to_vec())),
+    }
+}
+
+fn extension(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+// Octo hides its cartridges in the low two bits of each pixel's colour index,
+// four pixels per byte, most significant first. The bytes are a big-endian
+// length and that much JSON holding the program source and its options.
+pub fn decode_cartridge(bytes: &[u8]) -> Result<LoadedRom, RomFileError> {
+    let invalid = |what: &str| RomFileError::InvalidCartridge(what.to_string());
+    let pixels: Vec<u8> = decode_gif(bytes)?.concat();
+    let payload: Vec<u8> = pixels
+        .chunks_exact(4)
+        .map(|pixels| pixels.iter().fold(0, |byte, pixel| byte << 2 | pixel & 3))
+        .collect();
+
+    let length = match payload.get(..4) {
+        Some(&[a, b, c, d]) => u32::from_be_bytes([a, b, c, d]) as usize,
+        _ => return Err(invalid("no payload")),
+    };
+    let json = payload
+        .get(4..4 + length)
+        .ok_or_else(|| invalid("payload is truncated"))?;
+    let json: Value = serde_json::from_slice(json)
+        .map_err(|err| RomFileError::InvalidCartridge(err.to_string()))?;
+    let source = json["program"]
+        .as_str()
+        .ok_or_else(|| invalid("no program"))?;
+
+    let program = octo::compile(source)?;
+    Ok(LoadedRom {
+        rom: program.rom,
+        settings: octo_settings(&json["options"]),
+        source_map: Some(program.source_map),
+    })
+}
+
+// Maps Octo's option names onto ours. Quirks Octo leaves out keep the
+// platform defaults.
+fn octo_settings(options: &Value) -> RomSettings {
+    // Octo's SUPER-CHIP preset uses a 3583 byte limit, XO-CHIP the whole 64K
+    let platform = options["maxSize"].as_u64().map(|size| match size {
+        3583 => Platform::SuperChip,
+        size if size > (MEMORY_SIZE - PROGRAM_START) as u64 => Platform::XoChip,
+        _ => Platform::Chip8,
+    });
+
+    let mut quirks = platform.unwrap_or_default().default_quirks();
+    let mut any_quirk = false;
+    let mut quirk = |name: &str, set: &mut dyn FnMut(bool)| {
+        if let Some(value) = options[name].as_bool() {
+            set(value);
+            any_quirk = true;
+        }
+    };
+    quirk("shiftQuirks", &mut |value| quirks.shift_uses_vy = !value);
+    quirk("loadStoreQuirks", &mut |value| {
+        quirks.memory_increment = match value {
+            true => MemoryIncrement::None,
+            false => MemoryIncrement::XPlusOne,
+        }
+    });
+    quirk("jumpQuirks", &mut |value| quirks.jump_uses_vx = value);
+    quirk("logicQuirks", &mut |value| quirks.logic_resets_vf = value);
+    quirk("clipQuirks", &mut |value| quirks.clip_sprites = value);
+    quirk("vBlankQuirks", &mut |value| quirks.display_wait = value);
+
+    let colour = |name: &str| {
+        let text = options[name].as_str()?;
+        u32::from_str_radix(text.strip_prefix('#').unwrap_or(text), 16).ok()
+    };
+    let colours = ["backgroundColor", "fillColor", "fillColor2", "blendColor"].map(colour);
+    let palette = colours.iter().any(Option::is_some).then(|| {
+        let mut palette = DEFAULT_PALETTE;
+        for (entry, colour) in palette.iter_mut().zip(colours) {
+            *entry = colour.unwrap_or(*entry);
+        }
+        palette
+    });
+
+    RomSettings {
+        platform,
+        quirks: any_quirk.then_some(quirks),
+        clock_hz: options["tickrate"]
+            .as_u64()
+            .filter(|&tickrate| tickrate > 0)
+            .map(|tickrate| tickrate as u32 * TIMER_FREQUENCY),
+        palette,
+    }
+}
+
+// The colour indices of every image in a GIF, in order.
+pub fn decode_gif(bytes: &[u8]) -> Result<Vec<Vec<u8>>, RomFileError> {
+    let truncated = || RomFileError::InvalidGif("file is truncated");
+    if !bytes.starts_with(GIF_MAGIC) || bytes.len() < 13 {
+        return Err(RomFileError::InvalidGif("bad header"));
+    }
+
+    let screen_width = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+    let screen_height = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    if screen_width * screen_height > MAX_GIF_PIXELS {
+        return Err(RomFileError::InvalidGif("image is too large"));
+    }
+
+    let mut position = 13;
+    let flags = bytes[10];
+    if flags & 0x80 != 0 {
+        position += 3 << ((flags & 7) + 1);
+    }
+
+    let mut images = Vec::new();
+    loop {
+        match bytes.get(position) {
+            // Trailer
+            Some(0x3B) => return Ok(images),
+            // Extension: a label and sub-blocks
+            Some(0x21) => {
+                (_, position) = read_sub_blocks(bytes, position + 2).ok_or_else(truncated)?;
+            }
+            Some(0x2C) => {
+                let descriptor = bytes
+                    .get(position + 1..position + 10)
+                    .ok_or_else(truncated)?;
+                let field = |index: usize| {
+                    u16::from_le_bytes([descriptor[index], descriptor[index + 1]]) as usize
+                };
+                let (left, top, width, height) = (field(0), field(2), field(4), field(6));
+                if left + width > screen_width || top + height > screen_height {
+                    return Err(RomFileError::InvalidGif("frame is outside the screen"));
+                }
+                let flags = descriptor[8];
+                position += 10;
+                if flags & 0x80 != 0 {
+                    position += 3 << ((flags & 7) + 1);
+                }
+
+                let min_code_size = *bytes.get(position).ok_or_else(truncated)?;
+                let (data, next) = read_sub_blocks(bytes, position + 1).ok_or_else(truncated)?;
+                position = next;
+                let mut pixels = lzw_decode(&data, min_code_size, width * height)?;
+                if pixels.len() != width * height {
+                    return Err(RomFileError::InvalidGif(
+                        "frame size does not match its data",
+                    ));
+                }
+                if flags & 0x40 != 0 {
+                    pixels = deinterlace(&pixels, width, height);
+                }
+                images.push(pixels);
+            }
+            Some(_) => return Err(RomFileError::InvalidGif("unknown block")),
+            None => return Err(truncated()),
+        }
+    }
+}
+
+// Joins the data sub-blocks starting at `position`, returning them and the
+// position after the terminator.
+fn read_sub_blocks(bytes: &[u8], mut position: usize) -> Option<(Vec<u8>, usize)> {
+    let mut data = Vec::new();
+    loop {
+        let length = *bytes.get(position)? as usize;
+        if length == 0 {
+            return Some((data, position + 1));
+        }
+        data.extend_from_slice(bytes.get(position + 1..position + 1 + length)?);
+        position += 1 + length;
+    }
+}
+
+// GIF's variable-width LZW, codes packed from the least significant bit.
+// Decoding more than `max_len` pixels is an error.
+fn lzw_decode(data: &[u8], min_code_size: u8, max_len: usize) -> Result<Vec<u8>, RomFileError> {
+    if !(2..=8).contains(&min_code_size) {
+        return Err(RomFileError::InvalidGif("bad LZW code size"));
+    }
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+    let initial_table = || -> Vec<Vec<u8>> {
+        let mut table: Vec<Vec<u8>> = (0..clear).map(|index| vec![index as u8]).collect();
+        table.extend([Vec::new(), Vec::new()]);
+        table
+    };
+
+    let mut table = initial_table();
+    let mut code_size = min_code_size as u32 + 1;
+    let mut previous: Option<usize> = None;
+    let mut output = Vec::new();
+    let (mut bits, mut bit_count) = (0u32, 0u32);
+    let mut bytes = data.iter();
+    loop {
+        while bit_count < code_size {
+            // Some encoders leave out the end code
+            let Some(&byte) = bytes.next() else {
+                return Ok(output);
+            };
+            bits |= (byte as u32) << bit_count;
+            bit_count += 8;
+        }
+        let code = (bits & ((1 << code_size) - 1)) as usize;
+        bits >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear {
+            table = initial_table();
+            code_size = min_code_size as u32 + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            return Ok(output);
+        }
+
+        let entry = match previous {
+            _ if code < table.len() => table[code].clone(),
+            Some(previous) if code == table.len() => {
+                let mut entry = table[previous].clone();
+                entry.push(entry[0]);
+                entry
+            }
+            _ => return Err(RomFileError::InvalidGif("bad LZW code")),
+        };
+        if let Some(previous) = previous
+            && table.len() < 4096
+        {
+            let mut added = table[previous].clone();
+            added.push(entry[0]);
+            table.push(added);
+        }
+        output.extend_from_slice(&entry);
+        if output.len() > max_len {
+            return Err(RomFileError::InvalidGif(
+                "frame size does not match its data",
+            ));
+        }
+        if table.len() == 1 << code_size && code_size < 12 {
+            code_size += 1;
+        }
+        previous = Some(code);
+    }
+}
+
+// Interlaced images store rows 0, 8, 16.., then 4, 12.., then 2, 6.., then
+// the odd rows.
+fn deinterlace(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let rows = [(0, 8), (4, 8), (2, 4), (1, 2)]
+        .into_iter()
+        .flat_map(|(start, step)| (start..height).step_by(step));
+    let mut output = vec![0; pixels.len()];
+    for (source, row) in rows.enumerate() {
+        output[row * width..(row + 1) * width]
+            .copy_from_slice(&pixels[source * width..(source + 1) * width]);
+    }
+    output
+}
+
+// Finds the one ROM in a zip archive through its central directory, returning
+// its name and contents. Directories and macOS metadata are skipped; with
+// several files left only those with a ROM extension count.
+pub fn unzip_rom(bytes: &[u8]) -> Result<(String, Vec<u8>), RomFileError> {
+    let truncated = || RomFileError::InvalidZip("archive is truncated");
+    let u16_at = |at: usize| {
+        bytes
+            .get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_at = |at: usize| {
+        bytes
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let end = (0..bytes.len().saturating_sub(21))
+        .rev()
+        .find(|&at| bytes[at..].starts_with(ZIP_END_MAGIC))
+        .ok_or(RomFileError::InvalidZip("no central directory"))?;
+    let count = u16_at(end + 10).ok_or_else(truncated)?;
+    let mut position = u32_at(end + 16).ok_or_else(truncated)? as usize;
+
+    // (name, method, compressed size, local header offset)
+    let mut files = Vec::new();
+    for _ in 0..count {
+        if !bytes[position.min(bytes.len())..].starts_with(ZIP_ENTRY_MAGIC) {
+            return Err(RomFileError::InvalidZip("bad central directory entry"));
+        }
+        let method = u16_at(position + 10).ok_or_else(truncated)?;
+        let size = u32_at(position + 20).ok_or_else(truncated)? as usize;
+        let name_length = u16_at(position + 28).ok_or_else(truncated)? as usize;
+        let extra_length = u16_at(position + 30).ok_or_else(truncated)? as usize;
+        let comment_length = u16_at(position + 32).ok_or_else(truncated)? as usize;
+        let offset = u32_at(position + 42).ok_or_else(truncated)? as usize;
+        let name = bytes
+            .get(position + 46..position + 46 + name_length)
+            .ok_or_else(truncated)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        if !name.ends_with('/') && !name.starts_with("__MACOSX/") {
+            files.push((name, method, size, offset));
+        }
+        position += 46 + name_length + extra_length + comment_length;
+    }
+
+    if files.len() > 1 {
+        files.retain(|(name, ..)| ROM_EXTENSIONS.contains(&extension(name).as_str()));
+    }
+    let [(name, method, size, offset)] =
+        <[_; 1]>::try_from(files).map_err(|files: Vec<_>| RomFileError::ZipEntries(files.len()))?;
+
+    let name_length = u16_at(offset + 26).ok_or_else(truncated)? as usize;
+    let extra_length = u16_at(offset + 28).ok_or_else(truncated)? as usize;
+    let start = offset + 30 + name_length + extra_length;
+    let data = bytes.get(start..start + size).ok_or_else(truncated)?;
+    let contents = match method {
+        0 => data.to_vec(),
+        8 => miniz_oxide::inflate::decompress_to_vec(data)
+            .map_err(|_| RomFileError::InvalidZip("bad deflate data"))?,
+        _ => return Err(RomFileError::InvalidZip("unsupported compression")),
+    };
+    Ok((name, contents))
+}
+
+// Intel HEX data, extended segment and linear addresses. Data placed at
+// 0x200 or above is taken as a memory image and moved to the ROM start.
+pub fn parse_intel_hex(text: &str) -> Result<Vec<u8>, RomFileError> {
+    // (line, address, data)
+    let mut segments: Vec<(usize, usize, Vec<u8>)> = Vec::new();
+    let mut base = 0;
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: &str| RomFileError::InvalidHex {
+            line: index + 1,
+            message: message.to_string(),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let digits = line
+            .strip_prefix(':')
+            .ok_or_else(|| error("record does not start with ':'"))?;
+        let record = hex_bytes(digits).ok_or_else(|| error("invalid hex digits"))?;
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(error("wrong record length"));
+        }
+        if record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(error("checksum mismatch"));
+        }
+
+        let address = u16::from_be_bytes([record[1], record[2]]) as usize;
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            0x00 => segments.push((index + 1, base + address, data.to_vec())),
+            0x01 => break,
+            0x02 | 0x04 if data.len() == 2 => {
+                let value = u16::from_be_bytes([data[0], data[1]]) as usize;
+                base = if record[3] == 0x02 {
+                    value << 4
+                } else {
+                    value << 16
+                };
+            }
+            // Start addresses mean nothing to CHIP-8
+            0x03 | 0x05 => {}
+            _ => return Err(error("unknown record type")),
+        }
+    }
+
+    let lowest = segments.iter().map(|(_, address, _)| *address).min();
+    let origin = match lowest {
+        Some(address) if address >= PROGRAM_START => PROGRAM_START,
+        _ => 0,
+    };
+    let mut rom = Vec::new();
+    for (line, address, data) in segments {
+        let start = address - origin;
+        if start + data.len() > 0x10000 {
+            return Err(RomFileError::InvalidHex {
+                line,
+                message: format!("address {address:#X} is out of range"),
+            });
+        }
+        if rom.len() < start + data.len() {
+            rom.resize(start + data.len(), 0);
+        }
+        rom[start..start + data.len()].copy_from_slice(&data);
+    }
+    Ok(rom)
+}
+
+// Hex bytes separated by whitespace or commas, with an optional 0x prefix;
+// `#` and `;` start comments. A line starting with `offset:` is read like
+// xxd output, where the hex ends at the double space before the text column.
+pub fn parse_hex_dump(text: &str) -> Result<Vec<u8>, RomFileError> {
+    let mut rom = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let mut line = line.split(['#', ';']).next().unwrap_or_default().trim();
+        if let Some((offset, rest)) = line.split_once(':')
+            && !offset.contains(char::is_whitespace)
+            && hex_bytes(offset.strip_prefix("0x").unwrap_or(offset)).is_some()
+        {
+            let rest = rest.trim_start();
+            line = rest.split_once("  ").map_or(rest, |(hex, _)| hex);
+        }
+
+        for token in line
+            .split([' ', '\t', ','])
+            .filter(|token| !token.is_empty())
+        {
+            let digits = token.strip_prefix("0x").unwrap_or(token);
+            let bytes = hex_bytes(digits).ok_or_else(|| RomFileError::InvalidHex {
+                line: index + 1,
+                message: format!("invalid hex: {token}"),
+            })?;
+            rom.extend(bytes);
+        }
+    }
+    Ok(rom)
+}
+
+fn hex_bytes(digits: &str) -> Option<Vec<u8>> {
+    if digits.is_empty() || !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(&digits[at..at + 2], 16).ok())
+        .collect()
+}
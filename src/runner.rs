@@ -0,0 +1,160 @@
+use crate::chip8_machine::{Chip8MachineState, KEYBOARD_SIZE};
+use crate::error::Chip8Error;
+use std::collections::BTreeSet;
+use std::fmt;
+
+// A scripted keypad change at the start of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: usize,
+    pub pressed: bool,
+}
+
+// Parses `<frame>:<key>+` (press) and `<frame>:<key>-` (release) entries,
+// separated by whitespace or commas, e.g. "30:5+ 45:5-". Keys are hex digits
+// and `#` starts a comment running to the end of the line.
+pub fn parse_key_script(text: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut events = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        for entry in line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|entry| !entry.is_empty())
+        {
+            let invalid = || format!("Line {}: invalid key event: {entry}", number + 1);
+            let (frame, key) = entry.split_once(':').ok_or_else(invalid)?;
+            let (key, pressed) = match (key.strip_suffix('+'), key.strip_suffix('-')) {
+                (Some(key), _) => (key, true),
+                (_, Some(key)) => (key, false),
+                _ => return Err(invalid()),
+            };
+
+            events.push(KeyEvent {
+                frame: frame.parse().map_err(|_| invalid())?,
+                key: usize::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|&key| key < KEYBOARD_SIZE)
+                    .ok_or_else(invalid)?,
+                pressed,
+            });
+        }
+    }
+
+    // Stable, so events on the same frame keep their order
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    CycleLimit,
+    FrameLimit,
+    // A `JP` to its own address, the usual way for a test ROM to finish.
+    SelfJump(u16),
+    Address(u16),
+    Halted,
+    Fault(Chip8Error),
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunOutcome::CycleLimit => write!(f, "cycle limit reached"),
+            RunOutcome::FrameLimit => write!(f, "frame limit reached"),
+            RunOutcome::SelfJump(address) => write!(f, "jump to self at {:#05X}", address),
+            RunOutcome::Address(address) => write!(f, "reached {:#05X}", address),
+            RunOutcome::Halted => write!(f, "program exited"),
+            RunOutcome::Fault(err) => write!(f, "crashed: {err}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunReport {
+    pub outcome: RunOutcome,
+    // Instructions executed and frames completed by this run.
+    pub cycles: u64,
+    pub frames: u64,
+}
+
+// Runs a machine without a display or real time, for tests and CI. A frame is
+// one 60 Hz timer tick, i.e. `cycles_per_timer_tick` instructions. Without a
+// cycle or frame limit it runs until another stop condition is met.
+#[derive(Debug, Clone, Default)]
+pub struct Runner {
+    pub max_cycles: Option<u64>,
+    pub max_frames: Option<u64>,
+    pub stop_on_self_jump: bool,
+    // Stops before executing an instruction at one of these addresses.
+    pub stop_addresses: BTreeSet<u16>,
+    pub keys: Vec<KeyEvent>,
+}
+
+impl Runner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn run(&self, chip8: &mut Chip8MachineState) -> RunReport {
+        let start_cycles = chip8.cycles;
+        let mut frames = 0;
+        let mut keys = self.keys.iter().peekable();
+        let mut frame_started = false;
+
+        let outcome = loop {
+            if !frame_started {
+                if self.max_frames.is_some_and(|max| frames >= max) {
+                    break RunOutcome::FrameLimit;
+                }
+                while let Some(event) = keys.next_if(|event| event.frame <= frames) {
+                    chip8.set_key(event.key, event.pressed);
+                }
+                frame_started = true;
+            }
+
+            if self
+                .max_cycles
+                .is_some_and(|max| chip8.cycles - start_cycles >= max)
+            {
+                break RunOutcome::CycleLimit;
+            }
+            if chip8.halted {
+                break RunOutcome::Halted;
+            }
+
+            let pc = chip8.state.pc;
+            if self.stop_addresses.contains(&pc) {
+                break RunOutcome::Address(pc);
+            }
+            if self.stop_on_self_jump && is_self_jump(chip8, pc) {
+                break RunOutcome::SelfJump(pc);
+            }
+
+            if let Err(err) = chip8.execute_cycle() {
+                break RunOutcome::Fault(err);
+            }
+            if chip8.timer_cycles == 0 {
+                frames += 1;
+                frame_started = false;
+            }
+        };
+
+        RunReport {
+            outcome,
+            cycles: chip8.cycles - start_cycles,
+            frames,
+        }
+    }
+}
+
+fn is_self_jump(chip8: &Chip8MachineState, pc: u16) -> bool {
+    let memory = &chip8.state.memory;
+    let address = pc as usize;
+
+    match (memory.get(address), memory.get(address + 1)) {
+        (Some(&high), Some(&low)) => u16::from_be_bytes([high, low]) == 0x1000 | pc,
+        _ => false,
+    }
+}
@@ -0,0 +1,382 @@
+use crate::chip8_machine::{
+    Chip8MachineState, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, KEYBOARD_SIZE, SCREEN_HEIGHT,
+    SCREEN_WIDTH,
+};
+use crate::chip8_state::{
+    AUDIO_PATTERN_SIZE, BIG_FONT_SIZE, FONT_SIZE, RPL_SIZE, STACK_SIZE, V_SIZE,
+};
+use crate::framebuffer::FrameBuffer;
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::random::RandomSource;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::{fmt, fs, io, path::Path};
+
+pub const SAVE_STATE_MAGIC: &[u8; 4] = b"C8SS";
+// Version 1 always stored a ChaCha8 generator, version 2 tags the random source.
+pub const SAVE_STATE_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "Not a CHIP-8 save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save state version {version}")
+            }
+            SaveStateError::Truncated => write!(f, "Save state is truncated"),
+            SaveStateError::Invalid(what) => write!(f, "Invalid save state: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<SaveStateError> for io::Error {
+    fn from(err: SaveStateError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+// Little-endian encoding of a snapshot, fields in the order they are read back.
+pub(crate) struct Writer {
+    pub bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u128(&mut self, value: u128) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.bytes.extend_from_slice(value);
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    pub bytes: &'a [u8],
+    pub position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self.position + len;
+        let slice = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(SaveStateError::Truncated)?;
+
+        self.position = end;
+        Ok(slice)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid("boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn u128(&mut self) -> Result<u128, SaveStateError> {
+        Ok(u128::from_le_bytes(self.array()?))
+    }
+}
+
+pub(crate) fn platform_to_u8(platform: Platform) -> u8 {
+    match platform {
+        Platform::Chip8 => 0,
+        Platform::SuperChip => 1,
+        Platform::XoChip => 2,
+    }
+}
+
+pub(crate) fn platform_from_u8(value: u8) -> Result<Platform, SaveStateError> {
+    match value {
+        0 => Ok(Platform::Chip8),
+        1 => Ok(Platform::SuperChip),
+        2 => Ok(Platform::XoChip),
+        _ => Err(SaveStateError::Invalid("platform")),
+    }
+}
+
+fn memory_increment_to_u8(increment: MemoryIncrement) -> u8 {
+    match increment {
+        MemoryIncrement::None => 0,
+        MemoryIncrement::X => 1,
+        MemoryIncrement::XPlusOne => 2,
+    }
+}
+
+fn memory_increment_from_u8(value: u8) -> Result<MemoryIncrement, SaveStateError> {
+    match value {
+        0 => Ok(MemoryIncrement::None),
+        1 => Ok(MemoryIncrement::X),
+        2 => Ok(MemoryIncrement::XPlusOne),
+        _ => Err(SaveStateError::Invalid("memory increment quirk")),
+    }
+}
+
+fn write_chacha(writer: &mut Writer, random: &ChaCha8Rng) {
+    writer.bytes(&random.get_seed());
+    writer.u64(random.get_stream());
+    writer.u128(random.get_word_pos());
+}
+
+fn read_chacha(reader: &mut Reader) -> Result<ChaCha8Rng, SaveStateError> {
+    let mut random = ChaCha8Rng::from_seed(reader.array()?);
+    random.set_stream(reader.u64()?);
+    random.set_word_pos(reader.u128()?);
+    Ok(random)
+}
+
+pub(crate) fn write_quirks(writer: &mut Writer, quirks: &Quirks) {
+    writer.bool(quirks.shift_uses_vy);
+    writer.u8(memory_increment_to_u8(quirks.memory_increment));
+    writer.bool(quirks.jump_uses_vx);
+    writer.bool(quirks.logic_resets_vf);
+    writer.bool(quirks.clip_sprites);
+    writer.bool(quirks.display_wait);
+}
+
+pub(crate) fn read_quirks(reader: &mut Reader) -> Result<Quirks, SaveStateError> {
+    Ok(Quirks {
+        shift_uses_vy: reader.bool()?,
+        memory_increment: memory_increment_from_u8(reader.u8()?)?,
+        jump_uses_vx: reader.bool()?,
+        logic_resets_vf: reader.bool()?,
+        clip_sprites: reader.bool()?,
+        display_wait: reader.bool()?,
+    })
+}
+
+// A tag, then the state of the source.
+pub(crate) fn write_random(writer: &mut Writer, random: &RandomSource) {
+    match random {
+        RandomSource::ChaCha(random) => {
+            writer.u8(0);
+            write_chacha(writer, random);
+        }
+        RandomSource::Scripted { bytes, position } => {
+            writer.u8(1);
+            writer.u32(bytes.len() as u32);
+            writer.bytes(bytes);
+            writer.u32(*position as u32);
+        }
+    }
+}
+
+pub(crate) fn read_random(reader: &mut Reader) -> Result<RandomSource, SaveStateError> {
+    match reader.u8()? {
+        0 => Ok(RandomSource::ChaCha(Box::new(read_chacha(reader)?))),
+        1 => {
+            let len = reader.u32()? as usize;
+            let bytes = reader.take(len)?.to_vec();
+            let position = reader.u32()? as usize;
+            if position > bytes.len() {
+                return Err(SaveStateError::Invalid("random sequence position"));
+            }
+            Ok(RandomSource::Scripted { bytes, position })
+        }
+        _ => Err(SaveStateError::Invalid("random source")),
+    }
+}
+
+impl Chip8MachineState {
+    // Serializes everything needed to resume emulation, including the RNG
+    // stream position, so a restored state replays identically.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.save_state_with_keypad(&self.keyboard)
+    }
+
+    // A snapshot with `keyboard` in place of the live keypad.
+    pub(crate) fn save_state_with_keypad(&self, keyboard: &[bool; KEYBOARD_SIZE]) -> Vec<u8> {
+        let mut writer = Writer { bytes: Vec::new() };
+        let state = &self.state;
+
+        writer.bytes(SAVE_STATE_MAGIC);
+        writer.u16(SAVE_STATE_VERSION);
+
+        writer.u8(platform_to_u8(self.platform));
+        write_quirks(&mut writer, &self.quirks);
+
+        writer.u64(self.cycles);
+        writer.u32(self.cycles_per_timer_tick);
+        writer.u32(self.timer_cycles);
+        writer.bool(self.halted);
+        writer.u8(self.planes);
+
+        writer.u16(self.display.width() as u16);
+        writer.u16(self.display.height() as u16);
+        writer.bytes(self.display.pixels());
+        keyboard.iter().for_each(|&key| writer.bool(key));
+
+        writer.bytes(&state.v);
+        writer.u16(state.i);
+        writer.u8(state.delay_timer);
+        writer.u8(state.sound_timer);
+        writer.u16(state.pc);
+        writer.u8(state.sp);
+        state.stack.iter().for_each(|&address| writer.u16(address));
+        writer.u32(state.memory.len() as u32);
+        writer.bytes(&state.memory);
+        writer.u16(state.font_address);
+        writer.u16(state.big_font_address);
+        writer.bytes(&state.rpl);
+        writer.bool(state.audio_pattern.is_some());
+        writer.bytes(&state.audio_pattern.unwrap_or_default());
+        writer.u8(state.pitch);
+
+        write_random(&mut writer, &self.random);
+
+        writer.bytes
+    }
+
+    // Restores a snapshot from `save_state`. On error the machine is unchanged.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(SAVE_STATE_MAGIC.len())? != SAVE_STATE_MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        if version == 0 || version > SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        let platform = platform_from_u8(reader.u8()?)?;
+        let quirks = read_quirks(&mut reader)?;
+
+        let mut machine = Chip8MachineState::with_platform(platform, quirks);
+        machine.cycles = reader.u64()?;
+        machine.cycles_per_timer_tick = reader.u32()?;
+        machine.timer_cycles = reader.u32()?;
+        machine.halted = reader.bool()?;
+        machine.planes = reader.u8()?;
+
+        let width = reader.u16()? as usize;
+        let height = reader.u16()? as usize;
+        // The frame buffer is always lores or hires, hires only from SUPER-CHIP on
+        let hires = platform.has_super_chip_instructions()
+            && (width, height) == (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT);
+        if (width, height) != (SCREEN_WIDTH, SCREEN_HEIGHT) && !hires {
+            return Err(SaveStateError::Invalid("display size"));
+        }
+        let pixels = reader.take(width * height)?;
+        machine.display = FrameBuffer::new(width, height);
+        for (y, row) in pixels.chunks(width).enumerate() {
+            for (x, &planes) in row.iter().enumerate() {
+                machine.display.set_planes(x, y, planes);
+            }
+        }
+        for key in 0..KEYBOARD_SIZE {
+            machine.keyboard[key] = reader.bool()?;
+        }
+
+        let state = &mut machine.state;
+        state.v = reader.array::<V_SIZE>()?;
+        state.i = reader.u16()?;
+        state.delay_timer = reader.u8()?;
+        state.sound_timer = reader.u8()?;
+        state.pc = reader.u16()?;
+        state.sp = reader.u8()?;
+        if state.sp as usize > STACK_SIZE {
+            return Err(SaveStateError::Invalid("stack pointer"));
+        }
+        for index in 0..STACK_SIZE {
+            state.stack[index] = reader.u16()?;
+        }
+        let memory_size = reader.u32()? as usize;
+        if memory_size != platform.memory_size() {
+            return Err(SaveStateError::Invalid("memory size"));
+        }
+        state.memory = reader.take(memory_size)?.to_vec();
+        state.font_address = reader.u16()?;
+        state.big_font_address = reader.u16()?;
+        if state.font_address as usize + FONT_SIZE > memory_size
+            || state.big_font_address as usize + BIG_FONT_SIZE > memory_size
+        {
+            return Err(SaveStateError::Invalid("font address"));
+        }
+        state.rpl = reader.array::<RPL_SIZE>()?;
+        let has_audio_pattern = reader.bool()?;
+        let audio_pattern = reader.array::<AUDIO_PATTERN_SIZE>()?;
+        state.audio_pattern = has_audio_pattern.then_some(audio_pattern);
+        state.pitch = reader.u8()?;
+
+        machine.random = if version == 1 {
+            RandomSource::ChaCha(Box::new(read_chacha(&mut reader)?))
+        } else {
+            read_random(&mut reader)?
+        };
+
+        if reader.position != bytes.len() {
+            return Err(SaveStateError::Invalid("trailing data"));
+        }
+
+        // Watchpoints and tracing belong to the session, not the snapshot
+        machine.watchpoints = std::mem::take(&mut self.watchpoints);
+        machine.tracer = self.tracer.take();
+        *self = machine;
+        Ok(())
+    }
+
+    pub fn save_state_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.save_state())
+    }
+
+    pub fn load_state_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        self.load_state(&bytes)?;
+        Ok(())
+    }
+}
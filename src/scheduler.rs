@@ -0,0 +1,99 @@
+use crate::chip8_machine::{Chip8MachineState, TIMER_FREQUENCY};
+use crate::error::Chip8Error;
+
+pub const DEFAULT_CLOCK_HZ: u32 = 700;
+pub const DEFAULT_FAST_FORWARD_FACTOR: u32 = 4;
+pub const DEFAULT_SLOW_MOTION_DIVISOR: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedMode {
+    Normal,
+    Paused,
+    FastForward,
+    SlowMotion,
+}
+
+// Paces the CPU against displayed frames. Every frame is one 60 Hz timer tick
+// of emulated time, so the timers stay in step with the CPU in every mode.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    pub clock_hz: u32,
+    pub mode: SpeedMode,
+    pub fast_forward_factor: u32,
+    pub slow_motion_divisor: u32,
+    slow_motion_frames: u32,
+}
+
+impl Scheduler {
+    pub fn new(clock_hz: u32) -> Self {
+        Self {
+            clock_hz,
+            mode: SpeedMode::Normal,
+            fast_forward_factor: DEFAULT_FAST_FORWARD_FACTOR,
+            slow_motion_divisor: DEFAULT_SLOW_MOTION_DIVISOR,
+            slow_motion_frames: 0,
+        }
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        ((self.clock_hz + TIMER_FREQUENCY / 2) / TIMER_FREQUENCY).max(1)
+    }
+
+    pub fn set_mode(&mut self, mode: SpeedMode) {
+        self.mode = mode;
+        self.slow_motion_frames = 0;
+    }
+
+    // Switches to `mode`, or back to normal speed if it is already active.
+    pub fn toggle_mode(&mut self, mode: SpeedMode) {
+        if self.mode == mode {
+            self.set_mode(SpeedMode::Normal);
+        } else {
+            self.set_mode(mode);
+        }
+    }
+
+    // Runs one displayed frame and returns the number of emulated timer ticks.
+    pub fn run_frame(&mut self, chip8: &mut Chip8MachineState) -> Result<u32, Chip8Error> {
+        self.run_frame_with(chip8, |_| {})
+    }
+
+    // Like `run_frame`, calling `before_tick` before every emulated timer tick,
+    // e.g. to record or replay the keypad.
+    pub fn run_frame_with<F: FnMut(&mut Chip8MachineState)>(
+        &mut self,
+        chip8: &mut Chip8MachineState,
+        mut before_tick: F,
+    ) -> Result<u32, Chip8Error> {
+        chip8.cycles_per_timer_tick = self.instructions_per_frame();
+
+        let ticks = match self.mode {
+            SpeedMode::Normal => 1,
+            SpeedMode::Paused => 0,
+            SpeedMode::FastForward => self.fast_forward_factor.max(1),
+            SpeedMode::SlowMotion => {
+                self.slow_motion_frames += 1;
+
+                if self.slow_motion_frames >= self.slow_motion_divisor.max(1) {
+                    self.slow_motion_frames = 0;
+                    1
+                } else {
+                    0
+                }
+            }
+        };
+
+        for _ in 0..ticks {
+            before_tick(chip8);
+            chip8.run_timer_tick()?;
+        }
+
+        Ok(ticks)
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_CLOCK_HZ)
+    }
+}
@@ -0,0 +1,252 @@
+use crate::framebuffer::FrameBuffer;
+use std::{fs, io, path::Path};
+
+// RGB colours by lit XO-CHIP planes: none, first, second, both.
+pub type Palette = [u32; 4];
+
+pub const DEFAULT_PALETTE: Palette = [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555];
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+// Largest payload of a stored (uncompressed) deflate block.
+const DEFLATE_BLOCK_SIZE: usize = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotFormat {
+    Png,
+    Ppm,
+    // One character per pixel: "#" lit, "." dark, other planes as a digit.
+    Ascii,
+    // Half-block characters, two pixel rows per line of text.
+    Unicode,
+}
+
+impl ScreenshotFormat {
+    pub fn from_name(name: &str) -> Option<ScreenshotFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(ScreenshotFormat::Png),
+            "ppm" => Some(ScreenshotFormat::Ppm),
+            "ascii" | "txt" | "text" => Some(ScreenshotFormat::Ascii),
+            "unicode" | "blocks" => Some(ScreenshotFormat::Unicode),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ScreenshotFormat::Png => "png",
+            ScreenshotFormat::Ppm => "ppm",
+            ScreenshotFormat::Ascii | ScreenshotFormat::Unicode => "txt",
+        }
+    }
+
+    // Guesses the format from a file extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ScreenshotFormat> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        ScreenshotFormat::from_name(extension)
+    }
+}
+
+// Parses up to four comma-separated RGB hex colours, e.g. "000000,00FF00".
+// Missing entries keep their default colour.
+pub fn parse_palette(text: &str) -> Result<Palette, String> {
+    let mut palette = DEFAULT_PALETTE;
+    let colours: Vec<&str> = text.split(',').map(str::trim).collect();
+    if colours.len() > palette.len() {
+        return Err(format!("Too many palette colours: {text}"));
+    }
+
+    for (entry, colour) in palette.iter_mut().zip(colours) {
+        let digits = colour.trim_start_matches('#');
+        *entry = match u32::from_str_radix(digits, 16) {
+            Ok(value) if digits.len() == 6 => value,
+            _ => return Err(format!("Invalid colour: {colour}")),
+        };
+    }
+
+    Ok(palette)
+}
+
+// Encodes the display in `format`. `scale` and `palette` only apply to images.
+pub fn screenshot(
+    display: &FrameBuffer,
+    format: ScreenshotFormat,
+    scale: usize,
+    palette: &Palette,
+) -> Vec<u8> {
+    match format {
+        ScreenshotFormat::Png => encode_png(display, scale, palette),
+        ScreenshotFormat::Ppm => encode_ppm(display, scale, palette),
+        ScreenshotFormat::Ascii => ascii(display).into_bytes(),
+        ScreenshotFormat::Unicode => unicode(display).into_bytes(),
+    }
+}
+
+pub fn save_screenshot<P: AsRef<Path>>(
+    display: &FrameBuffer,
+    path: P,
+    format: ScreenshotFormat,
+    scale: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    fs::write(path, screenshot(display, format, scale, palette))
+}
+
+// Palette indexes of the scaled image, row by row.
+fn scaled_rows(display: &FrameBuffer, scale: usize) -> impl Iterator<Item = Vec<u8>> + '_ {
+    let scale = scale.max(1);
+    display.rows().flat_map(move |row| {
+        let scaled: Vec<u8> = row
+            .iter()
+            .flat_map(|&planes| std::iter::repeat_n(planes, scale))
+            .collect();
+        std::iter::repeat_n(scaled, scale)
+    })
+}
+
+// Binary PPM (P6).
+pub fn encode_ppm(display: &FrameBuffer, scale: usize, palette: &Palette) -> Vec<u8> {
+    let scale = scale.max(1);
+    let mut bytes = format!(
+        "P6\n{} {}\n255\n",
+        display.width() * scale,
+        display.height() * scale
+    )
+    .into_bytes();
+
+    for row in scaled_rows(display, scale) {
+        for planes in row {
+            bytes.extend_from_slice(&palette[planes as usize].to_be_bytes()[1..]);
+        }
+    }
+
+    bytes
+}
+
+// 8-bit indexed PNG. The image data is stored uncompressed, so the output only
+// depends on the pixels and compares byte-for-byte.
+pub fn encode_png(display: &FrameBuffer, scale: usize, palette: &Palette) -> Vec<u8> {
+    let scale = scale.max(1);
+    let mut bytes = PNG_SIGNATURE.to_vec();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&((display.width() * scale) as u32).to_be_bytes());
+    header.extend_from_slice(&((display.height() * scale) as u32).to_be_bytes());
+    // Bit depth 8, indexed colour, default compression, filter and interlace
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    write_chunk(&mut bytes, b"IHDR", &header);
+
+    let colours: Vec<u8> = palette
+        .iter()
+        .flat_map(|colour| colour.to_be_bytes()[1..].to_vec())
+        .collect();
+    write_chunk(&mut bytes, b"PLTE", &colours);
+
+    // Every row starts with filter type 0 (none)
+    let mut image = Vec::new();
+    for row in scaled_rows(display, scale) {
+        image.push(0);
+        image.extend_from_slice(&row);
+    }
+    write_chunk(&mut bytes, b"IDAT", &zlib_stored(&image));
+    write_chunk(&mut bytes, b"IEND", &[]);
+
+    bytes
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = bytes.len();
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(data);
+    let crc = crc32(&bytes[start..]);
+    bytes.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x78, 0x01];
+    let mut blocks = data.chunks(DEFLATE_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        bytes.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        bytes.push(last as u8);
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(&(!len).to_le_bytes());
+        bytes.extend_from_slice(block);
+    }
+
+    bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    bytes
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// One line per row, "#" for lit pixels and "." for dark ones. Pixels lit on
+// other XO-CHIP planes show their plane mask instead.
+pub fn ascii(display: &FrameBuffer) -> String {
+    display
+        .rows()
+        .map(|row| {
+            let mut line: String = row
+                .iter()
+                .map(|&planes| match planes {
+                    0 => '.',
+                    1 => '#',
+                    planes => char::from_digit(planes as u32, 16).unwrap_or('?'),
+                })
+                .collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+// Two pixel rows per line using "▀", "▄" and "█". Any lit plane counts as lit.
+pub fn unicode(display: &FrameBuffer) -> String {
+    let rows: Vec<&[u8]> = display.rows().collect();
+
+    rows.chunks(2)
+        .map(|pair| {
+            let mut line: String = (0..display.width())
+                .map(|x| {
+                    let top = pair[0][x] != 0;
+                    let bottom = pair.get(1).is_some_and(|row| row[x] != 0);
+                    match (top, bottom) {
+                        (false, false) => ' ',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (true, true) => '█',
+                    }
+                })
+                .collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
@@ -0,0 +1,272 @@
+use crate::frontend::{Frontend, Hotkey};
+use chip8::tui::render_half_blocks;
+use chip8::{Chip8MachineState, DEFAULT_PALETTE, KEYBOARD_SIZE, KeyHold, Palette};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::Print;
+use crossterm::terminal::{
+    self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen, SetTitle,
+};
+use crossterm::{cursor, execute, queue};
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+const FRAME_DURATION: Duration = Duration::from_micros(16600);
+// How long a message stays on the status line, three seconds
+const MESSAGE_FRAMES: u32 = 180;
+// Same layout as the window
+const KEY_MAP: [(char, usize); KEYBOARD_SIZE] = [
+    ('x', 0x0),
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('z', 0xA),
+    ('c', 0xB),
+    ('4', 0xC),
+    ('r', 0xD),
+    ('f', 0xE),
+    ('v', 0xF),
+];
+const FAST_FORWARD: usize = 0;
+const REWIND: usize = 1;
+
+// Draws the screen with half blocks, two pixel rows per line, and reads the
+// keypad from key presses. Esc or Ctrl+C quits.
+pub struct Terminal {
+    stdout: Stdout,
+    keys: KeyHold,
+    // Fast-forward and rewind, held like the keypad.
+    speed_keys: KeyHold<2>,
+    hotkeys: Vec<Hotkey>,
+    palette: Palette,
+    title: String,
+    // Shown on the status line instead of the help for `message_frames` more
+    // frames, as anything printed would end up over the screen.
+    message: String,
+    message_frames: u32,
+    // What was drawn last, to skip unchanged frames.
+    last_frame: String,
+    width: usize,
+    next_frame: Instant,
+    // The terminal reports releases, so holding keys is not emulated.
+    enhanced: bool,
+    open: bool,
+}
+
+impl Terminal {
+    pub fn new(title: &str, hold_frames: u32) -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(
+            stdout,
+            EnterAlternateScreen,
+            cursor::Hide,
+            Clear(ClearType::All)
+        )?;
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        let hold_frames = if enhanced { u32::MAX } else { hold_frames };
+        let mut terminal = Self {
+            stdout,
+            keys: KeyHold::new(hold_frames),
+            speed_keys: KeyHold::new(hold_frames),
+            hotkeys: Vec::new(),
+            palette: DEFAULT_PALETTE,
+            title: String::new(),
+            message: String::new(),
+            message_frames: 0,
+            last_frame: String::new(),
+            width: 0,
+            next_frame: Instant::now(),
+            enhanced,
+            open: true,
+        };
+        terminal.set_title(title);
+        Ok(terminal)
+    }
+
+    fn read_events(&mut self) -> io::Result<()> {
+        self.hotkeys.clear();
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) => self.key(key),
+                // Everything has to be drawn again
+                Event::Resize(..) => {
+                    self.last_frame.clear();
+                    execute!(self.stdout, Clear(ClearType::All))?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        let pressed = key.kind != KeyEventKind::Release;
+        match key.code {
+            KeyCode::Esc => self.open = false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.open = false
+            }
+            KeyCode::Tab => hold(&mut self.speed_keys, FAST_FORWARD, pressed),
+            KeyCode::Char('`') => hold(&mut self.speed_keys, REWIND, pressed),
+            KeyCode::Char(c) => {
+                let c = c.to_ascii_lowercase();
+                if let Some((_, index)) = KEY_MAP.iter().find(|(key, _)| *key == c) {
+                    hold(&mut self.keys, *index, pressed);
+                } else if c == ' ' && key.kind == KeyEventKind::Press {
+                    self.hotkeys.push(Hotkey::TogglePause);
+                }
+            }
+            code if key.kind == KeyEventKind::Press => {
+                let hotkey = match code {
+                    KeyCode::Backspace => Hotkey::ToggleSlowMotion,
+                    KeyCode::F(2) => Hotkey::Screenshot,
+                    KeyCode::F(5) => Hotkey::SaveState,
+                    KeyCode::F(9) => Hotkey::LoadState,
+                    KeyCode::F(12) => Hotkey::Break,
+                    _ => return,
+                };
+                self.hotkeys.push(hotkey);
+            }
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, chip8: &Chip8MachineState) -> io::Result<()> {
+        let frame = render_half_blocks(&chip8.display, &self.palette);
+        if frame == self.last_frame {
+            return Ok(());
+        }
+        // Switching between lores and hires leaves wider lines behind
+        if chip8.display.width() != self.width {
+            self.width = chip8.display.width();
+            queue!(self.stdout, Clear(ClearType::All))?;
+        }
+
+        let status = match (self.message_frames > 0, self.enhanced) {
+            (true, _) => format!("{} | {}", self.title, self.message),
+            (false, true) => format!("{} | Esc quits", self.title),
+            (false, false) => format!(
+                "{} | Esc quits, keys are held briefly per press",
+                self.title
+            ),
+        };
+        queue!(
+            self.stdout,
+            cursor::MoveTo(0, 0),
+            Print(&frame),
+            Clear(ClearType::UntilNewLine),
+            Print(status),
+            Clear(ClearType::UntilNewLine)
+        )?;
+        self.stdout.flush()?;
+        self.last_frame = frame;
+        Ok(())
+    }
+
+    // Sleeps until the next frame is due, catching up without bursts after a
+    // slow one.
+    fn wait_for_frame(&mut self) {
+        let now = Instant::now();
+        if self.next_frame > now {
+            std::thread::sleep(self.next_frame - now);
+            self.next_frame += FRAME_DURATION;
+        } else {
+            self.next_frame = now + FRAME_DURATION;
+        }
+    }
+}
+
+fn hold<const N: usize>(keys: &mut KeyHold<N>, index: usize, pressed: bool) {
+    match pressed {
+        true => keys.press(index),
+        false => keys.release(index),
+    }
+}
+
+impl Frontend for Terminal {
+    fn update(&mut self, chip8: &mut Chip8MachineState) -> bool {
+        self.keys.next_frame();
+        self.speed_keys.next_frame();
+        if self.read_events().is_err() {
+            self.open = false;
+        }
+        if !self.open {
+            return false;
+        }
+
+        for key in 0..KEYBOARD_SIZE {
+            chip8.set_key(key, self.keys.is_down(key));
+        }
+        if self.message_frames > 0 {
+            self.message_frames -= 1;
+            if self.message_frames == 0 {
+                self.last_frame.clear();
+            }
+        }
+        if self.draw(chip8).is_err() {
+            return false;
+        }
+        self.wait_for_frame();
+        true
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+        // Redraw for the status line
+        self.last_frame.clear();
+        let _ = execute!(self.stdout, SetTitle(title));
+    }
+
+    fn show_message(&mut self, message: &str) {
+        self.message = message.to_string();
+        self.message_frames = MESSAGE_FRAMES;
+        self.last_frame.clear();
+    }
+
+    fn hotkeys(&self) -> Vec<Hotkey> {
+        self.hotkeys.clone()
+    }
+
+    fn fast_forward_held(&self) -> bool {
+        self.speed_keys.is_down(FAST_FORWARD)
+    }
+
+    fn rewind_held(&self) -> bool {
+        self.speed_keys.is_down(REWIND)
+    }
+
+    fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.last_frame.clear();
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.enhanced {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.stdout, cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
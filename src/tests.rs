@@ -1,11 +1,46 @@
 #[cfg(test)]
 mod tests {
-    use rand::SeedableRng;
-    use rand_chacha::ChaCha8Rng;
-
-    use crate::chip8_machine::{Chip8MachineState, SCREEN_HEIGHT, SCREEN_WIDTH};
-    use crate::chip8_state::Chip8State;
-    use crate::disassembly::DisassemblyOutput;
+    use std::cell::RefCell;
+    use std::collections::BTreeSet;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use crate::assembler::{AssemblyError, assemble, assemble_with};
+    use crate::audio::{AudioConfig, AudioSink, Beeper, WavSink, Waveform, pattern_playback_rate};
+    use crate::chip8_machine::{
+        Chip8MachineState, DEFAULT_CYCLES_PER_TIMER_TICK, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH,
+        SCREEN_HEIGHT, SCREEN_WIDTH,
+    };
+    use crate::chip8_state::{
+        BIG_FONT_ADDRESS, BIG_FONT_SIZE, Chip8State, DEFAULT_BIG_FONT, DEFAULT_FONT, FONT_ADDRESS,
+        FONT_SIZE, MEMORY_SIZE, STACK_SIZE, XO_CHIP_MEMORY_SIZE,
+    };
+    use crate::debugger::{
+        Breakpoint, Command, Debugger, RunTarget, StopReason, disassembly_window, format_registers,
+        parse_watchpoint,
+    };
+    use crate::disassembly::{DisassemblyOutput, ProgramDisassembly, decrypt_chip8_instruction};
+    use crate::error::Chip8Error;
+    use crate::expression::Expression;
+    use crate::framebuffer::FrameBuffer;
+    use crate::movie::{MOVIE_MAGIC, Movie, MovieError};
+    use crate::octo::{self, OctoProgram};
+    use crate::platform::Platform;
+    use crate::quirks::{MemoryIncrement, Quirks};
+    use crate::random::RandomSource;
+    use crate::rewind::Rewind;
+    use crate::rom_database::{DatabaseError, RomDatabase};
+    use crate::rom_file::{
+        LoadedRom, RomFileError, RomSettings, decode_gif, decode_rom, parse_hex_dump,
+        parse_intel_hex,
+    };
+    use crate::runner::{KeyEvent, RunOutcome, Runner, parse_key_script};
+    use crate::save_state::SaveStateError;
+    use crate::scheduler::{Scheduler, SpeedMode};
+    use crate::screenshot::{self, DEFAULT_PALETTE, ScreenshotFormat, parse_palette};
+    use crate::trace::{TRACE_RECORD_SIZE, TraceFormat, TraceRecord, Tracer, read_binary_header};
+    use crate::tui::{KeyHold, THEMES, render_half_blocks, theme_from_name};
+    use crate::watchpoint::{Register, WatchEvent, WatchHit, Watchpoint};
 
     #[test]
     fn should_create() {
@@ -16,6 +51,48 @@ mod tests {
         println!("{:?}", cpu);
     }
 
+    #[test]
+    fn should_load_default_font() {
+        let cpu = Chip8State::new();
+        let start = FONT_ADDRESS as usize;
+
+        assert_eq!(cpu.memory[start..start + FONT_SIZE], DEFAULT_FONT);
+        assert_eq!(cpu.font_address, FONT_ADDRESS);
+    }
+
+    #[test]
+    fn should_load_custom_font() {
+        let glyphs = [0xAA; FONT_SIZE];
+        let cpu = Chip8State::with_font(0x000, &glyphs).unwrap();
+
+        assert_eq!(cpu.memory[0..FONT_SIZE], glyphs);
+        assert_eq!(cpu.font_glyph_address(0xF), Some(0x4B));
+        assert_eq!(cpu.big_font_address, FONT_SIZE as u16);
+    }
+
+    #[test]
+    fn should_place_big_font_after_custom_font() {
+        let glyphs = [0xAA; FONT_SIZE];
+        let cpu = Chip8State::with_font(0x098, &glyphs).unwrap();
+        let big_font = 0x098 + FONT_SIZE;
+
+        assert_eq!(cpu.memory[0x098..big_font], glyphs);
+        assert_eq!(cpu.big_font_address as usize, big_font);
+        assert_eq!(
+            cpu.memory[big_font..big_font + BIG_FONT_SIZE],
+            DEFAULT_BIG_FONT
+        );
+    }
+
+    #[test]
+    fn should_reject_font_address_without_room() {
+        let glyphs = [0xAA; FONT_SIZE];
+
+        assert!(Chip8State::with_font(0x111, &glyphs).is_err());
+        assert!(Chip8State::with_font(0xFFFF, &glyphs).is_err());
+        assert!(Chip8State::new().load_font(0x0FF0, &glyphs).is_err());
+    }
+
     #[test]
     fn should_print_instruction() {
         let cpu = Chip8State::new();
@@ -36,19 +113,19 @@ mod tests {
     fn should_execute_cls() {
         let mut chip8 = Chip8MachineState::new();
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x00, 0xE0]);
-        chip8.display[0][0] = true;
-        chip8.display[0][1] = true;
-        chip8.display[1][1] = true;
-        chip8.display[20][20] = true;
-        chip8.display[SCREEN_HEIGHT - 1][SCREEN_WIDTH - 1] = true;
-
-        chip8.execute_cycle();
-
-        assert!(!chip8.display[0][0]);
-        assert!(!chip8.display[0][1]);
-        assert!(!chip8.display[1][1]);
-        assert!(!chip8.display[20][20]);
-        assert!(!chip8.display[SCREEN_HEIGHT - 1][SCREEN_WIDTH - 1]);
+        chip8.set_pixel(0, 0, true);
+        chip8.set_pixel(1, 0, true);
+        chip8.set_pixel(1, 1, true);
+        chip8.set_pixel(20, 20, true);
+        chip8.set_pixel(SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1, true);
+
+        chip8.execute_cycle().unwrap();
+
+        assert!(!chip8.get_pixel(0, 0));
+        assert!(!chip8.get_pixel(1, 0));
+        assert!(!chip8.get_pixel(1, 1));
+        assert!(!chip8.get_pixel(20, 20));
+        assert!(!chip8.get_pixel(SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1));
     }
 
     #[test]
@@ -59,7 +136,7 @@ mod tests {
         chip8.state.sp = 1;
         chip8.state.pc = 0x200;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.sp, 0);
         assert_eq!(chip8.state.pc, 0x000);
@@ -71,7 +148,7 @@ mod tests {
         let mut chip8 = Chip8MachineState::new();
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x02, 0x00]);
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.sp, 1);
         assert_eq!(chip8.state.stack[0], 0x202);
@@ -83,7 +160,7 @@ mod tests {
         let mut chip8 = Chip8MachineState::new();
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x12, 0x00]);
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x200);
     }
@@ -93,7 +170,7 @@ mod tests {
         let mut chip8 = Chip8MachineState::new();
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x22, 0x00]);
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.sp, 1);
         assert_eq!(chip8.state.stack[0], 0x202);
@@ -115,16 +192,16 @@ mod tests {
         chip8.state.memory[0x300..0x308]
             .clone_from_slice(&[0x60, 0x01, 0x61, 0x01, 0x80, 0x14, 0x00, 0xEE]);
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.sp, 1);
         assert_eq!(chip8.state.stack[0], 0x202);
         assert_eq!(chip8.state.pc, 0x300);
 
-        chip8.execute_cycle();
-        chip8.execute_cycle();
-        chip8.execute_cycle();
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.sp, 0);
         assert_eq!(chip8.state.stack[0], 0x202);
@@ -136,7 +213,7 @@ mod tests {
         let mut chip8 = Chip8MachineState::new();
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x30, 0xFF]);
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x202);
     }
@@ -147,7 +224,7 @@ mod tests {
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x30, 0xFF]);
         chip8.state.v[0] = 0xFF;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x204);
     }
@@ -158,7 +235,7 @@ mod tests {
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x40, 0xFF]);
         chip8.state.v[0] = 0xFF;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x202);
     }
@@ -168,7 +245,7 @@ mod tests {
         let mut chip8 = Chip8MachineState::new();
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x40, 0xFF]);
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x204);
     }
@@ -180,7 +257,7 @@ mod tests {
         chip8.state.v[0] = 0xFF;
         chip8.state.v[2] = 0x00;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x202);
     }
@@ -192,7 +269,7 @@ mod tests {
         chip8.state.v[0] = 0xFF;
         chip8.state.v[2] = 0xFF;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x204);
     }
@@ -202,7 +279,7 @@ mod tests {
         let mut chip8 = Chip8MachineState::new();
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x63, 0x11]);
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[3], 0x11);
     }
@@ -213,7 +290,7 @@ mod tests {
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x7E, 0x11]);
         chip8.state.v[0xE] = 0x11;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0xE], 0x22);
     }
@@ -224,7 +301,7 @@ mod tests {
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x84, 0x50]);
         chip8.state.v[0x5] = 0xAA;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x4], 0xAA);
     }
@@ -236,7 +313,7 @@ mod tests {
         chip8.state.v[0x4] = 0x0B;
         chip8.state.v[0x5] = 0xB0;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x4], 0xBB);
     }
@@ -248,7 +325,7 @@ mod tests {
         chip8.state.v[0x5] = 0x1B;
         chip8.state.v[0x6] = 0xB0;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x5], 0x10);
     }
@@ -260,7 +337,7 @@ mod tests {
         chip8.state.v[0x6] = 0xB0;
         chip8.state.v[0x7] = 0xBB;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x6], 0x0B);
     }
@@ -272,7 +349,7 @@ mod tests {
         chip8.state.v[0xC] = 0x01;
         chip8.state.v[0xD] = 0x01;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0xC], 0x02);
         assert_eq!(chip8.state.v[0xF], 0x00);
@@ -285,7 +362,7 @@ mod tests {
         chip8.state.v[0xA] = 0xFF;
         chip8.state.v[0xB] = 0xFF;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0xA], 0xFE);
         assert_eq!(chip8.state.v[0xF], 0x01);
@@ -298,7 +375,7 @@ mod tests {
         chip8.state.v[0x8] = 0x0F;
         chip8.state.v[0x9] = 0x0E;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x8], 0x01);
         assert_eq!(chip8.state.v[0xF], 0x01);
@@ -311,7 +388,7 @@ mod tests {
         chip8.state.v[0x6] = 0xFE;
         chip8.state.v[0x7] = 0xFF;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x6], 0xFF);
         assert_eq!(chip8.state.v[0xF], 0x00);
@@ -323,7 +400,7 @@ mod tests {
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x85, 0x06]);
         chip8.state.v[0x5] = 0xFE;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x5], 0x7F);
         assert_eq!(chip8.state.v[0xF], 0x00);
@@ -335,7 +412,7 @@ mod tests {
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x85, 0x06]);
         chip8.state.v[0x5] = 0xFF;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x5], 0x7F);
         assert_eq!(chip8.state.v[0xF], 0x01);
@@ -348,7 +425,7 @@ mod tests {
         chip8.state.v[0x1] = 0x0E;
         chip8.state.v[0x2] = 0x0F;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x1], 0x01);
         assert_eq!(chip8.state.v[0xF], 0x01);
@@ -361,7 +438,7 @@ mod tests {
         chip8.state.v[0x1] = 0x0F;
         chip8.state.v[0x2] = 0x0E;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x1], 0xFF);
         assert_eq!(chip8.state.v[0xF], 0x00);
@@ -373,7 +450,7 @@ mod tests {
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0x81, 0x2E]);
         chip8.state.v[0x1] = 0x84;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x1], 0x08);
         assert_eq!(chip8.state.v[0xF], 0x01);
@@ -386,7 +463,7 @@ mod tests {
         chip8.state.v[0xE] = 0x00;
         chip8.state.v[0xF] = 0x00;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x202);
     }
@@ -398,7 +475,7 @@ mod tests {
         chip8.state.v[0xE] = 0x01;
         chip8.state.v[0xF] = 0x00;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x204);
     }
@@ -408,7 +485,7 @@ mod tests {
         let mut chip8 = Chip8MachineState::new();
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0xA2, 0x34]);
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.i, 0x0234);
     }
@@ -419,7 +496,7 @@ mod tests {
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0xB3, 0x45]);
         chip8.state.v[0x0] = 0x10;
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.pc, 0x0355);
     }
@@ -428,13 +505,114 @@ mod tests {
     fn should_execute_rnd_vx_byte() {
         let mut chip8 = Chip8MachineState::new();
         chip8.state.memory[0x200..0x202].clone_from_slice(&[0xC3, 0xFF]);
-        chip8.random = ChaCha8Rng::seed_from_u64(2); // Create fixed number, only for this test!
+        chip8.random = RandomSource::seeded(2);
 
-        chip8.execute_cycle();
+        chip8.execute_cycle().unwrap();
 
         assert_eq!(chip8.state.v[0x3], 0xC5);
     }
 
+    #[test]
+    fn should_mask_scripted_random_bytes() {
+        let mut chip8 = Chip8MachineState::with_random_source(
+            Platform::Chip8,
+            Quirks::default(),
+            RandomSource::scripted(vec![0xAB, 0x5A]),
+        );
+        chip8.state.memory[0x200..0x206].clone_from_slice(&[0xC3, 0x0F, 0xC4, 0xFF, 0xC5, 0xF0]);
+
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.v[0x3], 0x0B);
+        assert_eq!(chip8.state.v[0x4], 0x5A);
+        assert_eq!(chip8.state.v[0x5], 0xA0);
+    }
+
+    #[test]
+    fn should_repeat_sequences_for_equal_seeds() {
+        let mut first = RandomSource::seeded(42);
+        let mut second = RandomSource::seeded(42);
+
+        let bytes: Vec<u8> = (0..64).map(|_| first.next_byte()).collect();
+
+        assert!((0..64).all(|index| second.next_byte() == bytes[index]));
+        assert_eq!(RandomSource::scripted(Vec::new()).next_byte(), 0);
+    }
+
+    #[test]
+    fn should_save_random_source_position() {
+        let mut chip8 = Chip8MachineState::with_random_source(
+            Platform::Chip8,
+            Quirks::default(),
+            RandomSource::scripted(vec![1, 2, 3]),
+        );
+        chip8.random.next_byte();
+
+        let mut restored = Chip8MachineState::new();
+        restored.load_state(&chip8.save_state()).unwrap();
+        assert_eq!(restored.random, chip8.random);
+        assert_eq!(restored.random.next_byte(), 2);
+
+        chip8.random = RandomSource::seeded(7);
+        chip8.random.next_byte();
+        restored.load_state(&chip8.save_state()).unwrap();
+        assert_eq!(restored.random, chip8.random);
+        assert_eq!(restored.random.next_byte(), chip8.random.next_byte());
+    }
+
+    #[test]
+    fn should_execute_ld_f_vx() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0xF4, 0x29]);
+        chip8.state.v[0x4] = 0xA;
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.i, FONT_ADDRESS + 0xA * 5);
+        assert_eq!(chip8.state.memory[chip8.state.i as usize], 0xF0);
+    }
+
+    #[test]
+    fn should_execute_ld_vx_dt() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0xF2, 0x07]);
+        chip8.state.delay_timer = 0x30;
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.v[0x2], 0x30);
+    }
+
+    #[test]
+    fn should_tick_timers_after_cycles_per_timer_tick() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.cycles_per_timer_tick = 4;
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x12, 0x00]);
+        chip8.state.delay_timer = 2;
+        chip8.state.sound_timer = 1;
+
+        for _ in 0..3 {
+            chip8.execute_cycle().unwrap();
+        }
+
+        assert_eq!(chip8.state.delay_timer, 2);
+        assert_eq!(chip8.state.sound_timer, 1);
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.delay_timer, 1);
+        assert_eq!(chip8.state.sound_timer, 0);
+
+        chip8.run_timer_tick().unwrap();
+        chip8.run_timer_tick().unwrap();
+
+        assert_eq!(chip8.cycles, 12);
+        assert_eq!(chip8.state.delay_timer, 0);
+        assert_eq!(chip8.state.sound_timer, 0);
+    }
+
     #[test]
     fn should_set_and_get_pixel() {
         let mut chip8 = Chip8MachineState::new();
@@ -455,39 +633,2568 @@ mod tests {
         chip8.state.v[0x5] = 0;
         chip8.state.v[0x6] = 0;
 
-        chip8.execute_cycle();
-
-        let mut display: [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT] =
-            [[false; SCREEN_WIDTH]; SCREEN_HEIGHT];
-
-        display[0][0] = true;
-        display[0][1] = true;
-        display[0][2] = true;
-        display[0][3] = true;
-        display[0][4] = true;
-        display[0][5] = true;
-        display[0][6] = true;
-        display[0][7] = true;
-        display[1][0] = true;
-        display[1][1] = true;
-        display[1][2] = true;
-        display[1][3] = true;
-        display[1][4] = true;
-        display[1][5] = true;
-        display[1][6] = true;
-        display[1][7] = true;
-        display[2][0] = true;
-        display[2][1] = true;
-        display[2][2] = true;
-        display[2][3] = true;
-        display[2][4] = true;
-        display[2][5] = true;
-        display[2][6] = true;
-        display[2][7] = true;
+        chip8.execute_cycle().unwrap();
+
+        let mut display = FrameBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+
+        display.set(0, 0, true);
+        display.set(1, 0, true);
+        display.set(2, 0, true);
+        display.set(3, 0, true);
+        display.set(4, 0, true);
+        display.set(5, 0, true);
+        display.set(6, 0, true);
+        display.set(7, 0, true);
+        display.set(0, 1, true);
+        display.set(1, 1, true);
+        display.set(2, 1, true);
+        display.set(3, 1, true);
+        display.set(4, 1, true);
+        display.set(5, 1, true);
+        display.set(6, 1, true);
+        display.set(7, 1, true);
+        display.set(0, 2, true);
+        display.set(1, 2, true);
+        display.set(2, 2, true);
+        display.set(3, 2, true);
+        display.set(4, 2, true);
+        display.set(5, 2, true);
+        display.set(6, 2, true);
+        display.set(7, 2, true);
 
         assert_eq!(chip8.display, display);
     }
 
+    #[test]
+    fn should_run_scheduler_frame_at_clock_speed() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x12, 0x00]);
+        let mut scheduler = Scheduler::new(600);
+
+        assert_eq!(scheduler.run_frame(&mut chip8).unwrap(), 1);
+        assert_eq!(chip8.cycles, 10);
+    }
+
+    #[test]
+    fn should_run_scheduler_speed_modes() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x12, 0x00]);
+        let mut scheduler = Scheduler::new(600);
+
+        scheduler.toggle_mode(SpeedMode::Paused);
+        scheduler.run_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.cycles, 0);
+
+        scheduler.set_mode(SpeedMode::FastForward);
+        scheduler.fast_forward_factor = 3;
+        scheduler.run_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.cycles, 30);
+
+        scheduler.set_mode(SpeedMode::SlowMotion);
+        scheduler.slow_motion_divisor = 2;
+        scheduler.run_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.cycles, 30);
+        scheduler.run_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.cycles, 40);
+
+        scheduler.toggle_mode(SpeedMode::SlowMotion);
+        assert_eq!(scheduler.mode, SpeedMode::Normal);
+    }
+
+    #[derive(Default)]
+    struct RecordingSink {
+        samples: Rc<RefCell<Vec<f32>>>,
+    }
+
+    impl AudioSink for RecordingSink {
+        fn write_samples(&mut self, samples: &[f32]) -> std::io::Result<()> {
+            self.samples.borrow_mut().extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_beep_square_wave_while_sound_timer_active() {
+        let sink = RecordingSink::default();
+        let samples = Rc::clone(&sink.samples);
+        let config = AudioConfig {
+            sample_rate: 600,
+            frequency: 100.0,
+            volume: 0.5,
+            waveform: Waveform::Square,
+        };
+        let mut beeper = Beeper::new(config, Box::new(sink));
+
+        beeper.update(true, 1).unwrap();
+        beeper.update(false, 2).unwrap();
+
+        let samples = samples.borrow();
+        assert_eq!(samples.len(), 30);
+        assert_eq!(samples[0..6], [0.5, 0.5, 0.5, -0.5, -0.5, -0.5]);
+        assert!(samples[10..].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn should_write_wav_file() {
+        let mut sink = WavSink::new(Cursor::new(Vec::new()), 8000).unwrap();
+
+        sink.write_samples(&[0.0, 1.0, -1.0]).unwrap();
+        let bytes = sink.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(bytes[4..8], 42u32.to_le_bytes());
+        assert_eq!(bytes[24..28], 8000u32.to_le_bytes());
+        assert_eq!(bytes[40..44], 6u32.to_le_bytes());
+        assert_eq!(bytes[44..], [0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn should_fail_on_unknown_opcode() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0xFA, 0x81]);
+
+        let result = chip8.execute_cycle();
+
+        assert_eq!(
+            result,
+            Err(Chip8Error::UnknownOpcode {
+                address: 0x200,
+                opcode: 0xFA81
+            })
+        );
+        assert_eq!(chip8.state.pc, 0x200);
+        assert_eq!(chip8.cycles, 0);
+    }
+
+    #[test]
+    fn should_fail_on_pc_out_of_bounds() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.pc = MEMORY_SIZE as u16;
+
+        let result = chip8.execute_cycle();
+
+        assert_eq!(result, Err(Chip8Error::PcOutOfBounds { address: 0x1000 }));
+    }
+
+    #[test]
+    fn should_wait_for_key_at_the_end_of_memory() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::XoChip);
+        chip8.state.memory[0xFFFE..].clone_from_slice(&[0xF0, 0x0A]);
+        chip8.state.pc = 0xFFFE;
+
+        chip8.execute_cycle().unwrap();
+        assert_eq!(chip8.state.pc, 0xFFFE);
+
+        chip8.set_key(0x7, true);
+        chip8.execute_cycle().unwrap();
+        assert_eq!(chip8.state.pc, 0x0000);
+        assert_eq!(chip8.state.v[0x0], 0x7);
+    }
+
+    #[test]
+    fn should_fail_on_stack_overflow() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x22, 0x00]);
+
+        for _ in 0..STACK_SIZE {
+            chip8.execute_cycle().unwrap();
+        }
+        let result = chip8.execute_cycle();
+
+        assert_eq!(
+            result,
+            Err(Chip8Error::StackOverflow {
+                address: 0x200,
+                opcode: 0x2200
+            })
+        );
+        assert_eq!(chip8.state.sp as usize, STACK_SIZE);
+    }
+
+    #[test]
+    fn should_fail_on_stack_underflow() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x00, 0xEE]);
+
+        let result = chip8.execute_cycle();
+
+        assert_eq!(
+            result,
+            Err(Chip8Error::StackUnderflow {
+                address: 0x200,
+                opcode: 0x00EE
+            })
+        );
+    }
+
+    #[test]
+    fn should_fail_on_memory_out_of_bounds() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0xF3, 0x55]);
+        chip8.state.i = 0xFFE;
+
+        let result = chip8.execute_cycle();
+
+        assert_eq!(
+            result,
+            Err(Chip8Error::MemoryOutOfBounds {
+                address: 0x200,
+                opcode: 0xF355,
+                memory_address: 0x1001
+            })
+        );
+        assert_eq!(chip8.state.memory[0xFFE..], [0x00, 0x00]);
+    }
+
+    #[test]
+    fn should_select_quirks_preset_by_name() {
+        assert_eq!(Quirks::from_name("VIP"), Some(Quirks::COSMAC_VIP));
+        assert_eq!(Quirks::from_name("octo"), Some(Quirks::MODERN));
+        assert_eq!(Quirks::from_name("super-chip"), Some(Quirks::SUPER_CHIP));
+        assert_eq!(Quirks::from_name("unknown"), None);
+        // Would not match the chip8 platform's modern defaults
+        assert_eq!(Quirks::from_name("chip8"), None);
+    }
+
+    #[test]
+    fn should_execute_shr_vx_with_vip_quirks() {
+        let mut chip8 = Chip8MachineState::with_quirks(Quirks::COSMAC_VIP);
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x85, 0x66]);
+        chip8.state.v[0x5] = 0xFF;
+        chip8.state.v[0x6] = 0x03;
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.v[0x5], 0x01);
+        assert_eq!(chip8.state.v[0xF], 0x01);
+    }
+
+    #[test]
+    fn should_reset_vf_after_logic_with_vip_quirks() {
+        let mut chip8 = Chip8MachineState::with_quirks(Quirks::COSMAC_VIP);
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x84, 0x51]);
+        chip8.state.v[0xF] = 0x01;
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.v[0xF], 0x00);
+    }
+
+    #[test]
+    fn should_increment_i_after_ld_ref_i_vx_with_quirks() {
+        for (quirks, expected_i) in [
+            (Quirks::COSMAC_VIP, 0x304),
+            (Quirks::CHIP48, 0x303),
+            (Quirks::SUPER_CHIP, 0x300),
+        ] {
+            let mut chip8 = Chip8MachineState::with_quirks(quirks);
+            chip8.state.memory[0x200..0x202].clone_from_slice(&[0xF3, 0x55]);
+            chip8.state.i = 0x300;
+
+            chip8.execute_cycle().unwrap();
+
+            assert_eq!(chip8.state.i, expected_i);
+        }
+    }
+
+    #[test]
+    fn should_execute_jp_vx_addr_with_chip48_quirks() {
+        let mut chip8 = Chip8MachineState::with_quirks(Quirks::CHIP48);
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0xB3, 0x45]);
+        chip8.state.v[0x0] = 0x10;
+        chip8.state.v[0x3] = 0x20;
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.pc, 0x0365);
+    }
+
+    #[test]
+    fn should_clip_or_wrap_sprites_with_quirks() {
+        for (quirks, wrapped) in [(Quirks::COSMAC_VIP, false), (Quirks::MODERN, true)] {
+            let mut chip8 = Chip8MachineState::with_quirks(quirks);
+            chip8.state.memory[0x200..0x202].clone_from_slice(&[0xD0, 0x12]);
+            chip8.state.memory[0x300..0x302].clone_from_slice(&[0xFF, 0xFF]);
+            chip8.state.i = 0x300;
+            chip8.state.v[0x0] = (SCREEN_WIDTH - 4) as u8;
+            chip8.state.v[0x1] = (SCREEN_HEIGHT - 1) as u8;
+
+            chip8.execute_cycle().unwrap();
+
+            assert!(chip8.get_pixel(SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1));
+            assert_eq!(chip8.get_pixel(0, SCREEN_HEIGHT - 1), wrapped);
+            assert_eq!(chip8.get_pixel(SCREEN_WIDTH - 1, 0), wrapped);
+        }
+    }
+
+    #[test]
+    fn should_wait_for_next_timer_tick_after_draw_with_vip_quirks() {
+        let mut chip8 = Chip8MachineState::with_quirks(Quirks::COSMAC_VIP);
+        chip8.state.memory[0x200..0x204].clone_from_slice(&[0xD0, 0x01, 0x12, 0x02]);
+        chip8.state.i = 0x300;
+        chip8.state.delay_timer = 5;
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.delay_timer, 4);
+
+        chip8.run_timer_tick().unwrap();
+
+        assert_eq!(chip8.cycles, 1 + DEFAULT_CYCLES_PER_TIMER_TICK as u64);
+    }
+
+    #[test]
+    fn should_switch_between_lores_and_hires() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::SuperChip);
+        chip8.state.memory[0x200..0x204].clone_from_slice(&[0x00, 0xFF, 0x00, 0xFE]);
+        chip8.set_pixel(1, 1, true);
+
+        chip8.execute_cycle().unwrap();
+
+        assert!(chip8.is_hires());
+        assert_eq!(chip8.display.width(), HIRES_SCREEN_WIDTH);
+        assert_eq!(chip8.display.height(), HIRES_SCREEN_HEIGHT);
+        assert!(!chip8.get_pixel(1, 1));
+
+        chip8.execute_cycle().unwrap();
+
+        assert!(!chip8.is_hires());
+        assert_eq!(chip8.display.width(), SCREEN_WIDTH);
+    }
+
+    #[test]
+    fn should_execute_scd_scr_scl() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::SuperChip);
+        chip8.state.memory[0x200..0x206].clone_from_slice(&[0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC]);
+        chip8.set_pixel(10, 0, true);
+
+        chip8.execute_cycle().unwrap();
+        assert!(chip8.get_pixel(10, 3));
+        assert!(!chip8.get_pixel(10, 0));
+
+        chip8.execute_cycle().unwrap();
+        assert!(chip8.get_pixel(14, 3));
+
+        chip8.execute_cycle().unwrap();
+        assert!(chip8.get_pixel(10, 3));
+        assert_eq!(
+            chip8
+                .display
+                .pixels()
+                .iter()
+                .filter(|&&planes| planes != 0)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn should_execute_draw_16x16_sprite() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::SuperChip);
+        chip8.state.memory[0x200..0x204].clone_from_slice(&[0x00, 0xFF, 0xD0, 0x10]);
+        chip8.state.memory[0x300..0x320].fill(0xFF);
+        chip8.state.i = 0x300;
+        chip8.state.v[0x0] = 100;
+        chip8.state.v[0x1] = 40;
+
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+
+        assert!(chip8.get_pixel(100, 40));
+        assert!(chip8.get_pixel(115, 55));
+        assert!(!chip8.get_pixel(116, 55));
+        assert_eq!(
+            chip8
+                .display
+                .pixels()
+                .iter()
+                .filter(|&&planes| planes != 0)
+                .count(),
+            256
+        );
+        assert_eq!(chip8.state.v[0xF], 0);
+    }
+
+    #[test]
+    fn should_execute_exit() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::SuperChip);
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x00, 0xFD]);
+
+        chip8.execute_cycle().unwrap();
+        chip8.run_timer_tick().unwrap();
+
+        assert!(chip8.halted);
+        assert_eq!(chip8.state.pc, 0x202);
+        assert_eq!(chip8.cycles, 1);
+    }
+
+    #[test]
+    fn should_execute_ld_hf_vx() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::SuperChip);
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0xF1, 0x30]);
+        chip8.state.v[0x1] = 0x9;
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.i, BIG_FONT_ADDRESS + 90);
+        assert_eq!(chip8.state.memory[chip8.state.i as usize], 0xFF);
+    }
+
+    #[test]
+    fn should_save_and_restore_rpl_flags() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::SuperChip);
+        chip8.state.memory[0x200..0x204].clone_from_slice(&[0xF2, 0x75, 0xF2, 0x85]);
+        chip8.state.v[0..3].clone_from_slice(&[1, 2, 3]);
+
+        chip8.execute_cycle().unwrap();
+        chip8.state.v[0..3].fill(0);
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.rpl[0..4], [1, 2, 3, 0]);
+        assert_eq!(chip8.state.v[0..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn should_reject_super_chip_instructions_on_chip8() {
+        for opcode in [
+            [0x00, 0xC3],
+            [0x00, 0xFB],
+            [0x00, 0xFC],
+            [0x00, 0xFD],
+            [0x00, 0xFE],
+            [0x00, 0xFF],
+            [0xF1, 0x30],
+            [0xF2, 0x75],
+            [0xF2, 0x85],
+        ] {
+            let mut chip8 = Chip8MachineState::new();
+            chip8.state.memory[0x200..0x202].clone_from_slice(&opcode);
+
+            assert_eq!(
+                chip8.execute_cycle(),
+                Err(Chip8Error::UnknownOpcode {
+                    address: 0x200,
+                    opcode: u16::from_be_bytes(opcode),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn should_draw_no_rows_for_dxy0_on_chip8() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0xD0, 0x10]);
+        chip8.state.memory[0x300..0x320].fill(0xFF);
+        chip8.state.i = 0x300;
+
+        chip8.execute_cycle().unwrap();
+
+        assert!(chip8.display.pixels().iter().all(|&planes| planes == 0));
+        assert_eq!(chip8.state.v[0xF], 0);
+    }
+
+    #[test]
+    fn should_disassemble_super_chip_instructions() {
+        assert_eq!(decrypt_chip8_instruction(&[0x00, 0xC4]), "SCD 0x4");
+        assert_eq!(decrypt_chip8_instruction(&[0x00, 0xFB]), "SCR");
+        assert_eq!(decrypt_chip8_instruction(&[0x00, 0xFC]), "SCL");
+        assert_eq!(decrypt_chip8_instruction(&[0x00, 0xFD]), "EXIT");
+        assert_eq!(decrypt_chip8_instruction(&[0x00, 0xFE]), "LOW");
+        assert_eq!(decrypt_chip8_instruction(&[0x00, 0xFF]), "HIGH");
+        assert_eq!(decrypt_chip8_instruction(&[0xD1, 0x20]), "DRW V1, V2, 0x0");
+        assert_eq!(decrypt_chip8_instruction(&[0xF3, 0x30]), "LD HF, V3");
+        assert_eq!(decrypt_chip8_instruction(&[0xF4, 0x75]), "LD R, V4");
+        assert_eq!(decrypt_chip8_instruction(&[0xF5, 0x85]), "LD V5, R");
+    }
+
+    #[test]
+    fn should_use_64k_memory_for_xo_chip() {
+        let chip8 = Chip8MachineState::for_platform(Platform::XoChip);
+
+        assert_eq!(chip8.state.memory.len(), XO_CHIP_MEMORY_SIZE);
+        assert_eq!(chip8.quirks, Quirks::XO_CHIP);
+        assert_eq!(Chip8MachineState::new().state.memory.len(), MEMORY_SIZE);
+    }
+
+    #[test]
+    fn should_execute_ld_i_long_and_skip_over_it() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::XoChip);
+        chip8.state.memory[0x200..0x208]
+            .clone_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0xF0, 0x00]);
+        chip8.state.memory[0x208..0x20A].clone_from_slice(&[0xE0, 0x00]);
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.pc, 0x206);
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.i, 0xE000);
+        assert_eq!(chip8.state.pc, 0x20A);
+    }
+
+    #[test]
+    fn should_save_and_load_register_range() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::XoChip);
+        chip8.state.memory[0x200..0x204].clone_from_slice(&[0x53, 0x12, 0x51, 0x33]);
+        chip8.state.v[1..4].clone_from_slice(&[0x11, 0x22, 0x33]);
+        chip8.state.i = 0x300;
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.memory[0x300..0x303], [0x33, 0x22, 0x11]);
+        assert_eq!(chip8.state.i, 0x300);
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.v[1..4], [0x33, 0x22, 0x11]);
+    }
+
+    #[test]
+    fn should_draw_on_selected_planes() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::XoChip);
+        chip8.state.memory[0x200..0x206].clone_from_slice(&[0xF3, 0x01, 0xD0, 0x01, 0xF2, 0x01]);
+        chip8.state.memory[0x208..0x20A].clone_from_slice(&[0x00, 0xE0]);
+        chip8.state.memory[0x206..0x208].clone_from_slice(&[0x12, 0x08]);
+        chip8.state.memory[0x300..0x302].clone_from_slice(&[0x80, 0xC0]);
+        chip8.state.i = 0x300;
+
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.display.get_planes(0, 0), 0b11);
+        assert_eq!(chip8.display.get_planes(1, 0), 0b10);
+
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.display.get_planes(0, 0), 0b01);
+        assert_eq!(chip8.display.get_planes(1, 0), 0b00);
+    }
+
+    #[test]
+    fn should_load_audio_pattern_and_pitch() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::XoChip);
+        chip8.state.memory[0x200..0x204].clone_from_slice(&[0xF0, 0x02, 0xF5, 0x3A]);
+        chip8.state.memory[0x300..0x310].fill(0xF0);
+        chip8.state.i = 0x300;
+        chip8.state.v[0x5] = 112;
+
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.state.audio_pattern, Some([0xF0; 16]));
+        assert_eq!(chip8.state.pitch, 112);
+        assert_eq!(pattern_playback_rate(112), 8000.0);
+    }
+
+    #[test]
+    fn should_execute_scu_nibble() {
+        let mut chip8 = Chip8MachineState::for_platform(Platform::XoChip);
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x00, 0xD2]);
+        chip8.set_pixel(5, 4, true);
+
+        chip8.execute_cycle().unwrap();
+
+        assert!(chip8.get_pixel(5, 2));
+        assert!(!chip8.get_pixel(5, 4));
+    }
+
+    #[test]
+    fn should_reject_xo_chip_instructions_before_xo_chip() {
+        for platform in [Platform::Chip8, Platform::SuperChip] {
+            for opcode in [
+                [0x00, 0xD3],
+                [0x51, 0x22],
+                [0x51, 0x23],
+                [0xF0, 0x00],
+                [0xF2, 0x01],
+                [0xF0, 0x02],
+                [0xF1, 0x3A],
+            ] {
+                let mut chip8 = Chip8MachineState::for_platform(platform);
+                chip8.state.memory[0x200..0x202].clone_from_slice(&opcode);
+
+                assert_eq!(
+                    chip8.execute_cycle(),
+                    Err(Chip8Error::UnknownOpcode {
+                        address: 0x200,
+                        opcode: u16::from_be_bytes(opcode),
+                    })
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn should_disassemble_xo_chip_instructions() {
+        let mut cpu = Chip8State::with_memory_size(XO_CHIP_MEMORY_SIZE);
+        cpu.memory[0x200..0x204].clone_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+
+        assert_eq!(
+            cpu.disassemble(0x200),
+            "0x200: F0001234 -> LD I, LONG 0x1234"
+        );
+        assert_eq!(decrypt_chip8_instruction(&[0x00, 0xD3]), "SCU 0x3");
+        assert_eq!(decrypt_chip8_instruction(&[0x51, 0x42]), "LD [I], V1-V4");
+        assert_eq!(decrypt_chip8_instruction(&[0x51, 0x43]), "LD V1-V4, [I]");
+        assert_eq!(decrypt_chip8_instruction(&[0xF2, 0x01]), "PLANE 0x2");
+        assert_eq!(decrypt_chip8_instruction(&[0xF0, 0x02]), "AUDIO");
+        assert_eq!(decrypt_chip8_instruction(&[0xF7, 0x3A]), "LD PITCH, V7");
+    }
+
+    #[test]
+    fn should_replay_identically_after_load_state() {
+        let mut chip8 = Chip8MachineState::new();
+        // RND V0, 0xFF; LD I, 0x050; DRW V0, V1, 5; JP 0x200
+        chip8.state.memory[0x200..0x208]
+            .clone_from_slice(&[0xC0, 0xFF, 0xA0, 0x50, 0xD0, 0x15, 0x12, 0x00]);
+        chip8.set_key(0x5, true);
+        for _ in 0..50 {
+            chip8.execute_cycle().unwrap();
+        }
+        let snapshot = chip8.save_state();
+
+        for _ in 0..200 {
+            chip8.execute_cycle().unwrap();
+        }
+        let expected = chip8.save_state();
+
+        let mut restored = Chip8MachineState::new();
+        restored.load_state(&snapshot).unwrap();
+        assert_eq!(restored.save_state(), snapshot);
+        assert!(restored.keyboard[0x5]);
+        for _ in 0..200 {
+            restored.execute_cycle().unwrap();
+        }
+
+        assert_eq!(restored.save_state(), expected);
+        assert_eq!(restored.display, chip8.display);
+    }
+
+    #[test]
+    fn should_restore_platform_and_hires_display() {
+        let mut chip8 = Chip8MachineState::with_platform(Platform::XoChip, Quirks::XO_CHIP);
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x00, 0xFF]);
+        chip8.execute_cycle().unwrap();
+        chip8.display.set_planes(127, 63, 2);
+        chip8.state.audio_pattern = Some([0xAA; 16]);
+
+        let mut restored = Chip8MachineState::new();
+        restored.load_state(&chip8.save_state()).unwrap();
+
+        assert_eq!(restored.platform, Platform::XoChip);
+        assert_eq!(restored.quirks, Quirks::XO_CHIP);
+        assert_eq!(restored.state.memory.len(), XO_CHIP_MEMORY_SIZE);
+        assert_eq!(restored.display.get_planes(127, 63), 2);
+        assert_eq!(restored.state.audio_pattern, Some([0xAA; 16]));
+    }
+
+    #[test]
+    fn should_reject_invalid_save_state() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.pc = 0x300;
+        let mut snapshot = Chip8MachineState::new().save_state();
+
+        assert_eq!(
+            chip8.load_state(&snapshot[..snapshot.len() - 1]),
+            Err(SaveStateError::Truncated)
+        );
+        snapshot[4] = 0xFF;
+        assert_eq!(
+            chip8.load_state(&snapshot),
+            Err(SaveStateError::UnsupportedVersion(0x00FF))
+        );
+        assert_eq!(chip8.load_state(b"ROM!"), Err(SaveStateError::BadMagic));
+        assert_eq!(chip8.state.pc, 0x300);
+    }
+
+    #[test]
+    fn should_reject_save_state_with_stack_pointer_past_the_stack() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.sp = STACK_SIZE as u8 + 1;
+        let snapshot = chip8.save_state();
+
+        assert_eq!(
+            Chip8MachineState::new().load_state(&snapshot),
+            Err(SaveStateError::Invalid("stack pointer"))
+        );
+    }
+
+    #[test]
+    fn should_reject_save_state_with_font_past_memory() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.font_address = 0xFFFF;
+        let bad_font = chip8.save_state();
+        chip8.state.font_address = FONT_ADDRESS;
+        chip8.state.big_font_address = (MEMORY_SIZE - BIG_FONT_SIZE + 1) as u16;
+        let bad_big_font = chip8.save_state();
+
+        let mut restored = Chip8MachineState::new();
+        assert_eq!(
+            restored.load_state(&bad_font),
+            Err(SaveStateError::Invalid("font address"))
+        );
+        assert_eq!(
+            restored.load_state(&bad_big_font),
+            Err(SaveStateError::Invalid("font address"))
+        );
+        assert_eq!(
+            Chip8State {
+                font_address: 0xFFFF,
+                ..Chip8State::new()
+            }
+            .font_glyph_address(0xF),
+            None
+        );
+    }
+
+    #[test]
+    fn should_reject_save_state_with_wrong_memory_size() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory.resize(XO_CHIP_MEMORY_SIZE, 0);
+        let snapshot = chip8.save_state();
+
+        assert_eq!(
+            Chip8MachineState::new().load_state(&snapshot),
+            Err(SaveStateError::Invalid("memory size"))
+        );
+    }
+
+    #[test]
+    fn should_reject_save_state_with_wrong_display_size() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.display = FrameBuffer::new(100, 50);
+        let odd_size = chip8.save_state();
+        chip8.display = FrameBuffer::new(HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT);
+        let hires_on_chip8 = chip8.save_state();
+
+        assert_eq!(
+            Chip8MachineState::new().load_state(&odd_size),
+            Err(SaveStateError::Invalid("display size"))
+        );
+        assert_eq!(
+            Chip8MachineState::new().load_state(&hires_on_chip8),
+            Err(SaveStateError::Invalid("display size"))
+        );
+    }
+
+    #[test]
+    fn should_parse_debugger_commands() {
+        assert_eq!(Command::parse("b 0x2A4"), Ok(Command::Break(0x2A4)));
+        assert_eq!(Command::parse("delete 2a4"), Ok(Command::Delete(0x2A4)));
+        assert_eq!(Command::parse("s"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("step 10"), Ok(Command::Step(10)));
+        assert_eq!(Command::parse("l"), Ok(Command::List(None)));
+        assert_eq!(Command::parse("  c  "), Ok(Command::Continue));
+        assert!(Command::parse("b").is_err());
+        assert!(Command::parse("b 0xZZ").is_err());
+        assert!(Command::parse("n 1").is_err());
+        assert!(Command::parse("jump").is_err());
+    }
+
+    fn machine_with_program(bytes: &[u8]) -> Chip8MachineState {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.load_rom_bytes(bytes, 0x200).unwrap();
+        chip8
+    }
+
+    // CALL 0x206; LD V1, 0x01; JP 0x204, with a subroutine at 0x206 that calls
+    // another one at 0x20C before returning.
+    const DEBUGGER_PROGRAM: [u8; 16] = [
+        0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x22, 0x0C, 0x62, 0x02, 0x00, 0xEE, 0x63, 0x03, 0x00,
+        0xEE,
+    ];
+
+    #[test]
+    fn should_step_over_call() {
+        let mut chip8 = machine_with_program(&DEBUGGER_PROGRAM);
+        let mut debugger = Debugger::new();
+
+        let target = Debugger::step_over_target(&chip8);
+        let reason = debugger.run(&mut chip8, target, 100);
+
+        assert_eq!(reason, StopReason::Finished);
+        assert_eq!(chip8.state.pc, 0x202);
+        assert_eq!(chip8.state.v[0x3], 0x03);
+        assert_eq!(chip8.cycles, 6);
+        // Not a CALL: a plain step
+        assert_eq!(Debugger::step_over_target(&chip8), RunTarget::Step);
+    }
+
+    #[test]
+    fn should_step_out_to_ret() {
+        let mut chip8 = machine_with_program(&DEBUGGER_PROGRAM);
+        let mut debugger = Debugger::new();
+        assert_eq!(Debugger::step_out_target(&chip8), None);
+
+        assert_eq!(
+            debugger.run(&mut chip8, RunTarget::Step, 1),
+            StopReason::Stepped
+        );
+        assert_eq!(
+            debugger.run(&mut chip8, RunTarget::Step, 1),
+            StopReason::Stepped
+        );
+        assert_eq!(chip8.state.pc, 0x20C);
+
+        let target = Debugger::step_out_target(&chip8).unwrap();
+        assert_eq!(debugger.run(&mut chip8, target, 100), StopReason::Finished);
+        assert_eq!(chip8.state.pc, 0x208);
+        assert_eq!(chip8.state.sp, 1);
+    }
+
+    #[test]
+    fn should_continue_to_breakpoint() {
+        let mut chip8 = machine_with_program(&DEBUGGER_PROGRAM);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x208);
+        debugger.add_breakpoint(0x204);
+
+        assert_eq!(
+            debugger.run(&mut chip8, RunTarget::Continue, 100),
+            StopReason::Breakpoint(0x208)
+        );
+        assert_eq!(
+            debugger.run(&mut chip8, RunTarget::Continue, 100),
+            StopReason::Breakpoint(0x204)
+        );
+        // The self-jump at 0x204 is the breakpoint, so continuing stops on it again
+        assert_eq!(
+            debugger.run(&mut chip8, RunTarget::Continue, 100),
+            StopReason::Breakpoint(0x204)
+        );
+        assert!(debugger.remove_breakpoint(0x204));
+        assert_eq!(
+            debugger.run(&mut chip8, RunTarget::Continue, 100),
+            StopReason::Limit
+        );
+    }
+
+    #[test]
+    fn should_stop_debugger_on_fault() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0x00, 0xEE]);
+
+        let reason = Debugger::new().run(&mut chip8, RunTarget::Continue, 10);
+
+        assert_eq!(
+            reason,
+            StopReason::Fault(Chip8Error::StackUnderflow {
+                address: 0x200,
+                opcode: 0x00EE
+            })
+        );
+    }
+
+    #[test]
+    fn should_format_registers_and_disassembly_window() {
+        let mut chip8 = machine_with_program(&DEBUGGER_PROGRAM);
+        chip8.execute_cycle().unwrap();
+        chip8.state.v[0xF] = 0xAB;
+
+        let registers = format_registers(&chip8);
+        let window = disassembly_window(&chip8.state, &BTreeSet::from([0x208]), 0x206, 1, 1);
+
+        assert!(registers.starts_with("PC=0x206 I=0x000 DT=00 ST=00 SP=1 cycles=1\n"));
+        assert!(registers.contains("VF=AB"));
+        assert!(registers.ends_with("Stack: [0x202]"));
+        assert_eq!(
+            window,
+            vec![
+                "    0x204: 1204 -> JP 0x204",
+                "=>  0x206: 220C -> CALL 0x20C",
+                "  * 0x208: 6202 -> LD V2, 0x02",
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_memory_write_watchpoint() {
+        let mut chip8 = Chip8MachineState::new();
+        // LD I, 0x3F0; LD V0, 0xFE; LD B, V0
+        chip8.state.memory[0x200..0x206].clone_from_slice(&[0xA3, 0xF0, 0x60, 0xFE, 0xF0, 0x33]);
+        chip8
+            .watchpoints
+            .push(Watchpoint::memory(0x3F1, 0x3F1, false, true));
+        chip8
+            .watchpoints
+            .push(Watchpoint::memory(0x3F0, 0x3FF, true, false));
+
+        for _ in 0..3 {
+            chip8.execute_cycle().unwrap();
+        }
+
+        assert_eq!(
+            chip8.watch_hits,
+            vec![WatchHit {
+                watchpoint: 0,
+                pc: 0x204,
+                opcode: 0xF033,
+                event: WatchEvent::MemoryWrite {
+                    address: 0x3F1,
+                    old: 0x00,
+                    new: 0x05
+                },
+            }]
+        );
+        assert_eq!(
+            chip8.watch_hits[0].to_string(),
+            "0x204 (F033): write [0x3F1] 0x00 -> 0x05"
+        );
+    }
+
+    #[test]
+    fn should_report_sprite_read_watchpoint() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x202].clone_from_slice(&[0xD0, 0x05]);
+        chip8.state.i = FONT_ADDRESS;
+        chip8.watchpoints.push(Watchpoint::memory(
+            FONT_ADDRESS as usize + 4,
+            FONT_ADDRESS as usize + 20,
+            true,
+            false,
+        ));
+
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(
+            chip8.watch_hits[0].event,
+            WatchEvent::MemoryRead {
+                address: FONT_ADDRESS as usize + 4,
+                value: 0xF0
+            }
+        );
+    }
+
+    #[test]
+    fn should_report_register_change_watchpoint() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.cycles_per_timer_tick = 1;
+        // LD VF, 0x01; LD V1, 0xFF; ADD V1, V1; LD DT, V1
+        chip8.state.memory[0x200..0x208]
+            .clone_from_slice(&[0x6F, 0x01, 0x61, 0xFF, 0x81, 0x14, 0xF1, 0x15]);
+        chip8
+            .watchpoints
+            .push(Watchpoint::Register(Register::from_name("vf").unwrap()));
+        chip8
+            .watchpoints
+            .push(Watchpoint::Register(Register::DelayTimer));
+
+        chip8.execute_cycle().unwrap();
+        assert_eq!(chip8.watch_hits.len(), 1);
+        chip8.watch_hits.clear();
+
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+        // VF was already 1 after the overflowing add, only the timer write counts
+        chip8.execute_cycle().unwrap();
+
+        assert_eq!(chip8.watch_hits.len(), 1);
+        assert_eq!(chip8.watch_hits[0].pc, 0x206);
+        assert_eq!(
+            chip8.watch_hits[0].event,
+            WatchEvent::RegisterChange {
+                register: Register::DelayTimer,
+                old: 0x00,
+                new: 0xFE
+            }
+        );
+        // Counting down on the timer tick is not a change made by an instruction
+        assert_eq!(chip8.state.delay_timer, 0xFD);
+    }
+
+    #[test]
+    fn should_stop_debugger_on_watchpoint() {
+        let mut chip8 = machine_with_program(&DEBUGGER_PROGRAM);
+        chip8
+            .watchpoints
+            .push(parse_watchpoint("V2", None).unwrap());
+
+        let reason = Debugger::new().run(&mut chip8, RunTarget::Continue, 100);
+
+        match reason {
+            StopReason::Watchpoint(hits) => assert_eq!(hits[0].pc, 0x208),
+            reason => panic!("Unexpected stop: {reason:?}"),
+        }
+        assert!(chip8.watch_hits.is_empty());
+        assert_eq!(
+            parse_watchpoint("0x3F0-0x3F4", Some("w")),
+            Ok(Watchpoint::memory(0x3F0, 0x3F4, false, true))
+        );
+        assert!(parse_watchpoint("V2", Some("r")).is_err());
+        assert!(Command::parse("watch 0x300 x").is_err());
+    }
+
+    #[test]
+    fn should_evaluate_expressions() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.v[0x3] = 0x10;
+        chip8.state.i = 0x301;
+        chip8.state.memory[0x3F0] = 0x07;
+        chip8.cycles = 50001;
+        chip8.set_key(0xA, true);
+
+        let evaluate = |source: &str| Expression::parse(source).unwrap().evaluate(&chip8);
+
+        assert_eq!(evaluate("V3 == 0x10 && I > 0x300"), 1);
+        assert_eq!(evaluate("cycles > 50000"), 1);
+        assert_eq!(evaluate("mem[0x3F0] != 0"), 1);
+        assert_eq!(evaluate("mem[0x3EF + 1] * 2 + v3"), 0x1E);
+        assert_eq!(evaluate("1 + 2 * 3 == 7 || 0"), 1);
+        assert_eq!(evaluate("1 << 2 + 1"), 8);
+        assert_eq!(evaluate("(1 | 2) & ~1"), 2);
+        assert_eq!(evaluate("!key[0xA] || -VF"), 0);
+        assert_eq!(evaluate("V3 / VF + mem[0x10000]"), 0);
+        assert_eq!(evaluate("0b101 % 3"), 2);
+
+        assert!(Expression::parse("V3 ==").is_err());
+        assert!(Expression::parse("VG").is_err());
+        assert!(Expression::parse("mem[1").is_err());
+        assert!(Expression::parse("1 2").is_err());
+        assert!(Expression::parse("V3 = 1").is_err());
+    }
+
+    #[test]
+    fn should_limit_expression_nesting() {
+        let nested = |depth: usize, open: &str, close: &str| {
+            format!("{}1{}", open.repeat(depth), close.repeat(depth))
+        };
+
+        assert!(Expression::parse(&nested(64, "(", ")")).is_ok());
+        assert!(Expression::parse(&nested(60, "mem[", "]")).is_ok());
+        for source in [
+            nested(5000, "(", ")"),
+            nested(5000, "-", ""),
+            nested(5000, "mem[", "]"),
+        ] {
+            assert_eq!(
+                Expression::parse(&source),
+                Err("Expression is nested too deeply".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn should_parse_conditional_breakpoint_commands() {
+        assert_eq!(
+            Command::parse("b 0x202 if V3 == 0x10 && I > 0x300"),
+            Ok(Command::BreakIf {
+                address: Some(0x202),
+                condition: Expression::parse("V3 == 0x10 && I > 0x300").unwrap()
+            })
+        );
+        assert_eq!(
+            Command::parse("break if cycles > 50000"),
+            Ok(Command::BreakIf {
+                address: None,
+                condition: Expression::parse("cycles > 50000").unwrap()
+            })
+        );
+        assert_eq!(
+            Command::parse("log 300 mem[I]"),
+            Ok(Command::Log(0x300, Expression::parse("mem[I]").unwrap()))
+        );
+        assert_eq!(Command::parse("ignore #2 5"), Ok(Command::Ignore(2, 5)));
+        assert_eq!(Command::parse("d #2"), Ok(Command::DeleteId(2)));
+        assert!(Command::parse("b 0x202 when V3").is_err());
+        assert!(Command::parse("b if").is_err());
+    }
+
+    #[test]
+    fn should_stop_on_conditional_breakpoint() {
+        let mut chip8 = Chip8MachineState::new();
+        // ADD V3, 1; JP 0x200
+        chip8.state.memory[0x200..0x204].clone_from_slice(&[0x73, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new();
+        debugger.add(Breakpoint {
+            condition: Some(Expression::parse("V3 == 5").unwrap()),
+            ..Breakpoint::at(0x202)
+        });
+
+        let reason = debugger.run(&mut chip8, RunTarget::Continue, 100);
+
+        assert_eq!(reason, StopReason::Breakpoint(0x202));
+        assert_eq!(chip8.state.v[0x3], 5);
+        assert_eq!(debugger.breakpoints[&1].hits, 1);
+
+        // Without an address the condition is checked after every instruction
+        debugger.add(Breakpoint {
+            address: None,
+            condition: Some(Expression::parse("cycles >= 20").unwrap()),
+            ..Breakpoint::at(0)
+        });
+        debugger.run(&mut chip8, RunTarget::Continue, 100);
+        assert_eq!(chip8.cycles, 20);
+    }
+
+    #[test]
+    fn should_count_hits_and_record_logpoints() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.memory[0x200..0x204].clone_from_slice(&[0x73, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new();
+        let breakpoint = debugger.add_breakpoint(0x202);
+        debugger
+            .breakpoints
+            .get_mut(&breakpoint)
+            .unwrap()
+            .ignore_count = 2;
+        debugger.add(Breakpoint {
+            log: Some(Expression::parse("V3").unwrap()),
+            ..Breakpoint::at(0x202)
+        });
+
+        let reason = debugger.run(&mut chip8, RunTarget::Continue, 100);
+
+        assert_eq!(reason, StopReason::Breakpoint(0x202));
+        assert_eq!(chip8.state.v[0x3], 3);
+        assert_eq!(
+            debugger.log,
+            vec![
+                "0x202: V3 = 0x1 (1)",
+                "0x202: V3 = 0x2 (2)",
+                "0x202: V3 = 0x3 (3)"
+            ]
+        );
+        assert_eq!(
+            debugger.breakpoints[&breakpoint].to_string(),
+            "0x202 ignore 2 (hits: 3)"
+        );
+    }
+
+    #[derive(Default, Clone)]
+    struct SharedBuffer {
+        bytes: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.bytes.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // LD V1, 0x05; ADD V1, 0x01; JP 0x202
+    const TRACE_PROGRAM: [u8; 6] = [0x61, 0x05, 0x71, 0x01, 0x12, 0x02];
+
+    #[test]
+    fn should_write_text_trace() {
+        let buffer = SharedBuffer::default();
+        let mut chip8 = machine_with_program(&TRACE_PROGRAM);
+        chip8.tracer = Some(Tracer::new(Box::new(buffer.clone()), TraceFormat::Text));
+
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+
+        let text = String::from_utf8(buffer.bytes.borrow().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1],
+            "         1 0202 7101 ADD V1, 0x01         \
+         V=00060000000000000000000000000000 I=0000 SP=0 DT=00 ST=00"
+        );
+    }
+
+    #[test]
+    fn should_round_trip_binary_trace() {
+        let buffer = SharedBuffer::default();
+        let mut chip8 = machine_with_program(&TRACE_PROGRAM);
+        chip8.tracer = Some(Tracer::new(Box::new(buffer.clone()), TraceFormat::Binary));
+
+        for _ in 0..4 {
+            chip8.execute_cycle().unwrap();
+        }
+        chip8.tracer.take().unwrap().finish().unwrap();
+
+        let bytes = buffer.bytes.borrow().clone();
+        assert_eq!(bytes.len(), 6 + 4 * TRACE_RECORD_SIZE);
+        let mut reader = Cursor::new(bytes);
+        read_binary_header(&mut reader).unwrap();
+        let mut records = Vec::new();
+        while let Some(record) = TraceRecord::read_binary(&mut reader).unwrap() {
+            records.push(record);
+        }
+
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3].cycle, 3);
+        assert_eq!(records[3].pc, 0x202);
+        assert_eq!(records[3].opcode, 0x7101);
+        assert_eq!(records[3].v[1], 0x07);
+        assert_eq!(records[3].mnemonic(), "ADD V1, 0x01");
+    }
+
+    #[test]
+    fn should_round_trip_empty_binary_trace() {
+        let buffer = SharedBuffer::default();
+        let mut chip8 = machine_with_program(&TRACE_PROGRAM);
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), TraceFormat::Binary);
+        tracer.addresses = Some(0x300..=0x3FF);
+        chip8.tracer = Some(tracer);
+
+        for _ in 0..4 {
+            chip8.execute_cycle().unwrap();
+        }
+        chip8.tracer.take().unwrap().finish().unwrap();
+
+        let mut reader = Cursor::new(buffer.bytes.borrow().clone());
+        read_binary_header(&mut reader).unwrap();
+        assert!(TraceRecord::read_binary(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn should_filter_trace_by_address_and_cycle() {
+        let buffer = SharedBuffer::default();
+        let mut chip8 = machine_with_program(&TRACE_PROGRAM);
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), TraceFormat::Text);
+        tracer.addresses = Some(0x202..=0x202);
+        tracer.cycles = Some(2..6);
+        chip8.tracer = Some(tracer);
+
+        for _ in 0..9 {
+            chip8.execute_cycle().unwrap();
+        }
+
+        let text = String::from_utf8(buffer.bytes.borrow().clone()).unwrap();
+        let cycles: Vec<&str> = text
+            .lines()
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect();
+        // ADD runs on odd cycles, the jump on even ones
+        assert_eq!(cycles, vec!["3", "5"]);
+    }
+
+    #[test]
+    fn should_dump_trace_ring_buffer_on_fault() {
+        let buffer = SharedBuffer::default();
+        let mut chip8 = Chip8MachineState::new();
+        // Five LD instructions, then an unknown opcode
+        for (index, address) in (0x200..0x20A).step_by(2).enumerate() {
+            chip8.state.memory[address..address + 2].clone_from_slice(&[0x60, index as u8]);
+        }
+        chip8.state.memory[0x20A..0x20C].clone_from_slice(&[0xE0, 0x00]);
+        chip8.tracer = Some(Tracer::ring_buffer(
+            Box::new(buffer.clone()),
+            TraceFormat::Text,
+            3,
+        ));
+
+        for _ in 0..5 {
+            chip8.execute_cycle().unwrap();
+        }
+        assert!(buffer.bytes.borrow().is_empty());
+        assert!(chip8.execute_cycle().is_err());
+
+        let text = String::from_utf8(buffer.bytes.borrow().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("0204 6002"));
+        assert!(lines[2].contains("0208 6004"));
+        assert_eq!(lines[3], "# Unknown or invalid instruction at 0x20A: E000");
+    }
+
+    // RND V0, 0xFF; LD I, 0x050; DRW V0, V1, 5; JP 0x200
+    const REWIND_PROGRAM: [u8; 8] = [0xC0, 0xFF, 0xA0, 0x50, 0xD0, 0x15, 0x12, 0x00];
+
+    #[test]
+    fn should_step_back_through_rewind_history() {
+        let mut chip8 = machine_with_program(&REWIND_PROGRAM);
+        chip8.random = RandomSource::seeded(7);
+        let mut rewind = Rewind::new(100, 1);
+        let mut snapshots = Vec::new();
+        for _ in 0..20 {
+            rewind.record(&chip8);
+            snapshots.push(chip8.save_state());
+            for _ in 0..10 {
+                chip8.execute_cycle().unwrap();
+            }
+        }
+        assert_eq!(rewind.len(), 20);
+        // Deltas between frames are much smaller than whole snapshots
+        assert!(rewind.size() < 2 * snapshots[0].len());
+
+        for expected in snapshots.iter().rev() {
+            assert!(rewind.step_back(&mut chip8));
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert!(rewind.is_empty());
+        assert!(!rewind.step_back(&mut chip8));
+        assert_eq!(chip8.save_state(), snapshots[0]);
+    }
+
+    #[test]
+    fn should_replay_identically_after_rewind() {
+        let mut chip8 = machine_with_program(&REWIND_PROGRAM);
+        chip8.random = RandomSource::seeded(7);
+        let mut rewind = Rewind::default();
+        rewind.record(&chip8);
+        for _ in 0..300 {
+            chip8.execute_cycle().unwrap();
+        }
+        let expected = chip8.save_state();
+
+        rewind.step_back(&mut chip8);
+        for _ in 0..300 {
+            chip8.execute_cycle().unwrap();
+        }
+
+        assert_eq!(chip8.save_state(), expected);
+    }
+
+    #[test]
+    fn should_limit_rewind_depth_and_granularity() {
+        let mut chip8 = machine_with_program(&REWIND_PROGRAM);
+        chip8.random = RandomSource::seeded(7);
+        let mut rewind = Rewind::new(3, 2);
+        let mut snapshots = Vec::new();
+        for _ in 0..10 {
+            snapshots.push(chip8.save_state());
+            rewind.record(&chip8);
+            chip8.execute_cycle().unwrap();
+        }
+
+        // Every second frame is kept, and only the newest three of those
+        assert_eq!(rewind.len(), 3);
+        for expected in [&snapshots[9], &snapshots[7], &snapshots[5]] {
+            rewind.step_back(&mut chip8);
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn should_skip_unchanged_rewind_snapshots() {
+        let mut chip8 = machine_with_program(&REWIND_PROGRAM);
+        chip8.random = RandomSource::seeded(7);
+        chip8.watchpoints.push(Watchpoint::Register(Register::I));
+        let mut rewind = Rewind::new(10, 1);
+        for _ in 0..5 {
+            rewind.record(&chip8);
+        }
+        assert_eq!(rewind.len(), 1);
+
+        chip8.execute_cycle().unwrap();
+        rewind.record(&chip8);
+        rewind.step_back(&mut chip8);
+        rewind.step_back(&mut chip8);
+        assert_eq!(chip8.state.pc, 0x200);
+        // Session state survives rewinding
+        assert_eq!(chip8.watchpoints.len(), 1);
+    }
+
+    #[test]
+    fn should_parse_key_script() {
+        let events = parse_key_script("30:5+ 45:5-, 10:a+ # fire\n# comment\n10:F-").unwrap();
+
+        assert_eq!(
+            events,
+            vec![
+                KeyEvent {
+                    frame: 10,
+                    key: 0xA,
+                    pressed: true
+                },
+                KeyEvent {
+                    frame: 10,
+                    key: 0xF,
+                    pressed: false
+                },
+                KeyEvent {
+                    frame: 30,
+                    key: 0x5,
+                    pressed: true
+                },
+                KeyEvent {
+                    frame: 45,
+                    key: 0x5,
+                    pressed: false
+                },
+            ]
+        );
+        assert!(parse_key_script("30:5").is_err());
+        assert!(parse_key_script("30:10+").is_err());
+        assert_eq!(
+            parse_key_script("1:1+\nx:1+"),
+            Err("Line 2: invalid key event: x:1+".to_string())
+        );
+    }
+
+    #[test]
+    fn should_run_headless_until_self_jump() {
+        let mut chip8 = Chip8MachineState::new();
+        // LD V0, 0x05; ADD V0, 0x01; JP 0x204
+        chip8.state.memory[0x200..0x206].clone_from_slice(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x04]);
+        let runner = Runner {
+            stop_on_self_jump: true,
+            max_frames: Some(10),
+            ..Runner::new()
+        };
+
+        let report = runner.run(&mut chip8);
+
+        assert_eq!(report.outcome, RunOutcome::SelfJump(0x204));
+        assert_eq!(report.cycles, 2);
+        assert_eq!(chip8.state.v[0], 0x06);
+    }
+
+    #[test]
+    fn should_run_headless_until_limits_and_addresses() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.cycles_per_timer_tick = 4;
+        // ADD V0, 0x01; ADD V1, 0x01; JP 0x200
+        chip8.state.memory[0x200..0x206].clone_from_slice(&[0x70, 0x01, 0x71, 0x01, 0x12, 0x00]);
+
+        let runner = Runner {
+            max_frames: Some(3),
+            ..Runner::new()
+        };
+        let report = runner.run(&mut chip8);
+        assert_eq!(report.outcome, RunOutcome::FrameLimit);
+        assert_eq!((report.cycles, report.frames), (12, 3));
+
+        let runner = Runner {
+            max_cycles: Some(5),
+            ..Runner::new()
+        };
+        let report = runner.run(&mut chip8);
+        assert_eq!(report.outcome, RunOutcome::CycleLimit);
+        assert_eq!((report.cycles, report.frames), (5, 1));
+
+        let runner = Runner {
+            stop_addresses: BTreeSet::from([0x202]),
+            ..Runner::new()
+        };
+        let report = runner.run(&mut chip8);
+        assert_eq!(report.outcome, RunOutcome::Address(0x202));
+        assert_eq!(chip8.state.pc, 0x202);
+    }
+
+    #[test]
+    fn should_run_headless_with_scripted_keys() {
+        let mut chip8 = Chip8MachineState::new();
+        chip8.cycles_per_timer_tick = 10;
+        // LD V0, K; JP 0x202
+        chip8.state.memory[0x200..0x204].clone_from_slice(&[0xF0, 0x0A, 0x12, 0x02]);
+        let runner = Runner {
+            stop_on_self_jump: true,
+            max_frames: Some(100),
+            keys: parse_key_script("5:7+ 6:7-").unwrap(),
+            ..Runner::new()
+        };
+
+        let report = runner.run(&mut chip8);
+
+        assert_eq!(report.outcome, RunOutcome::SelfJump(0x202));
+        assert_eq!(chip8.state.v[0], 0x7);
+        // Stopped during frame 5, when the key went down
+        assert_eq!(report.frames, 5);
+    }
+
+    #[test]
+    fn should_report_headless_fault_and_dump_screen() {
+        let mut chip8 = Chip8MachineState::new();
+        // LD I, 0x050; DRW V0, V0, 5; unknown opcode
+        chip8.state.memory[0x200..0x206].clone_from_slice(&[0xA0, 0x50, 0xD0, 0x05, 0xE0, 0x00]);
+
+        let report = Runner::new().run(&mut chip8);
+
+        assert_eq!(
+            report.outcome,
+            RunOutcome::Fault(Chip8Error::UnknownOpcode {
+                address: 0x204,
+                opcode: 0xE000
+            })
+        );
+        let dump = screenshot::ascii(&chip8.display);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), SCREEN_HEIGHT);
+        // The "0" glyph
+        assert!(lines[0].starts_with("####...."));
+        assert!(lines[1].starts_with("#..#...."));
+    }
+
+    // A 4x3 display: a lit pixel in each corner, one on the second plane.
+    fn screenshot_display() -> FrameBuffer {
+        let mut display = FrameBuffer::new(4, 3);
+        display.set_planes(0, 0, 1);
+        display.set_planes(3, 0, 1);
+        display.set_planes(0, 2, 3);
+        display.set_planes(3, 2, 2);
+        display
+    }
+
+    #[test]
+    fn should_dump_screenshot_as_text() {
+        let display = screenshot_display();
+
+        assert_eq!(screenshot::ascii(&display), "#..#\n....\n3..2\n");
+        assert_eq!(screenshot::unicode(&display), "▀  ▀\n▀  ▀\n");
+    }
+
+    #[test]
+    fn should_encode_screenshot_as_ppm() {
+        let palette = parse_palette("101010,F0F0F0").unwrap();
+        let ppm = screenshot::screenshot(&screenshot_display(), ScreenshotFormat::Ppm, 2, &palette);
+
+        let header = b"P6\n8 6\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        let pixels = &ppm[header.len()..];
+        assert_eq!(pixels.len(), 8 * 6 * 3);
+        assert_eq!(&pixels[0..6], &[0xF0; 6]);
+        assert_eq!(&pixels[6..9], &[0x10; 3]);
+        // Second row of the scaled image repeats the first
+        assert_eq!(&pixels[24..30], &[0xF0; 6]);
+        // Both planes lit, bottom left
+        assert_eq!(&pixels[4 * 24..4 * 24 + 3], &[0x55; 3]);
+    }
+
+    #[test]
+    fn should_encode_screenshot_as_png() {
+        let png = screenshot::encode_png(&screenshot_display(), 1, &DEFAULT_PALETTE);
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        // IHDR: 4x3, 8-bit indexed
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..29], &[0, 0, 0, 4, 0, 0, 0, 3, 8, 3, 0, 0, 0]);
+        // PLTE with the four palette colours
+        assert_eq!(&png[37..41], b"PLTE");
+        assert_eq!(&png[41..44], &[0x00, 0x00, 0x00]);
+        assert_eq!(&png[44..47], &[0xFF, 0xFF, 0xFF]);
+        // IDAT holds one stored block of filter byte + indexes per row
+        assert_eq!(&png[61..65], b"IDAT");
+        assert_eq!(&png[65..72], &[0x78, 0x01, 1, 15, 0, !15, 0xFF]);
+        assert_eq!(&png[72..87], &[0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 3, 0, 0, 2]);
+        // IEND has a fixed CRC
+        assert_eq!(&png[png.len() - 8..], b"IEND\xAE\x42\x60\x82");
+        // Same pixels, same bytes
+        assert_eq!(
+            png,
+            screenshot::screenshot(
+                &screenshot_display(),
+                ScreenshotFormat::Png,
+                1,
+                &DEFAULT_PALETTE
+            )
+        );
+    }
+
+    #[test]
+    fn should_parse_screenshot_options() {
+        assert_eq!(
+            parse_palette("#112233, 445566").unwrap(),
+            [0x112233, 0x445566, 0xAAAAAA, 0x555555]
+        );
+        assert!(parse_palette("12345").is_err());
+        assert!(parse_palette("000000,000000,000000,000000,000000").is_err());
+        assert_eq!(
+            ScreenshotFormat::from_path("out/frame.PPM"),
+            Some(ScreenshotFormat::Ppm)
+        );
+        assert_eq!(
+            ScreenshotFormat::from_path("frame.txt"),
+            Some(ScreenshotFormat::Ascii)
+        );
+        assert_eq!(ScreenshotFormat::from_path("frame"), None);
+    }
+
+    // Adds random numbers to V2 while key 0 is down:
+    // LD V0, 0x00; RND V1, 0x0F; SKNP V0; ADD V2, V1; JP 0x202
+    const MOVIE_ROM: [u8; 10] = [0x60, 0x00, 0xC1, 0x0F, 0xE0, 0xA1, 0x82, 0x14, 0x12, 0x02];
+
+    fn record_movie(frames: usize) -> (Movie, Chip8MachineState) {
+        let mut chip8 = Chip8MachineState::with_platform(Platform::Chip8, Quirks::COSMAC_VIP);
+        chip8.state.load_rom_bytes(&MOVIE_ROM, 0x200).unwrap();
+        let mut scheduler = Scheduler::new(600);
+        chip8.cycles_per_timer_tick = scheduler.instructions_per_frame();
+        let mut movie = Movie::record(&chip8, &MOVIE_ROM);
+
+        for frame in 0..frames {
+            chip8.set_key(0x0, frame % 3 == 0);
+            scheduler
+                .run_frame_with(&mut chip8, |chip8| movie.record_frame(&chip8.keyboard))
+                .unwrap();
+        }
+        movie.finish(&chip8);
+        (movie, chip8)
+    }
+
+    #[test]
+    fn should_replay_movie_exactly() {
+        let (movie, recorded) = record_movie(30);
+        assert_eq!(movie.frames.len(), 30);
+        assert_eq!(movie.frames[..4], [0x0001, 0, 0, 0x0001]);
+
+        let mut chip8 = movie.start(&MOVIE_ROM).unwrap();
+        let mut scheduler = Scheduler::new(600);
+        let mut frame = 0;
+        for _ in 0..30 {
+            scheduler
+                .run_frame_with(&mut chip8, |chip8| {
+                    assert!(movie.play_frame(frame, chip8));
+                    frame += 1;
+                })
+                .unwrap();
+        }
+
+        assert_eq!(chip8.save_state(), recorded.save_state());
+        assert_eq!(movie.matches_end_state(&chip8), Some(true));
+        assert!(!movie.play_frame(30, &mut chip8));
+    }
+
+    #[test]
+    fn should_ignore_live_keys_when_checking_movie_end_state() {
+        let (movie, mut chip8) = record_movie(10);
+
+        chip8.set_key(0x5, true);
+        assert_eq!(movie.matches_end_state(&chip8), Some(true));
+        chip8.state.v[2] ^= 0xFF;
+        assert_eq!(movie.matches_end_state(&chip8), Some(false));
+    }
+
+    #[test]
+    fn should_replay_movie_with_headless_runner() {
+        let (movie, recorded) = record_movie(20);
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+
+        let mut chip8 = movie.start(&MOVIE_ROM).unwrap();
+        let runner = Runner {
+            max_frames: Some(movie.frames.len() as u64),
+            keys: movie.key_events(),
+            ..Runner::new()
+        };
+        let report = runner.run(&mut chip8);
+
+        assert_eq!(report.frames, 20);
+        assert_eq!(chip8.state.v[2], recorded.state.v[2]);
+        assert_eq!(movie.matches_end_state(&chip8), Some(true));
+    }
+
+    #[test]
+    fn should_round_trip_movie_file() {
+        let (movie, _) = record_movie(5);
+        let bytes = movie.to_bytes();
+
+        assert_eq!(&bytes[..4], MOVIE_MAGIC);
+        assert_eq!(Movie::from_bytes(&bytes), Ok(movie.clone()));
+        assert_eq!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MovieError::Invalid("frame count"))
+        );
+        assert_eq!(Movie::from_bytes(b"C8SS"), Err(MovieError::BadMagic));
+        assert_eq!(
+            movie.key_events(),
+            vec![
+                KeyEvent {
+                    frame: 0,
+                    key: 0,
+                    pressed: true
+                },
+                KeyEvent {
+                    frame: 1,
+                    key: 0,
+                    pressed: false
+                },
+                KeyEvent {
+                    frame: 3,
+                    key: 0,
+                    pressed: true
+                },
+                KeyEvent {
+                    frame: 4,
+                    key: 0,
+                    pressed: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_reject_movie_for_another_rom() {
+        let (movie, _) = record_movie(1);
+        let mut rom = MOVIE_ROM;
+        rom[1] = 0x01;
+
+        assert_eq!(movie.start(&rom).err(), Some(MovieError::RomMismatch));
+    }
+
+    #[test]
+    fn should_not_panic_disassembling_the_last_byte() {
+        let cpu = Chip8State::new();
+        let last = cpu.memory.len() - 1;
+
+        assert_eq!(cpu.disassemble(last), "0xFFF: 00 -> Incomplete instruction");
+        assert_eq!(cpu.disassemble(last + 1), "0x1000: Outside memory");
+        assert_eq!(decrypt_chip8_instruction(&[0xC3, 0x0F]), "RND V3, 0x0F");
+    }
+
+    // CALL 0x208; LD I, 0x20E; JP 0x206 (self jump); sub: DRW V0, V1, 2; RET;
+    // then a sprite and a stray byte nothing reaches.
+    const LISTING_ROM: [u8; 17] = [
+        0x22, 0x08, 0xA2, 0x0E, 0x12, 0x06, 0x12, 0x06, 0xD0, 0x12, 0x00, 0xEE, 0x3F, 0x01, 0xFF,
+        0x81, 0x42,
+    ];
+
+    #[test]
+    fn should_separate_code_from_data() {
+        let disassembly = ProgramDisassembly::new(&LISTING_ROM);
+
+        assert_eq!(
+            disassembly.instructions.iter().copied().collect::<Vec<_>>(),
+            vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20A]
+        );
+        assert_eq!(disassembly.label(0x200).as_deref(), Some("start"));
+        assert_eq!(disassembly.label(0x206).as_deref(), Some("label_206"));
+        assert_eq!(disassembly.label(0x208).as_deref(), Some("sub_208"));
+        assert_eq!(disassembly.label(0x20E).as_deref(), Some("data_20E"));
+        assert_eq!(disassembly.label(0x202), None);
+    }
+
+    #[test]
+    fn should_list_a_program_with_labels_and_data() {
+        let listing = ProgramDisassembly::new(&LISTING_ROM).listing();
+
+        assert_eq!(
+            listing,
+            "start:\n\
+         \x20   CALL sub_208            ; 200: 2208\n\
+         \x20   LD I, data_20E          ; 202: A20E\n\
+         \x20   JP label_206            ; 204: 1206\n\
+         label_206:\n\
+         \x20   JP label_206            ; 206: 1206\n\
+         sub_208:\n\
+         \x20   DRW V0, V1, 0x2         ; 208: D012\n\
+         \x20   RET                     ; 20A: 00EE\n\
+         \x20   DB 0x3F, 0x01           ; 20C\n\
+         data_20E:\n\
+         \x20   DB 0xFF, 0x81, 0x42     ; 20E\n"
+        );
+    }
+
+    #[test]
+    fn should_follow_both_paths_of_a_skip() {
+        // SE V0, 0x00; JP 0x208; LD V1, 0x01; EXIT; LD V2, 0x02; EXIT
+        let rom = [
+            0x30, 0x00, 0x12, 0x08, 0x61, 0x01, 0x00, 0xFD, 0x62, 0x02, 0x00, 0xFD,
+        ];
+        let disassembly = ProgramDisassembly::new(&rom);
+
+        assert_eq!(disassembly.instructions.len(), 6);
+        assert!(
+            disassembly
+                .listing()
+                .contains("label_208:\n    LD V2, 0x02")
+        );
+    }
+
+    #[test]
+    fn should_treat_unknown_instructions_as_data() {
+        // LD V0, 0x01; then 0xFFFF, which does not decode
+        let disassembly = ProgramDisassembly::new(&[0x60, 0x01, 0xFF, 0xFF, 0x12]);
+
+        assert_eq!(disassembly.instructions.len(), 1);
+        assert!(
+            disassembly
+                .listing()
+                .ends_with("    DB 0xFF, 0xFF, 0x12     ; 202\n")
+        );
+    }
+
+    #[test]
+    fn should_keep_the_shift_source_register_in_listings() {
+        // SHR V1, V2; SHL V3; EXIT
+        let listing = ProgramDisassembly::new(&[0x81, 0x26, 0x83, 0x0E, 0x00, 0xFD]).listing();
+
+        assert!(listing.contains("    SHR V1, V2 "));
+        assert!(listing.contains("    SHL V3 "));
+    }
+
+    #[test]
+    fn should_assemble_every_instruction() {
+        let source = "
+        CLS
+        RET
+        SYS 0x111
+        JP 0x222
+        CALL 0x333
+        SE VE, 0xAA
+        SNE VF, 0xBB
+        SE V1, V2
+        LD VF, 0x10
+        ADD V0, 0x01
+        LD V1, V1
+        OR V2, V2
+        AND V3, V3
+        XOR V4, V4
+        ADD V5, V5
+        SUB V6, V6
+        SHR V7, V7
+        SUBN V8, V8
+        SHL V9, V9
+        SNE V8, V8
+        LD I, 0x999
+        JP V0, 0xAAA
+        RND VF, 0xBB
+        DRW V1, V2, 0x3
+        SKP V0
+        SKNP V0
+        LD V1, DT
+        LD V2, K
+        LD DT, V3
+        LD ST, V4
+        ADD I, V5
+        LD F, V6
+        LD B, V7
+        LD [I], V8
+        LD V9, [I]
+    ";
+        let expected = Chip8State::with_all_instructions().memory[0x200..0x246].to_vec();
+
+        assert_eq!(assemble(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn should_assemble_super_and_xo_chip_instructions() {
+        let source = "
+        SCD 0x3
+        SCU 0x4
+        SCR
+        SCL
+        EXIT
+        LOW
+        HIGH
+        LD HF, V1
+        LD R, V2
+        LD V3, R
+        LD [I], V1-V4
+        LD V1-V4, [I]
+        PLANE 0x2
+        AUDIO
+        LD PITCH, V7
+        LD I, LONG 0x1234
+    ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            [
+                0x00, 0xC3, 0x00, 0xD4, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFD, 0x00, 0xFE, 0x00, 0xFF,
+                0xF1, 0x30, 0xF2, 0x75, 0xF3, 0x85, 0x51, 0x42, 0x51, 0x43, 0xF2, 0x01, 0xF0, 0x02,
+                0xF7, 0x3A, 0xF0, 0x00, 0x12, 0x34
+            ]
+        );
+    }
+
+    #[test]
+    fn should_round_trip_disassembly_listings() {
+        let listing = ProgramDisassembly::new(&LISTING_ROM).listing();
+        assert_eq!(assemble(&listing).unwrap(), LISTING_ROM);
+
+        // The listing of every decodable instruction, lone byte included
+        let all = &Chip8State::with_all_instructions().memory[0x200..0x247];
+        let listing = ProgramDisassembly::new(all).listing();
+        assert_eq!(assemble(&listing).unwrap(), all);
+    }
+
+    #[test]
+    fn should_assemble_labels_constants_and_data() {
+        let source = "
+        SPEED EQU 3
+        TOP = sprite + 1     ; constants may use labels defined later
+        start:  LD V0, SPEED
+                ADD V0, -1
+                LD I, TOP
+                JP start
+        sprite:
+                DB 0x3C, 0b01000010, \"Hi, there\"
+                DW 0x1234, start
+    ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            [
+                0x60, 0x03, 0x70, 0xFF, 0xA2, 0x09, 0x12, 0x00, 0x3C, 0x42, b'H', b'i', b',', b' ',
+                b't', b'h', b'e', b'r', b'e', 0x12, 0x34, 0x02, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn should_assemble_included_files() {
+        let source = "JP main\nINCLUDE \"lib/sprites.asm\"\nmain: LD I, ball";
+        let included = |path: &std::path::Path| match path.to_str() {
+            Some("lib/sprites.asm") => Ok("ball: DB 0x80\nINCLUDE \"more.asm\"".to_string()),
+            Some("lib/more.asm") => Ok("DB 0x81".to_string()),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        };
+
+        assert_eq!(
+            assemble_with(source, included).unwrap(),
+            [0x12, 0x04, 0x80, 0x81, 0xA2, 0x02]
+        );
+    }
+
+    #[test]
+    fn should_report_assembly_errors_with_line_numbers() {
+        let error = |source: &str| assemble(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error("CLS\n  JMP 0x200"),
+            "Line 2: Unknown instruction: JMP"
+        );
+        assert_eq!(error("LD V0"), "Line 1: Invalid operands for LD");
+        assert_eq!(error("\n\nLD V0, 0x100"), "Line 3: Value out of range: 256");
+        assert_eq!(error("JP nowhere"), "Line 1: Unknown symbol: nowhere");
+        assert_eq!(error("a: CLS\na: CLS"), "Line 2: Symbol defined twice: a");
+        assert_eq!(error("V1 EQU 2"), "Line 1: Invalid symbol name: V1");
+        assert_eq!(
+            error("X EQU Y\nY EQU X\nJP X"),
+            "Line 3: Constant defined in terms of itself"
+        );
+        assert_eq!(error("LD V0, 1 +"), "Line 1: Missing value: 1 +");
+
+        let included = assemble_with(
+            "CLS\nINCLUDE \"bad.asm\"",
+            |_| Ok("CLS\nDRW V0".to_string()),
+        );
+        assert_eq!(
+            included,
+            Err(AssemblyError {
+                path: Some("bad.asm".into()),
+                line: 2,
+                message: "Invalid operands for DRW".to_string(),
+            })
+        );
+        assert_eq!(
+            included.unwrap_err().to_string(),
+            "bad.asm:2: Invalid operands for DRW"
+        );
+    }
+
+    fn compile_octo(source: &str) -> OctoProgram {
+        octo::compile(source).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    #[test]
+    fn should_compile_octo_statements() {
+        let program = compile_octo(
+            "
+        : main
+            clear
+            v3 := 0x10   v3 += 1   v3 -= 1   v3 := v4   v3 =- v4
+            v3 |= v4   v3 &= v4   v3 ^= v4   v3 >>= v4   v3 <<= v4
+            v1 := random 0x0F   v2 := key   v2 := delay
+            delay := v1   buzzer := v2   pitch := v3
+            i := 0x300   i := hex v5   i := bighex v6   i += v7   i := long 0x1234
+            sprite v1 v2 5   bcd v8   save v9   load va   save v1 - v4   load v1 - v4
+            saveflags v3   loadflags v4   scroll-down 2   scroll-up 3   scroll-left
+            scroll-right   hires   lores   plane 3   audio   native 0x123   jump0 0x400
+            return   exit
+        ",
+        );
+
+        assert_eq!(
+            program.rom,
+            [
+                0x00, 0xE0, 0x63, 0x10, 0x73, 0x01, 0x73, 0xFF, 0x83, 0x40, 0x83, 0x47, 0x83, 0x41,
+                0x83, 0x42, 0x83, 0x43, 0x83, 0x46, 0x83, 0x4E, 0xC1, 0x0F, 0xF2, 0x0A, 0xF2, 0x07,
+                0xF1, 0x15, 0xF2, 0x18, 0xF3, 0x3A, 0xA3, 0x00, 0xF5, 0x29, 0xF6, 0x30, 0xF7, 0x1E,
+                0xF0, 0x00, 0x12, 0x34, 0xD1, 0x25, 0xF8, 0x33, 0xF9, 0x55, 0xFA, 0x65, 0x51, 0x42,
+                0x51, 0x43, 0xF3, 0x75, 0xF4, 0x85, 0x00, 0xC2, 0x00, 0xD3, 0x00, 0xFC, 0x00, 0xFB,
+                0x00, 0xFF, 0x00, 0xFE, 0xF3, 0x01, 0xF0, 0x02, 0x01, 0x23, 0xB4, 0x00, 0x00, 0xEE,
+                0x00, 0xFD
+            ]
+        );
+    }
+
+    #[test]
+    fn should_jump_to_main_and_resolve_forward_labels() {
+        let program = compile_octo(
+            "
+        : sprite 0x3C 0b01000010
+        : draw i := sprite sprite v0 v1 2 ;
+        : main draw jump end
+        : end jump end
+        ",
+        );
+
+        assert_eq!(
+            program.rom,
+            [
+                0x12, 0x0A, 0x3C, 0x42, 0xA2, 0x02, 0xD0, 0x12, 0x00, 0xEE, 0x22, 0x04, 0x12, 0x0E,
+                0x12, 0x0E
+            ]
+        );
+        assert_eq!(program.labels["main"], 0x20A);
+        assert_eq!(program.labels["end"], 0x20E);
+    }
+
+    #[test]
+    fn should_compile_octo_control_flow() {
+        let program = compile_octo(
+            "
+        : main
+            if v0 == 1 then v1 := 2
+            if v0 != v2 begin
+                v3 := 4
+            else
+                v3 := 5
+            end
+            loop
+                v0 += 1
+                while v0 != 10
+                if v4 key then v5 := 1
+            again
+        ",
+        );
+
+        assert_eq!(
+            program.rom,
+            [
+                // if ... then skips the statement when the condition is false
+                0x40, 0x01, 0x61, 0x02,
+                // if ... begin jumps to the else branch when it is false
+                0x90, 0x20, 0x12, 0x0C, 0x63, 0x04, 0x12, 0x0E, 0x63, 0x05,
+                // loop ... while ... again
+                0x70, 0x01, 0x40, 0x0A, 0x12, 0x1A, 0xE4, 0xA1, 0x65, 0x01, 0x12, 0x0E,
+            ]
+        );
+    }
+
+    #[test]
+    fn should_compile_octo_comparisons_through_vf() {
+        let program = compile_octo(": main if v1 < v2 then v0 := 1 if v1 >= 5 then v0 := 2");
+
+        assert_eq!(
+            program.rom,
+            [
+                0x8F, 0x10, 0x8F, 0x25, 0x4F, 0x00, 0x60, 0x01, 0x6F, 0x05, 0x8F, 0x17, 0x4F, 0x01,
+                0x60, 0x02
+            ]
+        );
+    }
+
+    #[test]
+    fn should_run_compiled_octo_comparisons() {
+        // Counts v0 up while it is below 7, then v1 holds whether v0 > 6
+        let program = compile_octo(
+            "
+        : main
+            loop
+                v0 += 1
+                while v0 < 7
+            again
+            if v0 > 6 then v1 := 1
+        : done jump done
+        ",
+        );
+        let mut chip8 = Chip8MachineState::new();
+        chip8.state.load_rom_bytes(&program.rom, 0x200).unwrap();
+        let runner = Runner {
+            max_cycles: Some(1000),
+            stop_on_self_jump: true,
+            ..Runner::new()
+        };
+
+        let report = runner.run(&mut chip8);
+
+        assert_eq!(report.outcome, RunOutcome::SelfJump(program.labels["done"]));
+        assert_eq!(chip8.state.v[0], 7);
+        assert_eq!(chip8.state.v[1], 1);
+    }
+
+    #[test]
+    fn should_compile_octo_directives() {
+        let program = compile_octo(
+            "
+        :alias counter v4
+        :const SPEED 3
+        :calc DOUBLE { SPEED * 2 + 1 }      # right to left: 3 * (2 + 1)
+        :calc MASK { ( 1 << 4 ) - 1 }
+        :macro add-twice register amount { register += amount register += amount }
+        : main
+            counter := SPEED
+            add-twice counter DOUBLE
+        :next target
+            v0 := 0
+            v1 := MASK
+            :unpack 0xA data
+            :byte { DOUBLE + 1 }
+            :call data
+        : data
+        ",
+        );
+
+        assert_eq!(
+            program.rom,
+            [
+                0x64, 0x03, 0x74, 0x09, 0x74, 0x09, 0x60, 0x00, 0x61, 0x0F, 0x60, 0xA2, 0x61, 0x11,
+                0x0A, 0x22, 0x11
+            ]
+        );
+        assert_eq!(program.labels["target"], 0x207);
+    }
+
+    #[test]
+    fn should_map_octo_code_to_source_lines() {
+        let program =
+            compile_octo(": main\n  v0 := 1\n\n  if v0 == 1 then\n    v1 := 2\n  :byte 7");
+        let map = &program.source_map;
+
+        assert_eq!(map.line(0x200), Some(2));
+        assert_eq!(map.line(0x202), Some(4));
+        assert_eq!(map.line(0x203), Some(4));
+        assert_eq!(map.line(0x204), Some(5));
+        assert_eq!(map.line(0x206), Some(6));
+        assert_eq!(map.line(0x207), None);
+        assert_eq!(map.address(5), Some(0x204));
+
+        let mut debugger = Debugger::new();
+        debugger.source_map = Some(program.source_map.clone());
+        assert_eq!(debugger.source_line(0x204), Some(5));
+    }
+
+    #[test]
+    fn should_report_octo_errors_with_line_numbers() {
+        let error = |source: &str| octo::compile(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error(": main\n  v0 := 0x100"),
+            "Line 2: Value out of range: 0x100"
+        );
+        assert_eq!(
+            error(": main\n\n  jump nowhere"),
+            "Line 3: Undefined name: nowhere"
+        );
+        assert_eq!(error("v0 := 1"), "Line 1: Missing main label");
+        assert_eq!(error(": main\n  loop\n  v0 += 1"), "Line 2: Missing again");
+        assert_eq!(error(": main else"), "Line 1: else without if ... begin");
+        assert_eq!(error(": main : main"), "Line 1: Name already defined: main");
+        assert_eq!(error(": main v0 := vz"), "Line 1: Undefined name: vz");
+        assert_eq!(
+            error(": main if v0 = 1 then"),
+            "Line 1: Invalid condition: ="
+        );
+        assert_eq!(
+            error(":macro loop-forever { loop-forever }\n: main loop-forever"),
+            "Line 1: Macro expands itself: loop-forever"
+        );
+        assert_eq!(
+            error(":calc X { 1 + }\n: main"),
+            "Line 1: Missing value in expression"
+        );
+    }
+
+    // A GIF whose colour indices carry `payload` the way Octo cartridges do. The
+    // LZW stream clears its table before codes would grow past 9 bits.
+    fn cartridge_gif(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        let mut pixels: Vec<u8> = bytes
+            .iter()
+            .flat_map(|byte| [6, 4, 2, 0].map(|shift| byte >> shift & 3 | 0x10))
+            .collect();
+        let width = 64;
+        pixels.resize(pixels.len().div_ceil(width) * width, 0);
+
+        let mut codes = Vec::new();
+        for chunk in pixels.chunks(250) {
+            codes.push(0x100);
+            codes.extend(chunk.iter().map(|&pixel| pixel as u32));
+        }
+        codes.push(0x101);
+        let mut data = Vec::new();
+        let (mut bits, mut bit_count) = (0u32, 0);
+        for code in codes {
+            bits |= code << bit_count;
+            bit_count += 9;
+            while bit_count >= 8 {
+                data.push(bits as u8);
+                bits >>= 8;
+                bit_count -= 8;
+            }
+        }
+        data.push(bits as u8);
+
+        let height = (pixels.len() / width) as u16;
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend([64, 0, height as u8, (height >> 8) as u8, 0x87, 0, 0]);
+        gif.extend([0; 256 * 3]);
+        // A comment extension to skip
+        gif.extend([0x21, 0xFE, 2, b'h', b'i', 0]);
+        gif.extend([
+            0x2C,
+            0,
+            0,
+            0,
+            0,
+            64,
+            0,
+            height as u8,
+            (height >> 8) as u8,
+            0,
+            8,
+        ]);
+        for block in data.chunks(255) {
+            gif.push(block.len() as u8);
+            gif.extend_from_slice(block);
+        }
+        gif.extend([0, 0x3B]);
+        gif
+    }
+
+    // A zip archive with a central directory. Entries are (name, contents,
+    // deflated).
+    fn zip_archive(entries: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut directory = Vec::new();
+        for &(name, contents, deflated) in entries {
+            let data = match deflated {
+                true => miniz_oxide::deflate::compress_to_vec(contents, 6),
+                false => contents.to_vec(),
+            };
+            let method: u16 = if deflated { 8 } else { 0 };
+            let offset = zip.len() as u32;
+
+            zip.extend(b"PK\x03\x04");
+            zip.extend([20, 0, 0, 0]);
+            zip.extend(method.to_le_bytes());
+            zip.extend([0; 8]);
+            zip.extend((data.len() as u32).to_le_bytes());
+            zip.extend((contents.len() as u32).to_le_bytes());
+            zip.extend((name.len() as u16).to_le_bytes());
+            zip.extend([0, 0]);
+            zip.extend(name.as_bytes());
+            zip.extend(&data);
+
+            directory.extend(b"PK\x01\x02");
+            directory.extend([20, 0, 20, 0, 0, 0]);
+            directory.extend(method.to_le_bytes());
+            directory.extend([0; 8]);
+            directory.extend((data.len() as u32).to_le_bytes());
+            directory.extend((contents.len() as u32).to_le_bytes());
+            directory.extend((name.len() as u16).to_le_bytes());
+            directory.extend([0; 12]);
+            directory.extend(offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+
+        let directory_offset = zip.len() as u32;
+        zip.extend(&directory);
+        zip.extend(b"PK\x05\x06");
+        zip.extend([0; 4]);
+        zip.extend((entries.len() as u16).to_le_bytes());
+        zip.extend((entries.len() as u16).to_le_bytes());
+        zip.extend((directory.len() as u32).to_le_bytes());
+        zip.extend(directory_offset.to_le_bytes());
+        zip.extend([0, 0]);
+        zip
+    }
+
+    #[test]
+    fn should_load_octo_cartridges_with_their_options() {
+        let payload = r##"{"program": ": main\n  v0 := 7\n  loop again\n",
+        "options": {"tickrate": 20, "maxSize": 3583, "shiftQuirks": false,
+        "loadStoreQuirks": true, "jumpQuirks": false, "vBlankQuirks": true,
+        "backgroundColor": "#102030", "fillColor": "#FFCC00"}}"##;
+
+        let loaded = decode_rom(&cartridge_gif(payload.as_bytes()), "game.gif").unwrap();
+        assert_eq!(loaded.rom, vec![0x60, 0x07, 0x12, 0x02]);
+        assert_eq!(loaded.source_map.unwrap().line(0x200), Some(2));
+
+        let settings = loaded.settings;
+        assert_eq!(settings.platform, Some(Platform::SuperChip));
+        assert_eq!(settings.clock_hz, Some(1200));
+        let quirks = settings.quirks.unwrap();
+        assert!(quirks.shift_uses_vy);
+        assert_eq!(quirks.memory_increment, MemoryIncrement::None);
+        assert!(!quirks.jump_uses_vx);
+        assert!(quirks.display_wait);
+        // Quirks the cartridge leaves out come from the platform
+        assert!(quirks.clip_sprites);
+        assert_eq!(
+            settings.palette,
+            Some([0x102030, 0xFFCC00, DEFAULT_PALETTE[2], DEFAULT_PALETTE[3]])
+        );
+    }
+
+    #[test]
+    fn should_reject_broken_cartridges() {
+        let gif = cartridge_gif(br#"{"options": {}}"#);
+        assert_eq!(
+            decode_rom(&gif, "game.gif").unwrap_err().to_string(),
+            "Invalid Octo cartridge: no program"
+        );
+        assert!(matches!(
+            decode_rom(&gif[..gif.len() / 2], "game.gif"),
+            Err(RomFileError::InvalidGif(_))
+        ));
+    }
+
+    #[test]
+    fn should_reject_cartridge_frames_that_do_not_fit() {
+        let gif = cartridge_gif(br#"{"program": ": main"}"#);
+        // After the header, the colour table and the comment extension
+        let descriptor = 13 + 256 * 3 + 6;
+        let error = |gif: &[u8]| match decode_gif(gif) {
+            Err(RomFileError::InvalidGif(what)) => what,
+            result => panic!("Unexpected result: {result:?}"),
+        };
+
+        let mut huge_screen = gif.clone();
+        huge_screen[6..10].copy_from_slice(&[0xFF; 4]);
+        assert_eq!(error(&huge_screen), "image is too large");
+
+        let mut wide_frame = gif.clone();
+        wide_frame[descriptor + 5..descriptor + 7].copy_from_slice(&[0xFF, 0xFF]);
+        assert_eq!(error(&wide_frame), "frame is outside the screen");
+
+        let mut short_data = gif.clone();
+        short_data[8] += 1;
+        short_data[descriptor + 7] += 1;
+        assert_eq!(error(&short_data), "frame size does not match its data");
+
+        let mut wide_codes = gif.clone();
+        wide_codes[descriptor + 10] = 11;
+        assert_eq!(error(&wide_codes), "bad LZW code size");
+    }
+
+    #[test]
+    fn should_let_the_command_line_override_rom_settings() {
+        let cartridge = RomSettings {
+            platform: Some(Platform::XoChip),
+            clock_hz: Some(1200),
+            ..RomSettings::default()
+        };
+        let command_line = RomSettings {
+            clock_hz: Some(600),
+            ..RomSettings::default()
+        };
+
+        let settings = command_line.or(cartridge);
+        assert_eq!(settings.platform(), Platform::XoChip);
+        assert_eq!(settings.quirks(), Quirks::XO_CHIP);
+        assert_eq!(settings.clock_hz(), 600);
+        assert_eq!(settings.palette(), DEFAULT_PALETTE);
+    }
+
+    #[test]
+    fn should_load_intel_hex() {
+        // Data at 0x200 is a memory image, moved to the start of the ROM
+        let text = ":0402000000E0A22A4E\n:02020400600C8C\n:00000001FF\n";
+        let loaded = decode_rom(text.as_bytes(), "game.hex").unwrap();
+        assert_eq!(loaded.rom, vec![0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C]);
+
+        let text = ":020000040000FA\n:020000001200EC\n:00000001FF\n";
+        assert_eq!(parse_intel_hex(text).unwrap(), vec![0x12, 0x00]);
+
+        assert_eq!(
+            parse_intel_hex(":020000001200ED\n")
+                .unwrap_err()
+                .to_string(),
+            "Line 1: checksum mismatch"
+        );
+        assert_eq!(
+            parse_intel_hex("\n:02000000\n").unwrap_err().to_string(),
+            "Line 2: wrong record length"
+        );
+    }
+
+    #[test]
+    fn should_load_hex_dumps() {
+        let text = "# Clear and jump\n00 E0, 0x12 0x00 ; loop\n";
+        let loaded = decode_rom(text.as_bytes(), "game.txt").unwrap();
+        assert_eq!(loaded.rom, vec![0x00, 0xE0, 0x12, 0x00]);
+
+        // xxd output, ignoring the text column
+        let text = "00000000: 00e0 a22a 600c  ...*`.\n00000006: 6108            a.\n";
+        assert_eq!(
+            parse_hex_dump(text).unwrap(),
+            vec![0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08]
+        );
+
+        assert_eq!(
+            parse_hex_dump("00 E0\n12 G0\n").unwrap_err().to_string(),
+            "Line 2: invalid hex: G0"
+        );
+    }
+
+    #[test]
+    fn should_load_the_rom_inside_a_zip() {
+        let rom: Vec<u8> = (0..200).map(|byte| (byte % 7) as u8).collect();
+        let zip = zip_archive(&[("readme.txt", b"Hello", false), ("game.ch8", &rom, true)]);
+        assert_eq!(decode_rom(&zip, "game.zip").unwrap().rom, rom);
+
+        // A zipped cartridge still brings its options
+        let gif = cartridge_gif(br#"{"program": ": main v0 := 1", "options": {"tickrate": 7}}"#);
+        let zip = zip_archive(&[("game.gif", &gif, false)]);
+        let loaded = decode_rom(&zip, "game.zip").unwrap();
+        assert_eq!(loaded.rom, vec![0x60, 0x01]);
+        assert_eq!(loaded.settings.clock_hz, Some(420));
+
+        let zip = zip_archive(&[("a.ch8", &rom, false), ("b.ch8", &rom, false)]);
+        assert_eq!(
+            decode_rom(&zip, "games.zip").unwrap_err().to_string(),
+            "Zip archive holds 2 ROMs, expected one"
+        );
+    }
+
+    #[test]
+    fn should_load_raw_roms_as_they_are() {
+        let rom = [0x12, 0x00];
+        let loaded = decode_rom(&rom, "game.ch8").unwrap();
+        assert_eq!(
+            loaded,
+            LoadedRom {
+                rom: rom.to_vec(),
+                ..LoadedRom::default()
+            }
+        );
+    }
+
+    fn sha1_hex(rom: &[u8]) -> String {
+        sha1_smol::Sha1::from(rom).digest().to_string()
+    }
+
+    #[test]
+    fn should_look_roms_up_in_the_database() {
+        let rom = [0x00, 0xE0, 0x12, 0x00];
+        let json = format!(
+            r##"[{{"title": "Blank", "roms": {{"{}": {{
+            "platforms": ["megachip8", "superchip", "xochip"],
+            "quirkyPlatforms": {{"superchip": {{"wrap": true, "memoryIncrementByX": true}}}},
+            "tickrate": 30,
+            "colors": {{"pixels": ["#000080", "#ffff00"]}}
+        }}}}}}, {{"title": "Other", "roms": {{}}}}]"##,
+            sha1_hex(&rom).to_uppercase()
+        );
+
+        let database = RomDatabase::parse(&json).unwrap();
+        let entry = database.lookup(&rom).unwrap();
+        assert_eq!(entry.title.as_deref(), Some("Blank"));
+        assert_eq!(entry.settings.platform, Some(Platform::SuperChip));
+        assert_eq!(entry.settings.clock_hz, Some(1800));
+        assert_eq!(
+            entry.settings.palette,
+            Some([0x000080, 0xFFFF00, DEFAULT_PALETTE[2], DEFAULT_PALETTE[3]])
+        );
+        let quirks = entry.settings.quirks.unwrap();
+        assert!(!quirks.clip_sprites);
+        assert_eq!(quirks.memory_increment, MemoryIncrement::X);
+        assert!(quirks.jump_uses_vx);
+
+        assert_eq!(database.lookup(&[0x12, 0x00]), None);
+        assert_eq!(database.settings(&[0x12, 0x00]), RomSettings::default());
+    }
+
+    #[test]
+    fn should_map_database_quirks() {
+        let rom = [0x12, 0x00];
+        let json = format!(
+            r#"{{"{}": {{"platforms": ["originalChip8"], "quirkyPlatforms": {{"originalChip8":
+            {{"shift": true, "memoryIncrementByX": false, "memoryLeaveIUnchanged": false,
+            "vblank": false, "logic": false, "jump": true}}}}}}}}"#,
+            sha1_hex(&rom)
+        );
+
+        let quirks = RomDatabase::parse(&json).unwrap().settings(&rom).quirks();
+        assert_eq!(
+            quirks,
+            Quirks {
+                shift_uses_vy: false,
+                memory_increment: MemoryIncrement::XPlusOne,
+                jump_uses_vx: true,
+                logic_resets_vf: false,
+                clip_sprites: true,
+                display_wait: false,
+            }
+        );
+    }
+
+    #[test]
+    fn should_prefer_overrides_to_the_database() {
+        let rom = [0x12, 0x00];
+        let database = format!(
+            r#"[{{"title": "Loop", "roms": {{"{}": {{"platforms": ["xochip"], "tickrate": 100}}}}}}]"#,
+            sha1_hex(&rom)
+        );
+        let overrides = format!(r#"{{"{}": {{"tickrate": 10}}}}"#, sha1_hex(&rom));
+        let database = RomDatabase::parse(&database).unwrap();
+        let overrides = RomDatabase::parse(&overrides).unwrap();
+
+        let settings = overrides.settings(&rom).or(database.settings(&rom));
+        assert_eq!(settings.platform(), Platform::XoChip);
+        assert_eq!(settings.quirks(), Quirks::XO_CHIP);
+        assert_eq!(settings.clock_hz(), 600);
+    }
+
+    #[test]
+    fn should_reject_invalid_databases() {
+        assert!(matches!(
+            RomDatabase::parse("[{\"title\": \"No ROMs\"}]"),
+            Err(DatabaseError::Invalid("program without roms"))
+        ));
+        assert!(matches!(
+            RomDatabase::parse("[1,"),
+            Err(DatabaseError::Json(_))
+        ));
+        assert!(
+            RomDatabase::open(None, "/nonexistent/programs.json")
+                .unwrap()
+                .entries
+                .is_empty()
+        );
+        assert!(RomDatabase::open(Some("/nonexistent/programs.json"), "").is_err());
+    }
+
+    #[test]
+    fn should_render_two_pixel_rows_per_line() {
+        let mut display = FrameBuffer::new(2, 3);
+        display.set_planes(0, 0, 1);
+        display.set_planes(1, 1, 2);
+        let palette = [0x000000, 0xFF0000, 0x00FF00, 0x0000FF];
+
+        assert_eq!(
+            render_half_blocks(&display, &palette),
+            "\x1b[38;2;255;0;0;48;2;0;0;0m▀\x1b[38;2;0;0;0;48;2;0;255;0m▀\x1b[0m\r\n\
+         \x1b[38;2;0;0;0;48;2;0;0;0m▀▀\x1b[0m\r\n"
+        );
+    }
+
+    #[test]
+    fn should_find_themes_by_name() {
+        assert_eq!(theme_from_name("default"), Some(DEFAULT_PALETTE));
+        assert_eq!(theme_from_name("Amber"), Some(THEMES[1].1));
+        assert_eq!(theme_from_name("plaid"), None);
+    }
+
+    #[test]
+    fn should_release_keys_after_the_hold_time() {
+        let mut keys: KeyHold = KeyHold::new(3);
+        keys.press(0x5);
+        assert!(keys.is_down(0x5));
+        keys.next_frame();
+        keys.next_frame();
+        assert!(keys.is_down(0x5));
+        keys.next_frame();
+        assert!(!keys.is_down(0x5));
+
+        // Auto-repeat keeps a held key down
+        keys.press(0xA);
+        for _ in 0..10 {
+            keys.next_frame();
+            keys.press(0xA);
+            assert!(keys.is_down(0xA));
+        }
+
+        keys.release(0xA);
+        assert!(!keys.is_down(0xA));
+    }
+
     /*
     #[test]
     fn should_execute_
@@ -0,0 +1,265 @@
+use crate::chip8_state::{Chip8State, V_SIZE};
+use crate::disassembly::decrypt_chip8_instruction;
+use crate::error::Chip8Error;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    ops::{Range, RangeInclusive},
+    path::Path,
+};
+
+pub const TRACE_MAGIC: &[u8; 4] = b"C8TR";
+pub const TRACE_VERSION: u16 = 1;
+// Size of one record in the binary format.
+pub const TRACE_RECORD_SIZE: usize = 8 + 2 + 2 + V_SIZE + 2 + 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    // One human-readable line per instruction.
+    Text,
+    // A header followed by fixed-size little-endian records.
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" | "txt" => Some(TraceFormat::Text),
+            "binary" | "bin" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+// An executed instruction and the registers after it ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    // Cycles executed before this instruction.
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; V_SIZE],
+    pub i: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    pub fn new(cycle: u64, pc: u16, opcode: u16, state: &Chip8State) -> Self {
+        Self {
+            cycle,
+            pc,
+            opcode,
+            v: state.v,
+            i: state.i,
+            sp: state.sp,
+            delay_timer: state.delay_timer,
+            sound_timer: state.sound_timer,
+        }
+    }
+
+    pub fn mnemonic(&self) -> String {
+        decrypt_chip8_instruction(&self.opcode.to_be_bytes())
+    }
+
+    pub fn write_text<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let registers: String = self
+            .v
+            .iter()
+            .map(|value| format!("{:02X}", value))
+            .collect();
+
+        writeln!(
+            writer,
+            "{:>10} {:04X} {:04X} {:<20} V={} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            self.mnemonic(),
+            registers,
+            self.i,
+            self.sp,
+            self.delay_timer,
+            self.sound_timer
+        )
+    }
+
+    pub fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(TRACE_RECORD_SIZE);
+        bytes.extend_from_slice(&self.cycle.to_le_bytes());
+        bytes.extend_from_slice(&self.pc.to_le_bytes());
+        bytes.extend_from_slice(&self.opcode.to_le_bytes());
+        bytes.extend_from_slice(&self.v);
+        bytes.extend_from_slice(&self.i.to_le_bytes());
+        bytes.extend_from_slice(&[self.sp, self.delay_timer, self.sound_timer]);
+        writer.write_all(&bytes)
+    }
+
+    // Reads the next record of a binary trace, after `read_binary_header`.
+    // Returns None at the end of the trace.
+    pub fn read_binary<R: Read>(reader: &mut R) -> io::Result<Option<TraceRecord>> {
+        let mut bytes = [0u8; TRACE_RECORD_SIZE];
+        match reader.read_exact(&mut bytes) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+
+        let registers = 12..12 + V_SIZE;
+        let rest = registers.end;
+        Ok(Some(TraceRecord {
+            cycle: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            pc: u16::from_le_bytes([bytes[8], bytes[9]]),
+            opcode: u16::from_le_bytes([bytes[10], bytes[11]]),
+            v: bytes[registers].try_into().unwrap(),
+            i: u16::from_le_bytes([bytes[rest], bytes[rest + 1]]),
+            sp: bytes[rest + 2],
+            delay_timer: bytes[rest + 3],
+            sound_timer: bytes[rest + 4],
+        }))
+    }
+}
+
+pub fn read_binary_header<R: Read>(reader: &mut R) -> io::Result<()> {
+    let mut header = [0u8; 6];
+    reader.read_exact(&mut header)?;
+
+    if &header[..4] != TRACE_MAGIC || header[4..] != TRACE_VERSION.to_le_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a CHIP-8 binary trace",
+        ));
+    }
+
+    Ok(())
+}
+
+// Writes executed instructions to a trace. With a ring buffer only the last
+// instructions are kept, and written out when the CPU faults. Tracing stops at
+// the first write error, which `finish` returns.
+pub struct Tracer {
+    pub format: TraceFormat,
+    // Only instructions at these addresses are traced.
+    pub addresses: Option<RangeInclusive<u16>>,
+    // Only instructions in this cycle window are traced.
+    pub cycles: Option<Range<u64>>,
+    writer: Box<dyn Write>,
+    ring: Option<VecDeque<TraceRecord>>,
+    ring_capacity: usize,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    // Binary traces start with their header right away, so one without any
+    // records still reads back.
+    pub fn new(writer: Box<dyn Write>, format: TraceFormat) -> Self {
+        let mut tracer = Self {
+            format,
+            addresses: None,
+            cycles: None,
+            writer,
+            ring: None,
+            ring_capacity: 0,
+            error: None,
+        };
+
+        if format == TraceFormat::Binary {
+            let result = tracer
+                .writer
+                .write_all(TRACE_MAGIC)
+                .and_then(|_| tracer.writer.write_all(&TRACE_VERSION.to_le_bytes()));
+            tracer.keep_error(result);
+        }
+        tracer
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Self> {
+        Ok(Tracer::new(
+            Box::new(BufWriter::new(File::create(path)?)),
+            format,
+        ))
+    }
+
+    // Keeps only the last `capacity` instructions until a fault.
+    pub fn ring_buffer(writer: Box<dyn Write>, format: TraceFormat, capacity: usize) -> Self {
+        Self {
+            ring: Some(VecDeque::with_capacity(capacity)),
+            ring_capacity: capacity,
+            ..Tracer::new(writer, format)
+        }
+    }
+
+    pub fn record(&mut self, record: TraceRecord) {
+        let address_matches = self
+            .addresses
+            .as_ref()
+            .is_none_or(|range| range.contains(&record.pc));
+        let cycle_matches = self
+            .cycles
+            .as_ref()
+            .is_none_or(|range| range.contains(&record.cycle));
+        if !address_matches || !cycle_matches {
+            return;
+        }
+
+        match self.ring.as_mut() {
+            Some(ring) => {
+                if ring.len() == self.ring_capacity {
+                    ring.pop_front();
+                }
+                if self.ring_capacity > 0 {
+                    ring.push_back(record);
+                }
+            }
+            None => self.write(&record),
+        }
+    }
+
+    // Called when the CPU faults: dumps the ring buffer, if any.
+    pub fn fault(&mut self, err: &Chip8Error) {
+        let records: Vec<TraceRecord> = match self.ring.as_mut() {
+            Some(ring) => ring.drain(..).collect(),
+            None => Vec::new(),
+        };
+
+        for record in &records {
+            self.write(record);
+        }
+        if self.format == TraceFormat::Text && self.error.is_none() {
+            let result = writeln!(self.writer, "# {err}");
+            self.keep_error(result);
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.error.is_none() {
+            let result = self.writer.flush();
+            self.keep_error(result);
+        }
+
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, record: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = match self.format {
+            TraceFormat::Text => record.write_text(&mut self.writer),
+            TraceFormat::Binary => record.write_binary(&mut self.writer),
+        };
+        self.keep_error(result);
+    }
+
+    fn keep_error(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            self.error.get_or_insert(err);
+        }
+    }
+}
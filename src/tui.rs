@@ -0,0 +1,101 @@
+use crate::chip8_machine::KEYBOARD_SIZE;
+use crate::framebuffer::FrameBuffer;
+use crate::screenshot::{DEFAULT_PALETTE, Palette};
+use std::fmt::Write;
+
+// Frames a key stays down after the terminal reports a press. Auto-repeat
+// presses keep extending it while the key is held.
+pub const DEFAULT_KEY_HOLD_FRAMES: u32 = 12;
+
+// Colour themes for the terminal frontend: off, plane 1, plane 2, both.
+pub const THEMES: [(&str, Palette); 5] = [
+    ("default", DEFAULT_PALETTE),
+    ("amber", [0x1A0F00, 0xFFB000, 0x805800, 0xFFD080]),
+    ("green", [0x001400, 0x33FF33, 0x1A801A, 0xA0FFA0]),
+    ("ice", [0x0A1A2A, 0xB0E0FF, 0x4080C0, 0xFFFFFF]),
+    ("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+];
+
+pub fn theme_from_name(name: &str) -> Option<Palette> {
+    THEMES
+        .iter()
+        .find(|(theme, _)| theme.eq_ignore_ascii_case(name))
+        .map(|(_, palette)| *palette)
+}
+
+// Two pixel rows per line, each cell an upper half block with the top pixel's
+// colour in front and the bottom one's behind, as 24-bit ANSI escapes.
+// Colours are only sent when they change. Lines end in "\r\n" for raw mode
+// and the colours are reset at the end.
+pub fn render_half_blocks(display: &FrameBuffer, palette: &Palette) -> String {
+    let rows: Vec<&[u8]> = display.rows().collect();
+    let mut text = String::new();
+    for pair in rows.chunks(2) {
+        let mut current = None;
+        for x in 0..display.width() {
+            let top = palette[pair[0][x] as usize & 3];
+            let bottom = pair
+                .get(1)
+                .map_or(palette[0], |row| palette[row[x] as usize & 3]);
+            if current != Some((top, bottom)) {
+                let _ = write!(
+                    text,
+                    "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                    top >> 16,
+                    top >> 8 & 0xFF,
+                    top & 0xFF,
+                    bottom >> 16,
+                    bottom >> 8 & 0xFF,
+                    bottom & 0xFF
+                );
+                current = Some((top, bottom));
+            }
+            text.push('▀');
+        }
+        text.push_str("\x1b[0m\r\n");
+    }
+    text
+}
+
+// Terminals report key presses but not releases, so a pressed key is let go
+// after a number of frames unless another press comes in first. Holds the
+// keypad by default, any other set of N keys otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyHold<const N: usize = KEYBOARD_SIZE> {
+    pub hold_frames: u32,
+    frame: u64,
+    release_at: [Option<u64>; N],
+}
+
+impl<const N: usize> KeyHold<N> {
+    pub fn new(hold_frames: u32) -> Self {
+        Self {
+            hold_frames,
+            frame: 0,
+            release_at: [None; N],
+        }
+    }
+
+    pub fn press(&mut self, key: usize) {
+        self.release_at[key] = Some(self.frame + self.hold_frames as u64);
+    }
+
+    // For terminals that do report releases.
+    pub fn release(&mut self, key: usize) {
+        self.release_at[key] = None;
+    }
+
+    pub fn is_down(&self, key: usize) -> bool {
+        self.release_at[key].is_some_and(|frame| frame > self.frame)
+    }
+
+    // Advances one frame, letting go of keys whose time is up.
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+        for release_at in &mut self.release_at {
+            if release_at.is_some_and(|frame| frame <= self.frame) {
+                *release_at = None;
+            }
+        }
+    }
+}
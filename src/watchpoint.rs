@@ -0,0 +1,236 @@
+use crate::chip8_state::{Chip8State, V_SIZE};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(usize),
+    I,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register {
+    pub const ALL: [Register; V_SIZE + 3] = {
+        let mut all = [Register::I; V_SIZE + 3];
+        let mut index = 0;
+        while index < V_SIZE {
+            all[index] = Register::V(index);
+            index += 1;
+        }
+        all[V_SIZE + 1] = Register::DelayTimer;
+        all[V_SIZE + 2] = Register::SoundTimer;
+        all
+    };
+
+    pub fn from_name(name: &str) -> Option<Register> {
+        match name.to_ascii_uppercase().as_str() {
+            "I" => Some(Register::I),
+            "DT" => Some(Register::DelayTimer),
+            "ST" => Some(Register::SoundTimer),
+            name => {
+                let digit = name.strip_prefix('V')?;
+                match usize::from_str_radix(digit, 16) {
+                    Ok(index) if digit.len() == 1 => Some(Register::V(index)),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    pub fn value(&self, state: &Chip8State) -> u16 {
+        match self {
+            Register::V(index) => state.v[*index] as u16,
+            Register::I => state.i,
+            Register::DelayTimer => state.delay_timer as u16,
+            Register::SoundTimer => state.sound_timer as u16,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(index) => write!(f, "V{:X}", index),
+            Register::I => write!(f, "I"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watchpoint {
+    // Data accesses to `start..=end` by instructions. Instruction fetches are
+    // not reported.
+    Memory {
+        start: usize,
+        end: usize,
+        read: bool,
+        write: bool,
+    },
+    // Changes made by an instruction. Timers counting down at 60 Hz do not count.
+    Register(Register),
+}
+
+impl Watchpoint {
+    pub fn memory(start: usize, end: usize, read: bool, write: bool) -> Self {
+        Watchpoint::Memory {
+            start: start.min(end),
+            end: start.max(end),
+            read,
+            write,
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory {
+                start,
+                end,
+                read,
+                write,
+            } => {
+                let access = match (read, write) {
+                    (true, true) => "rw",
+                    (true, false) => "r",
+                    (false, true) => "w",
+                    (false, false) => "-",
+                };
+                if start == end {
+                    write!(f, "[{:#05X}] {}", start, access)
+                } else {
+                    write!(f, "[{:#05X}-{:#05X}] {}", start, end, access)
+                }
+            }
+            Watchpoint::Register(register) => write!(f, "{register}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    // `address` is the first watched byte the instruction touched.
+    MemoryRead {
+        address: usize,
+        value: u8,
+    },
+    MemoryWrite {
+        address: usize,
+        old: u8,
+        new: u8,
+    },
+    RegisterChange {
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+}
+
+// A triggered watchpoint: `pc` and `opcode` are the responsible instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: usize,
+    pub pc: u16,
+    pub opcode: u16,
+    pub event: WatchEvent,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#05X} ({:04X}): ", self.pc, self.opcode)?;
+
+        match &self.event {
+            WatchEvent::MemoryRead { address, value } => {
+                write!(f, "read [{:#05X}] = {:#04X}", address, value)
+            }
+            WatchEvent::MemoryWrite { address, old, new } => {
+                write!(f, "write [{:#05X}] {:#04X} -> {:#04X}", address, old, new)
+            }
+            WatchEvent::RegisterChange { register, old, new } => {
+                write!(f, "{} {:#04X} -> {:#04X}", register, old, new)
+            }
+        }
+    }
+}
+
+// A data access an instruction made, with the bytes before it ran.
+#[derive(Debug)]
+pub(crate) struct MemoryAccess {
+    pub start: usize,
+    pub access: Access,
+    pub old: Vec<u8>,
+}
+
+// Matches the accesses and register changes of one instruction against the
+// watchpoints, in watchpoint order.
+pub(crate) fn check_watchpoints(
+    watchpoints: &[Watchpoint],
+    accesses: &[MemoryAccess],
+    registers_before: &[u16],
+    state: &Chip8State,
+    pc: u16,
+    opcode: u16,
+) -> Vec<WatchHit> {
+    let mut hits = Vec::new();
+
+    for (index, watchpoint) in watchpoints.iter().enumerate() {
+        let event = match *watchpoint {
+            Watchpoint::Memory {
+                start,
+                end,
+                read,
+                write,
+            } => accesses.iter().find_map(|access| {
+                let wanted = match access.access {
+                    Access::Read => read,
+                    Access::Write => write,
+                };
+                let address = access.start.max(start);
+                if !wanted || address > end || address >= access.start + access.old.len() {
+                    return None;
+                }
+
+                let old = access.old[address - access.start];
+                let new = state.memory[address];
+                Some(match access.access {
+                    Access::Read => WatchEvent::MemoryRead {
+                        address,
+                        value: old,
+                    },
+                    Access::Write => WatchEvent::MemoryWrite { address, old, new },
+                })
+            }),
+            Watchpoint::Register(register) => {
+                let position = Register::ALL.iter().position(|&r| r == register);
+                let old = position.map(|position| registers_before[position]);
+                let new = register.value(state);
+
+                match old {
+                    Some(old) if old != new => {
+                        Some(WatchEvent::RegisterChange { register, old, new })
+                    }
+                    _ => None,
+                }
+            }
+        };
+
+        if let Some(event) = event {
+            hits.push(WatchHit {
+                watchpoint: index,
+                pc,
+                opcode,
+                event,
+            });
+        }
+    }
+
+    hits
+}